@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use omvdecoder::decoder::{DecodeOptions, OmvReader, OnError};
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks the error policy so all code paths get exercised
+    let Some((&policy, data)) = data.split_first() else {
+        return;
+    };
+    let on_error = match policy % 3 {
+        0 => OnError::Stop,
+        1 => OnError::Skip,
+        _ => OnError::RepeatLast,
+    };
+    let Ok(reader) = OmvReader::open(
+        data,
+        DecodeOptions {
+            on_error,
+            ..Default::default()
+        },
+    ) else {
+        return;
+    };
+    // bound the work per input, a valid stream may be long
+    for frame in reader.take(64) {
+        if frame.is_err() {
+            break;
+        }
+    }
+});
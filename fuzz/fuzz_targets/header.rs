@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use omvdecoder::{decoder, ogg};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decoder::read_omv_header(data);
+    let _ = decoder::find_payload(data);
+    // page scan and Theora header parsing see the same untrusted bytes
+    let _ = ogg::TheoraStream::collect(ogg::PageScanner::new(data).filter_map(|item| match item {
+        ogg::ScanItem::Page(page) => Some(page),
+        _ => None,
+    }));
+});
@@ -0,0 +1,272 @@
+"""Consumer for the PXY4M stream written by `omvdecoder --format piped-png`.
+
+The protocol is described in the "Piped PNG Stream Protocol" section of the
+README. Only the standard library is needed to parse the stream; decoding PNG
+or QOI payloads into images uses Pillow when it is installed.
+"""
+
+import io
+import os
+import shutil
+import subprocess
+
+
+class ProtocolError(Exception):
+    """The stream does not follow the PXY4M protocol."""
+
+
+class TruncatedStreamError(ProtocolError):
+    """The stream ended before the END trailer."""
+
+
+class StreamHeader:
+    def __init__(self, version, width, height, fps_num, fps_den, channels, encoding):
+        self.version = version
+        self.width = width
+        self.height = height
+        self.fps_num = fps_num
+        self.fps_den = fps_den
+        self.channels = channels
+        self.encoding = encoding
+        self.meta = {}
+
+    @property
+    def fps(self):
+        return self.fps_num / max(self.fps_den, 1)
+
+    @property
+    def has_alpha(self):
+        # version 1 streams do not say, so assume they do
+        return self.meta.get("ALPHA", "1") == "1"
+
+    @property
+    def frame_count(self):
+        value = self.meta.get("FRAMES")
+        return int(value) if value is not None else None
+
+    @property
+    def source_name(self):
+        return self.meta.get("SOURCE")
+
+    @property
+    def raw_frame_len(self):
+        """Bytes of one frame of raw pixels."""
+        return self.width * self.height * (3 if self.channels == "rgb" else 4)
+
+    @property
+    def max_payload_len(self):
+        """Largest payload a frame may have: the raw size for raw, twice that
+        plus 1 MiB for QOI and PNG, far above what either encoder produces."""
+        if self.encoding == "raw":
+            return self.raw_frame_len
+        return 2 * self.raw_frame_len + (1 << 20)
+
+    @classmethod
+    def parse(cls, line):
+        tokens = line.split(" ")
+        if tokens[0] != "PXY4M":
+            raise ProtocolError(f"not a PXY4M stream: {line!r}")
+
+        header = cls(1, 0, 0, 0, 1, "rgba", "png")
+        try:
+            for token in tokens[1:]:
+                if token.startswith("Enc:"):
+                    header.encoding = token[4:]
+                elif token.startswith("V"):
+                    header.version = int(token[1:])
+                elif token.startswith("W"):
+                    header.width = int(token[1:])
+                elif token.startswith("H"):
+                    header.height = int(token[1:])
+                elif token.startswith("F"):
+                    num, den = token[1:].split("/", 1)
+                    header.fps_num, header.fps_den = int(num), int(den)
+                elif token.startswith("C"):
+                    header.channels = token[1:]
+                # unknown tokens are reserved for future versions
+        except ValueError as e:
+            raise ProtocolError(f"invalid header {line!r}: {e}") from None
+
+        if header.width <= 0 or header.height <= 0:
+            raise ProtocolError(f"invalid frame size in header: {line!r}")
+        if header.channels not in ("rgba", "rgb"):
+            raise ProtocolError(f"unsupported channel layout: {header.channels}")
+        if header.encoding not in ("raw", "qoi", "png"):
+            raise ProtocolError(f"unsupported payload encoding: {header.encoding}")
+        return header
+
+
+class Frame:
+    def __init__(self, header, pts, payload):
+        self.header = header
+        self.pts = pts
+        self.payload = payload
+
+    @property
+    def mode(self):
+        return "RGBA" if self.header.channels == "rgba" else "RGB"
+
+    def to_image(self):
+        """Decode the payload into a Pillow image."""
+        from PIL import Image
+
+        if self.header.encoding == "raw":
+            return Image.frombytes(self.mode, (self.header.width, self.header.height), self.payload)
+        return Image.open(io.BytesIO(self.payload))
+
+
+class PipedStreamReader:
+    """Iterates the frames of a PXY4M stream read from a binary file object.
+
+    Iteration stops at the END trailer. On version 2 streams, EOF before the
+    trailer raises TruncatedStreamError.
+    """
+
+    def __init__(self, stream):
+        self._stream = stream
+        self._pending = None
+        self.frames_read = 0
+        self.end_frames = None
+
+        line = self._read_line()
+        if line is None:
+            raise TruncatedStreamError("empty stream")
+        self.header = StreamHeader.parse(line)
+
+        while True:
+            line = self._read_line()
+            if line is None or not line.startswith("META "):
+                self._pending = line
+                break
+            key, _, value = line[5:].partition(" ")
+            self.header.meta.setdefault(key, value)
+
+    @property
+    def finished(self):
+        return self.end_frames is not None
+
+    def __iter__(self):
+        return self
+
+    def __next__(self):
+        frame = self.read_frame()
+        if frame is None:
+            raise StopIteration
+        return frame
+
+    def read_frame(self):
+        """Return the next Frame, or None after the END trailer."""
+        if self.end_frames is not None:
+            return None
+
+        if self._pending is not None:
+            line, self._pending = self._pending, None
+        else:
+            line = self._read_line()
+
+        if line is None:
+            if self.header.version < 2:
+                # version 1 has no trailer, EOF at a frame boundary is the end
+                self.end_frames = self.frames_read
+                return None
+            raise TruncatedStreamError(
+                f"stream truncated after {self.frames_read} frame(s): missing END trailer"
+            )
+
+        if line.startswith("END"):
+            rest = line[3:].strip()
+            total = int(rest[len("FRAMES"):]) if rest.startswith("FRAMES") else self.frames_read
+            if total != self.frames_read:
+                raise ProtocolError(
+                    f"END announces {total} frame(s) but {self.frames_read} were read"
+                )
+            self.end_frames = total
+            return None
+
+        if line != "FRAME":
+            raise ProtocolError(f"expected FRAME, got {line!r}")
+        pts = self._read_field("PTS")
+        n = self._read_field("BYTES")
+        limit = self.header.max_payload_len
+        h = self.header
+        if h.encoding == "raw" and n != limit:
+            raise ProtocolError(
+                f"raw payload of frame {self.frames_read} is {n} bytes, "
+                f"{h.width}x{h.height} {h.channels} needs {limit}"
+            )
+        if n < 0 or n > limit:
+            raise ProtocolError(
+                f"payload of frame {self.frames_read} is {n} bytes, "
+                f"more than {limit} allowed for {h.width}x{h.height}"
+            )
+
+        payload = self._read_exact(n)
+        if payload is None:
+            raise TruncatedStreamError(
+                f"stream truncated in payload of frame {self.frames_read}"
+            )
+        self.frames_read += 1
+        return Frame(self.header, pts, payload)
+
+    def _read_line(self):
+        line = self._stream.readline()
+        if not line:
+            return None
+        if not line.endswith(b"\n"):
+            raise TruncatedStreamError("stream truncated inside a control line")
+        return line[:-1].decode("utf-8")
+
+    def _read_field(self, name):
+        line = self._read_line()
+        if line is None:
+            raise TruncatedStreamError(f"stream truncated: missing {name} line")
+        prefix = name + " "
+        if not line.startswith(prefix):
+            raise ProtocolError(f"expected {name}, got {line!r}")
+        try:
+            return int(line[len(prefix):])
+        except ValueError:
+            raise ProtocolError(f"invalid {name}: {line!r}") from None
+
+    def _read_exact(self, n):
+        buf = bytearray()
+        while len(buf) < n:
+            # in chunks, so a truncated stream never allocates the full claim
+            chunk = self._stream.read(min(n - len(buf), 1 << 16))
+            if not chunk:
+                return None
+            buf.extend(chunk)
+        return bytes(buf)
+
+
+def find_decoder():
+    """Locate the omvdecoder binary via $OMVDECODER, PATH or a local cargo build."""
+    env = os.environ.get("OMVDECODER")
+    if env:
+        return env
+    found = shutil.which("omvdecoder")
+    if found:
+        return found
+    root = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+    for profile in ("release", "debug"):
+        for name in ("omvdecoder", "omvdecoder.exe"):
+            candidate = os.path.join(root, "target", profile, name)
+            if os.path.isfile(candidate):
+                return candidate
+    raise FileNotFoundError("omvdecoder not found, set $OMVDECODER or add it to PATH")
+
+
+def spawn_decoder(input_file, payload="png", decoder=None, extra_args=()):
+    """Start omvdecoder in piped mode and return the Popen object.
+
+    Read the stream with PipedStreamReader(proc.stdout).
+    """
+    cmd = [
+        decoder or find_decoder(),
+        "--input", str(input_file),
+        "--output", "-",
+        "--format", "piped-png",
+        "--piped-payload", payload,
+        *extra_args,
+    ]
+    return subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE, bufsize=0)
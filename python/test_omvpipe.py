@@ -0,0 +1,112 @@
+import io
+import unittest
+
+from omvpipe import PipedStreamReader, ProtocolError, TruncatedStreamError
+
+WIDTH, HEIGHT = 3, 2
+
+
+def raw_frame(index, channels=4):
+    return bytes((index + i) % 256 for i in range(WIDTH * HEIGHT * channels))
+
+
+def build_stream(frames, channels="rgba", end=True, version=2, meta=None):
+    out = io.BytesIO()
+    v = f"V{version} " if version >= 2 else ""
+    out.write(f"PXY4M {v}W{WIDTH} H{HEIGHT} F30000/1001 C{channels} Enc:raw\n".encode())
+    for key, value in (meta or {}).items():
+        out.write(f"META {key} {value}\n".encode())
+    for pts, payload in enumerate(frames):
+        out.write(f"FRAME\nPTS {pts}\nBYTES {len(payload)}\n".encode())
+        out.write(payload)
+    if end:
+        out.write(f"END FRAMES {len(frames)}\n".encode())
+    return out.getvalue()
+
+
+class PipedStreamReaderTest(unittest.TestCase):
+    def test_header_and_meta(self):
+        data = build_stream([], meta={"ALPHA": 0, "FRAMES": 12, "SOURCE": "op movie.omv"})
+        reader = PipedStreamReader(io.BytesIO(data))
+        h = reader.header
+        self.assertEqual((h.version, h.width, h.height), (2, WIDTH, HEIGHT))
+        self.assertEqual((h.fps_num, h.fps_den), (30000, 1001))
+        self.assertAlmostEqual(h.fps, 29.97, places=2)
+        self.assertEqual((h.channels, h.encoding), ("rgba", "raw"))
+        self.assertFalse(h.has_alpha)
+        self.assertEqual(h.frame_count, 12)
+        self.assertEqual(h.source_name, "op movie.omv")
+        self.assertEqual(list(reader), [])
+        self.assertTrue(reader.finished)
+
+    def test_frames_in_order(self):
+        frames = [raw_frame(i) for i in range(4)]
+        reader = PipedStreamReader(io.BytesIO(build_stream(frames)))
+        decoded = list(reader)
+        self.assertEqual([f.pts for f in decoded], [0, 1, 2, 3])
+        self.assertEqual([f.payload for f in decoded], frames)
+        self.assertEqual(reader.end_frames, 4)
+
+    def test_rgb_payload(self):
+        frames = [raw_frame(0, channels=3)]
+        reader = PipedStreamReader(io.BytesIO(build_stream(frames, channels="rgb")))
+        frame = next(reader)
+        self.assertEqual(frame.mode, "RGB")
+        self.assertEqual(len(frame.payload), WIDTH * HEIGHT * 3)
+
+    def test_missing_trailer(self):
+        frames = [raw_frame(i) for i in range(2)]
+        reader = PipedStreamReader(io.BytesIO(build_stream(frames, end=False)))
+        next(reader)
+        next(reader)
+        with self.assertRaises(TruncatedStreamError):
+            next(reader)
+
+    def test_truncated_payload(self):
+        data = build_stream([raw_frame(0)], end=False)
+        reader = PipedStreamReader(io.BytesIO(data[:-5]))
+        with self.assertRaises(TruncatedStreamError):
+            next(reader)
+
+    def test_payload_size_is_checked(self):
+        # a raw payload must be exactly one frame
+        data = build_stream([raw_frame(0)[:-1]])
+        with self.assertRaises(ProtocolError):
+            next(PipedStreamReader(io.BytesIO(data)))
+        # an absurd BYTES field fails before anything is read
+        header = f"PXY4M V2 W{WIDTH} H{HEIGHT} F30/1 Crgba Enc:png\n".encode()
+        data = header + b"FRAME\nPTS 0\nBYTES 99999999999999\n"
+        with self.assertRaises(ProtocolError):
+            next(PipedStreamReader(io.BytesIO(data)))
+
+    def test_trailer_count_mismatch(self):
+        data = build_stream([raw_frame(0)], end=False) + b"END FRAMES 2\n"
+        reader = PipedStreamReader(io.BytesIO(data))
+        next(reader)
+        with self.assertRaises(ProtocolError):
+            next(reader)
+
+    def test_version_1_ends_at_eof(self):
+        frames = [raw_frame(0)]
+        reader = PipedStreamReader(io.BytesIO(build_stream(frames, end=False, version=1)))
+        self.assertEqual(reader.header.version, 1)
+        self.assertTrue(reader.header.has_alpha)
+        self.assertEqual(len(list(reader)), 1)
+        self.assertTrue(reader.finished)
+
+    def test_not_a_stream(self):
+        with self.assertRaises(ProtocolError):
+            PipedStreamReader(io.BytesIO(b"YUV4MPEG2 W3 H2\n"))
+
+    def test_to_image(self):
+        try:
+            import PIL  # noqa: F401
+        except ImportError:
+            self.skipTest("Pillow is not installed")
+        reader = PipedStreamReader(io.BytesIO(build_stream([raw_frame(0)])))
+        img = next(reader).to_image()
+        self.assertEqual((img.width, img.height, img.mode), (WIDTH, HEIGHT, "RGBA"))
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,392 @@
+//! Quality metrics between decoded OMV frames and a reference: PSNR and SSIM of
+//! every channel, alpha included, per frame and over the whole video.
+
+use anyhow::{anyhow, Context, Result};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::decoder::{DecodeOptions, OmvReader};
+use crate::error::Error;
+use crate::progress::Progress;
+
+/// Frames of one side of a comparison, in presentation order.
+pub type Frames = Box<dyn Iterator<Item = Result<RgbaImage>>>;
+
+/// A value per channel of an RGBA frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Channels {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+impl Channels {
+    fn from_array([r, g, b, a]: [f64; 4]) -> Self {
+        Self { r, g, b, a }
+    }
+
+    fn to_array(self) -> [f64; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+}
+
+/// Scores of one frame. PSNR is in dB and infinite for identical channels
+/// (`inf` in CSV, `null` in JSON).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameScores {
+    pub frame: u32,
+    pub mse: Channels,
+    pub psnr: Channels,
+    pub ssim: Channels,
+}
+
+/// Scores over all compared frames: PSNR from the mean squared error, like
+/// ffmpeg's average, and the mean SSIM.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub frames: u32,
+    pub psnr: Channels,
+    pub ssim: Channels,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub frames: Vec<FrameScores>,
+    pub summary: Summary,
+}
+
+/// Output format of a comparison.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportFormat {
+    /// One row per frame, then an `all` row with the summary.
+    #[default]
+    Csv,
+    Json,
+}
+
+impl Comparison {
+    pub fn write(&self, format: ReportFormat, out: &mut dyn Write) -> Result<()> {
+        match format {
+            ReportFormat::Csv => self.write_csv(out)?,
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_csv(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(
+            out,
+            "frame,psnr_r,psnr_g,psnr_b,psnr_a,ssim_r,ssim_g,ssim_b,ssim_a"
+        )?;
+        let row = |out: &mut dyn Write, frame: &str, psnr: Channels, ssim: Channels| {
+            write!(out, "{}", frame)?;
+            for value in psnr.to_array() {
+                write!(out, ",{:.4}", value)?;
+            }
+            for value in ssim.to_array() {
+                write!(out, ",{:.6}", value)?;
+            }
+            writeln!(out)
+        };
+        for scores in &self.frames {
+            row(out, &scores.frame.to_string(), scores.psnr, scores.ssim)?;
+        }
+        row(out, "all", self.summary.psnr, self.summary.ssim)?;
+        Ok(())
+    }
+}
+
+/// Compares every channel of two frames of the same size.
+pub fn compare_frames(frame: u32, image: &RgbaImage, reference: &RgbaImage) -> Result<FrameScores> {
+    if image.dimensions() != reference.dimensions() {
+        return Err(Error::InvalidGeometry(format!(
+            "frame {} is {}x{}, the reference {}x{}",
+            frame,
+            image.width(),
+            image.height(),
+            reference.width(),
+            reference.height()
+        ))
+        .into());
+    }
+    let mse = [0, 1, 2, 3].map(|c| channel_mse(image, reference, c));
+    Ok(FrameScores {
+        frame,
+        mse: Channels::from_array(mse),
+        psnr: Channels::from_array(mse.map(psnr)),
+        ssim: Channels::from_array([0, 1, 2, 3].map(|c| channel_ssim(image, reference, c))),
+    })
+}
+
+/// Compares `frames` with `reference` frame by frame. When one side has more
+/// frames, the extra ones are reported and left out.
+pub fn compare(
+    mut frames: Frames,
+    mut reference: Frames,
+    progress: &mut Progress,
+) -> Result<Comparison> {
+    let mut scores = Vec::new();
+    loop {
+        let index = scores.len() as u32;
+        match (frames.next().transpose()?, reference.next().transpose()?) {
+            (Some(image), Some(other)) => {
+                scores.push(compare_frames(index, &image, &other)?);
+                progress.advance();
+            }
+            (None, None) => break,
+            (Some(_), None) => {
+                log::warn!(
+                    "the reference ends after {} frame(s), comparing those",
+                    index
+                );
+                break;
+            }
+            (None, Some(_)) => {
+                log::warn!("the video ends after {} frame(s), comparing those", index);
+                break;
+            }
+        }
+    }
+    if scores.is_empty() {
+        return Err(anyhow!("no frames to compare"));
+    }
+
+    let count = scores.len() as f64;
+    let mean = |values: fn(&FrameScores) -> Channels| {
+        let mut sum = [0.0; 4];
+        for frame in &scores {
+            for (sum, value) in sum.iter_mut().zip(values(frame).to_array()) {
+                *sum += value;
+            }
+        }
+        sum.map(|sum| sum / count)
+    };
+    let summary = Summary {
+        frames: scores.len() as u32,
+        psnr: Channels::from_array(mean(|s| s.mse).map(psnr)),
+        ssim: Channels::from_array(mean(|s| s.ssim)),
+    };
+    Ok(Comparison {
+        frames: scores,
+        summary,
+    })
+}
+
+fn channel_mse(image: &RgbaImage, reference: &RgbaImage, channel: usize) -> f64 {
+    let a = image.as_raw().iter().skip(channel).step_by(4);
+    let b = reference.as_raw().iter().skip(channel).step_by(4);
+    let sum: u64 = a
+        .zip(b)
+        .map(|(&x, &y)| {
+            let d = x as i64 - y as i64;
+            (d * d) as u64
+        })
+        .sum();
+    sum as f64 / (image.width() as f64 * image.height() as f64)
+}
+
+fn psnr(mse: f64) -> f64 {
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+/// Mean SSIM over 8x8 windows placed every 4 pixels, as libvpx and ffmpeg do.
+fn channel_ssim(image: &RgbaImage, reference: &RgbaImage, channel: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let window = 8.min(width).min(height);
+    let step = (window / 2).max(1);
+    let (a, b) = (image.as_raw(), reference.as_raw());
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - window).step_by(step) {
+        for x in (0..=width - window).step_by(step) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for row in y..y + window {
+                let start = (row * width + x) * 4 + channel;
+                for i in (start..start + window * 4).step_by(4) {
+                    let (p, q) = (a[i] as f64, b[i] as f64);
+                    sa += p;
+                    sb += q;
+                    saa += p * p;
+                    sbb += q * q;
+                    sab += p * q;
+                }
+            }
+            let n = (window * window) as f64;
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb) = (saa / n - ma * ma, sbb / n - mb * mb);
+            let cov = sab / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// The second side of a comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference {
+    Omv(PathBuf),
+    /// A directory of numbered pictures, such as png-picture output.
+    Images(PathBuf),
+    /// Anything ffmpeg can decode.
+    Video(PathBuf),
+}
+
+impl Reference {
+    /// A directory is an image sequence, an `.omv` file another OMV, anything
+    /// else a video for ffmpeg.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        if path.is_dir() {
+            Reference::Images(path)
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("omv"))
+        {
+            Reference::Omv(path)
+        } else {
+            Reference::Video(path)
+        }
+    }
+
+    /// Opens the frames, for a video cropped to `width`x`height` from the top
+    /// left so padding added for an encoder is left out.
+    pub fn frames(&self, width: u32, height: u32) -> Result<Frames> {
+        match self {
+            Reference::Omv(path) => {
+                let data = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+                omv_frames(&data)
+            }
+            Reference::Images(dir) => image_frames(dir),
+            Reference::Video(path) => video_frames(path, width, height),
+        }
+    }
+}
+
+/// Decodes the OMV file held in `mem`.
+pub fn omv_frames(mem: &[u8]) -> Result<Frames> {
+    let reader = OmvReader::open(mem, DecodeOptions::default())?;
+    Ok(Box::new(reader.map(|frame| frame.map(|frame| frame.image))))
+}
+
+fn image_frames(dir: &Path) -> Result<Frames> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let path = entry.map_err(|e| Error::io(dir, e))?.path();
+        if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return Err(anyhow!("no pictures in {}", dir.display()));
+    }
+    // numeric order for names like frame_9999 and frame_10000
+    paths.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_os_string();
+        (name.len(), name)
+    });
+    Ok(Box::new(paths.into_iter().map(|path| {
+        let image = image::open(&path).with_context(|| path.display().to_string())?;
+        Ok(image.into_rgba8())
+    })))
+}
+
+/// Raw RGBA frames read from an ffmpeg child process.
+struct VideoFrames {
+    child: Child,
+    stdout: ChildStdout,
+    frame_len: usize,
+    width: u32,
+    height: u32,
+    done: bool,
+}
+
+fn video_frames(path: &Path, width: u32, height: u32) -> Result<Frames> {
+    let ffmpeg = which::which("ffmpeg")
+        .map_err(|_| Error::EncoderUnavailable("ffmpeg not found in PATH".to_string()))?;
+    let mut child = Command::new(ffmpeg)
+        .args(["-v", "error", "-nostdin", "-i"])
+        .arg(path)
+        .args(["-vf", &format!("crop={}:{}:0:0", width, height)])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| Error::EncoderUnavailable(format!("cannot start ffmpeg: {}", e)))?;
+    let stdout = child.stdout.take().unwrap();
+    Ok(Box::new(VideoFrames {
+        child,
+        stdout,
+        frame_len: width as usize * height as usize * 4,
+        width,
+        height,
+        done: false,
+    }))
+}
+
+impl VideoFrames {
+    fn read_frame(&mut self) -> Result<Option<RgbaImage>> {
+        let mut buffer = vec![0u8; self.frame_len];
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.stdout.read(&mut buffer[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == buffer.len() {
+            return Ok(RgbaImage::from_raw(self.width, self.height, buffer));
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(Error::EncoderFailed(format!("ffmpeg exited with {}", status)).into());
+        }
+        if filled > 0 {
+            return Err(anyhow!("ffmpeg output ends inside a frame"));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for VideoFrames {
+    type Item = Result<RgbaImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.read_frame().transpose();
+        if !matches!(frame, Some(Ok(_))) {
+            self.done = true;
+        }
+        frame
+    }
+}
+
+impl Drop for VideoFrames {
+    fn drop(&mut self) {
+        // stop ffmpeg when the comparison ends before the video
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
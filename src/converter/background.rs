@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::Converter;
+use crate::decoder::RawPlanes;
+use crate::frame_rate::FrameRate;
+
+/// Edge of a checkerboard square, in pixels.
+const CHECKER_SIZE: u32 = 8;
+const CHECKER_LIGHT: [u8; 3] = [0xff, 0xff, 0xff];
+const CHECKER_DARK: [u8; 3] = [0xcc, 0xcc, 0xcc];
+
+/// What transparent regions are composited over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Background {
+    Color([u8; 3]),
+    /// An image, stretched to the frame size.
+    Image(PathBuf),
+    Checkerboard,
+}
+
+impl FromStr for Background {
+    type Err = anyhow::Error;
+
+    /// Parses `checkerboard`, a colour name, `#rrggbb`, or else an image path.
+    fn from_str(s: &str) -> Result<Self> {
+        let named = match s.to_ascii_lowercase().as_str() {
+            "checkerboard" => return Ok(Background::Checkerboard),
+            "black" => Some([0, 0, 0]),
+            "white" => Some([0xff, 0xff, 0xff]),
+            "gray" | "grey" => Some([0x80, 0x80, 0x80]),
+            "red" => Some([0xff, 0, 0]),
+            "green" => Some([0, 0xff, 0]),
+            "blue" => Some([0, 0, 0xff]),
+            "magenta" => Some([0xff, 0, 0xff]),
+            _ => None,
+        };
+        if let Some(color) = named {
+            return Ok(Background::Color(color));
+        }
+        if let Some(hex) = s.strip_prefix('#') {
+            let value = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or_else(|| anyhow!("invalid colour {:?}, expected #rrggbb", s))?;
+            let [_, r, g, b] = value.to_be_bytes();
+            return Ok(Background::Color([r, g, b]));
+        }
+        if s.is_empty() {
+            return Err(anyhow!("empty background"));
+        }
+        Ok(Background::Image(PathBuf::from(s)))
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Background::Color([r, g, b]) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+            Background::Image(path) => write!(f, "{}", path.display()),
+            Background::Checkerboard => write!(f, "checkerboard"),
+        }
+    }
+}
+
+impl Background {
+    /// Renders the background at the frame size.
+    pub fn render(&self, width: u32, height: u32) -> Result<RgbaImage> {
+        let image = match self {
+            Background::Color([r, g, b]) => {
+                RgbaImage::from_pixel(width, height, Rgba([*r, *g, *b, 0xff]))
+            }
+            Background::Image(path) => {
+                let image = image::open(path)
+                    .map_err(|e| anyhow!("cannot open background {}: {}", path.display(), e))?
+                    .into_rgba8();
+                let mut image = if image.dimensions() == (width, height) {
+                    image
+                } else {
+                    imageops::resize(&image, width, height, FilterType::Triangle)
+                };
+                // the background itself must be opaque
+                for pixel in image.pixels_mut() {
+                    pixel.0[3] = 0xff;
+                }
+                image
+            }
+            Background::Checkerboard => RgbaImage::from_fn(width, height, |x, y| {
+                let [r, g, b] = if ((x / CHECKER_SIZE) ^ (y / CHECKER_SIZE)) & 1 == 0 {
+                    CHECKER_LIGHT
+                } else {
+                    CHECKER_DARK
+                };
+                Rgba([r, g, b, 0xff])
+            }),
+        };
+        Ok(image)
+    }
+}
+
+/// Composites `image` over the opaque `background` in place.
+pub fn flatten(image: &mut RgbaImage, background: &RgbaImage) {
+    for (pixel, under) in image.pixels_mut().zip(background.pixels()) {
+        let a = pixel.0[3] as u32;
+        for c in 0..3 {
+            let value = pixel.0[c] as u32 * a + under.0[c] as u32 * (255 - a);
+            pixel.0[c] = ((value + 127) / 255) as u8;
+        }
+        pixel.0[3] = 0xff;
+    }
+}
+
+/// Flattens frames onto a background before handing them to an opaque encoder.
+pub struct BackgroundConverter {
+    inner: Box<dyn Converter>,
+    background: Background,
+    rendered: Option<RgbaImage>,
+}
+
+impl BackgroundConverter {
+    pub fn new(inner: Box<dyn Converter>, background: Background) -> Self {
+        Self {
+            inner,
+            background,
+            rendered: None,
+        }
+    }
+}
+
+impl Converter for BackgroundConverter {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        self.rendered = Some(self.background.render(width, height)?);
+        self.inner.prepare(width, height, rate)
+    }
+
+    fn convert_frame(&mut self, mut image: RgbaImage, frame_id: u32) -> Result<()> {
+        let background = self
+            .rendered
+            .as_ref()
+            .ok_or_else(|| anyhow!("Converter not prepared"))?;
+        if image.dimensions() != background.dimensions() {
+            return Err(anyhow!(
+                "frame {} is {}x{}, the background {}x{}",
+                frame_id,
+                image.width(),
+                image.height(),
+                background.width(),
+                background.height()
+            ));
+        }
+        flatten(&mut image, background);
+        self.inner.convert_frame(image, frame_id)
+    }
+
+    fn convert_planes(&mut self, planes: &RawPlanes, frame_id: u32) -> Result<()> {
+        self.inner.convert_planes(planes, frame_id)
+    }
+
+    fn finish(&self) -> Result<()> {
+        self.inner.finish()
+    }
+}
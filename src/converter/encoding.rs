@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// x264 speed preset; slower ones compress better at the same quality.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    #[default]
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl Preset {
+    /// The name x264 knows the preset by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Ultrafast => "ultrafast",
+            Preset::Superfast => "superfast",
+            Preset::Veryfast => "veryfast",
+            Preset::Faster => "faster",
+            Preset::Fast => "fast",
+            Preset::Medium => "medium",
+            Preset::Slow => "slow",
+            Preset::Slower => "slower",
+            Preset::Veryslow => "veryslow",
+        }
+    }
+}
+
+/// H.264 profile, which limits the coding tools players have to support.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum H264Profile {
+    Baseline,
+    #[default]
+    Main,
+    High,
+}
+
+impl H264Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            H264Profile::Baseline => "baseline",
+            H264Profile::Main => "main",
+            H264Profile::High => "high",
+        }
+    }
+}
+
+/// A video bitrate in bits per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitrate(pub u32);
+
+impl FromStr for Bitrate {
+    type Err = anyhow::Error;
+
+    /// Parses bits per second, optionally with a `k` or `M` suffix: `800k`, `2.5M`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (number, scale) = match s.as_bytes().last() {
+            Some(b'k' | b'K') => (&s[..s.len() - 1], 1e3),
+            Some(b'm' | b'M') => (&s[..s.len() - 1], 1e6),
+            _ => (s, 1.0),
+        };
+        let bps = number
+            .parse::<f64>()
+            .ok()
+            .map(|n| n * scale)
+            .filter(|bps| *bps >= 1.0 && *bps <= u32::MAX as f64)
+            .ok_or_else(|| anyhow!("invalid bitrate {:?}, expected e.g. 2500k or 4M", s))?;
+        Ok(Bitrate(bps.round() as u32))
+    }
+}
+
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Quality and speed of the h264 and ffmpeg encoders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderSettings {
+    /// x264 constant rate factor, 0-51, lower is better. ffmpeg only.
+    pub crf: Option<u8>,
+    /// Target bitrate; ffmpeg uses it instead of the crf.
+    pub bitrate: Option<Bitrate>,
+    /// ffmpeg only, ffmpeg's own default when left out.
+    pub preset: Option<Preset>,
+    /// ffmpeg only.
+    pub profile: H264Profile,
+}
+
+impl EncoderSettings {
+    /// The crf used when neither a crf nor a bitrate is given.
+    pub const DEFAULT_CRF: u8 = 18;
+    pub const MAX_CRF: u8 = 51;
+
+    /// Rejects a crf out of range, and a crf together with a bitrate.
+    pub fn check(self) -> Result<Self> {
+        match (self.crf, self.bitrate) {
+            (Some(crf), _) if crf > Self::MAX_CRF => Err(anyhow!(
+                "crf {} is out of range, expected 0 to {}",
+                crf,
+                Self::MAX_CRF
+            )),
+            (Some(_), Some(_)) => Err(anyhow!("a crf and a bitrate cannot be combined")),
+            _ => Ok(self),
+        }
+    }
+
+    /// Settings the openh264 encoder ignores.
+    pub(crate) fn ffmpeg_only(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.crf.is_some() {
+            names.push("crf");
+        }
+        if self.preset.is_some() {
+            names.push("preset");
+        }
+        if self.profile != H264Profile::default() {
+            names.push("profile");
+        }
+        names
+    }
+}
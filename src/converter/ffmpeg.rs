@@ -9,74 +9,121 @@ use anyhow::{anyhow, Result};
 use image::RgbaImage;
 use which::which;
 
-use super::Converter;
+use super::{Converter, EncoderSettings, FittedSize, SizePolicy};
+use crate::error::Error;
+use crate::frame_rate::FrameRate;
 
 pub struct FfmepgConverter {
     path: PathBuf,
     encoder: RefCell<Option<Child>>,
     stdin: RefCell<Option<ChildStdin>>,
-    fps: f32,
+    rate: Option<FrameRate>,
     width: u32,
     height: u32,
+    size_policy: SizePolicy,
+    size: Option<FittedSize>,
+    settings: EncoderSettings,
 }
 
-
 impl FfmepgConverter {
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
             encoder: RefCell::new(None),
             stdin: RefCell::new(None),
-            fps: 0.0,
+            rate: None,
             width: 0,
             height: 0,
+            size_policy: SizePolicy::default(),
+            size: None,
+            settings: EncoderSettings::default(),
         }
     }
-}
 
-fn rgba_to_rgb(img: RgbaImage) -> Vec<u8> {
-    let mut out = Vec::with_capacity((img.width() * img.height() * 3) as usize);
-    for pixel in img.pixels() {
-        out.extend_from_slice(&pixel.0[0..3]);
+    /// How to fit frames whose width or height is odd, which yuv420p rejects.
+    pub fn with_size_policy(mut self, policy: SizePolicy) -> Self {
+        self.size_policy = policy;
+        self
+    }
+
+    /// The crf or bitrate, preset and profile passed to libx264.
+    pub fn with_encoder_settings(mut self, settings: EncoderSettings) -> Self {
+        self.settings = settings;
+        self
     }
-    out
 }
 
 impl Converter for FfmepgConverter {
-    fn prepare(&mut self, width: u32, height: u32, fps: f32) -> Result<()> {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        let size = FittedSize::new(width, height, 2, self.size_policy, "ffmpeg yuv420p")?;
+        let (width, height) = (size.width, size.height);
         self.width = width;
         self.height = height;
-        self.fps = fps;
+        self.rate = Some(rate);
+        self.size = Some(size);
 
-        let ffmpeg_path = which("ffmpeg").map_err(|_| anyhow!("ffmpeg not found"))?;
+        let ffmpeg_path = which("ffmpeg")
+            .map_err(|_| Error::EncoderUnavailable("ffmpeg not found in PATH".to_string()))?;
 
         let mut cmd = Command::new(ffmpeg_path);
         cmd.arg("-y")
-            .arg("-f").arg("rawvideo")
-            .arg("-pix_fmt").arg("rgba")
-            .arg("-s").arg(format!("{}x{}", width, height))
-            .arg("-r").arg(format!("{}", fps))
-            .arg("-i").arg("-")
-            .arg("-c:v").arg("libx264")
-            .arg("-pix_fmt").arg("yuv420p")
-            .arg("-profile:v").arg("main")
-            .arg("-crf").arg("18")
-            .arg(&self.path)
+            .arg("-f")
+            .arg("rawvideo")
+            .arg("-pix_fmt")
+            .arg("rgba")
+            .arg("-s")
+            .arg(format!("{}x{}", width, height))
+            .arg("-r")
+            .arg(rate.to_string())
+            .arg("-i")
+            .arg("-")
+            .arg("-c:v")
+            .arg("libx264")
+            .arg("-pix_fmt")
+            .arg("yuv420p")
+            .arg("-profile:v")
+            .arg(self.settings.profile.name());
+        if let Some(preset) = self.settings.preset {
+            cmd.arg("-preset").arg(preset.name());
+        }
+        match self.settings.bitrate {
+            Some(bitrate) => cmd.arg("-b:v").arg(bitrate.to_string()),
+            None => cmd.arg("-crf").arg(
+                self.settings
+                    .crf
+                    .unwrap_or(EncoderSettings::DEFAULT_CRF)
+                    .to_string(),
+            ),
+        };
+        if size.is_adjusted() {
+            cmd.arg("-metadata")
+                .arg(format!("comment={}", size.original_size_tag()));
+        }
+        cmd.arg(&self.path)
             .stdin(Stdio::piped())
             .stdout(Stdio::null())
             .stderr(Stdio::inherit());
 
-        let mut child = cmd.spawn().map_err(|e| anyhow!("Failed to spawn ffmpeg: {}", e))?;
-        let child_stdin = child.stdin.take().ok_or_else(|| anyhow!("Failed to open stdin"))?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Error::EncoderUnavailable(format!("failed to spawn ffmpeg: {}", e)))?;
+        let child_stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Failed to open stdin"))?;
 
         *self.encoder.borrow_mut() = Some(child);
         *self.stdin.borrow_mut() = Some(child_stdin);
         Ok(())
     }
 
-    fn convert_frame(&mut self, image: RgbaImage, _frame_id: u32) -> Result<()> {
+    fn convert_frame(&mut self, image: RgbaImage, frame_id: u32) -> Result<()> {
+        let size = self.size.ok_or_else(|| anyhow!("Encoder not prepared"))?;
+        let image = size.fit(image, frame_id)?;
         if let Some(stdin) = &mut *self.stdin.borrow_mut() {
-            stdin.write_all(&image.into_raw())?;
+            stdin.write_all(&image.into_raw()).map_err(|e| {
+                Error::EncoderFailed(format!("ffmpeg stopped reading frames: {}", e))
+            })?;
             Ok(())
         } else {
             Err(anyhow!("Encoder not prepared"))
@@ -86,13 +133,13 @@ impl Converter for FfmepgConverter {
     fn finish(&self) -> Result<()> {
         if let Some(mut stdin) = self.stdin.borrow_mut().take() {
             stdin.flush()?; // flush
-            drop(stdin); 
+            drop(stdin);
         }
 
         if let Some(mut child) = self.encoder.borrow_mut().take() {
             let status = child.wait()?;
             if !status.success() {
-                return Err(anyhow!("ffmpeg exited with status {}", status));
+                return Err(Error::EncoderFailed(format!("ffmpeg exited with {}", status)).into());
             }
         }
 
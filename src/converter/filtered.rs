@@ -0,0 +1,55 @@
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+
+use super::Converter;
+use crate::decoder::RawPlanes;
+use crate::filter::FilterChain;
+use crate::frame_rate::FrameRate;
+
+/// Runs frames through a filter chain before handing them on, announcing the
+/// filtered size to the inner converter.
+pub struct FilteredConverter {
+    inner: Box<dyn Converter>,
+    chain: FilterChain,
+    size: Option<(u32, u32)>,
+}
+
+impl FilteredConverter {
+    pub fn new(inner: Box<dyn Converter>, chain: FilterChain) -> Self {
+        Self {
+            inner,
+            chain,
+            size: None,
+        }
+    }
+}
+
+impl Converter for FilteredConverter {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        let (width, height) = self.chain.output_size(width, height)?;
+        self.size = Some((width, height));
+        self.inner.prepare(width, height, rate)
+    }
+
+    fn convert_frame(&mut self, image: RgbaImage, frame_id: u32) -> Result<()> {
+        let image = self.chain.apply(image)?;
+        if Some(image.dimensions()) != self.size {
+            return Err(anyhow!(
+                "filtered frame {} is {}x{}, {:?} was announced",
+                frame_id,
+                image.width(),
+                image.height(),
+                self.size
+            ));
+        }
+        self.inner.convert_frame(image, frame_id)
+    }
+
+    fn convert_planes(&mut self, planes: &RawPlanes, frame_id: u32) -> Result<()> {
+        self.inner.convert_planes(planes, frame_id)
+    }
+
+    fn finish(&self) -> Result<()> {
+        self.inner.finish()
+    }
+}
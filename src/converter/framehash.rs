@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+
+use super::{Converter, PipedOutput};
+use crate::decoder::RawPlanes;
+use crate::frame_rate::FrameRate;
+
+/// Writes one MD5 line per frame in the layout of ffmpeg's framemd5 muxer, for
+/// golden files that pin down the decoder output.
+///
+/// Frames are hashed as raw RGBA8. With [`with_channels`](Self::with_channels)
+/// every line also carries a hash of each of the R, G, B and A channels, so a
+/// change in the output can be told apart as a colour or an alpha change.
+/// With [`with_planes`](Self::with_planes) it also hashes the Y, Cb and Cr planes
+/// the decoder produced, to tell a decoder change from a change in how frames
+/// are reconstructed from them.
+pub struct FrameHashConverter {
+    target: PipedOutput,
+    /// Opened in `prepare` unless a writer was supplied up front.
+    out: RefCell<Option<Box<dyn Write>>>,
+    channels: bool,
+    planes: bool,
+    /// Hashes of the planes [`convert_planes`](Converter::convert_planes) got
+    /// for the next frame.
+    plane_hashes: Option<(u32, [md5::Digest; 3])>,
+}
+
+impl FrameHashConverter {
+    /// Writes to the destination named by `path`, see [`PipedOutput::from_path`].
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            target: PipedOutput::from_path(path),
+            out: RefCell::new(None),
+            channels: false,
+            planes: false,
+            plane_hashes: None,
+        }
+    }
+
+    /// Writes into an arbitrary `writer`.
+    pub fn from_writer(writer: impl Write + 'static) -> Self {
+        let cvt = Self::new("-");
+        *cvt.out.borrow_mut() = Some(Box::new(writer));
+        cvt
+    }
+
+    /// Also hash each channel of the RGBA frames on its own.
+    pub fn with_channels(mut self, channels: bool) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Also hash each decoded plane. The reader has to keep them, see
+    /// [`DecodeOptions::raw_planes`](crate::decoder::DecodeOptions::raw_planes).
+    pub fn with_planes(mut self, planes: bool) -> Self {
+        self.planes = planes;
+        self
+    }
+}
+
+impl Converter for FrameHashConverter {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        let out = self.out.get_mut();
+        if out.is_none() {
+            *out = Some(self.target.open()?);
+        }
+        let out = out.as_mut().unwrap();
+        writeln!(out, "#format: frame checksums")?;
+        writeln!(out, "#version: 2")?;
+        writeln!(out, "#hash: MD5")?;
+        // one tick per frame, so the pts is the frame index
+        writeln!(out, "#tb 0: {}/{}", rate.den(), rate.num())?;
+        writeln!(out, "#media_type 0: video")?;
+        writeln!(out, "#codec_id 0: rawvideo")?;
+        writeln!(out, "#dimensions 0: {}x{}", width, height)?;
+        writeln!(out, "#sar 0: 1/1")?;
+        write!(out, "#stream#, dts,        pts, duration,     size, hash")?;
+        if self.channels {
+            write!(out, ", r, g, b, a")?;
+        }
+        if self.planes {
+            write!(out, ", y, cb, cr")?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn convert_frame(&mut self, image: RgbaImage, frame_id: u32) -> Result<()> {
+        let mut out = self.out.borrow_mut();
+        let out = out
+            .as_mut()
+            .ok_or_else(|| anyhow!("Frame hash not prepared"))?;
+        let pixels = image.as_raw();
+        write!(
+            out,
+            "0, {:10}, {:10}, {:8}, {:8}, {:x}",
+            frame_id,
+            frame_id,
+            1,
+            pixels.len(),
+            md5::compute(pixels)
+        )?;
+        if self.channels {
+            let mut contexts = [(); 4].map(|_| md5::Context::new());
+            for row in pixels.chunks(image.width() as usize * 4) {
+                for (channel, context) in contexts.iter_mut().enumerate() {
+                    let samples: Vec<u8> = row.iter().skip(channel).step_by(4).copied().collect();
+                    context.consume(samples);
+                }
+            }
+            for context in contexts {
+                write!(out, ", {:x}", context.compute())?;
+            }
+        }
+        if self.planes {
+            let hashes = match self.plane_hashes.take() {
+                Some((id, hashes)) if id == frame_id => hashes,
+                _ => return Err(anyhow!("no decoded planes for frame {}", frame_id)),
+            };
+            for hash in hashes {
+                write!(out, ", {:x}", hash)?;
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn convert_planes(&mut self, planes: &RawPlanes, frame_id: u32) -> Result<()> {
+        if self.planes {
+            let hashes = [0, 1, 2].map(|i| md5::compute(planes.plane(i)));
+            self.plane_hashes = Some((frame_id, hashes));
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        let mut out = self.out.borrow_mut();
+        let out = out
+            .as_mut()
+            .ok_or_else(|| anyhow!("Frame hash not prepared"))?;
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -1,22 +1,31 @@
 use anyhow::Result;
 use image::DynamicImage;
 use minimp4::Mp4Muxer;
-use std::{io::Cursor, path::{Path, PathBuf}};
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
 
 use openh264::{
-    encoder::Encoder,
+    encoder::{BitRate, Encoder, EncoderConfig},
     formats::{RgbaSliceU8, YUVBuffer},
+    OpenH264API,
 };
 
-use super::Converter;
+use super::{set_mp4_frame_rate, Converter, EncoderSettings, FittedSize, SizePolicy};
+use crate::error::Error;
+use crate::frame_rate::FrameRate;
 
 pub struct H264Converter {
     path: PathBuf,
     encoder: Option<Encoder>,
-    fps: f32,
+    rate: Option<FrameRate>,
     width: u32,
     height: u32,
     buffer: Vec<u8>,
+    size_policy: SizePolicy,
+    size: Option<FittedSize>,
+    settings: EncoderSettings,
 }
 
 impl H264Converter {
@@ -24,25 +33,58 @@ impl H264Converter {
         Self {
             path: path.as_ref().to_path_buf(),
             encoder: None,
-            fps: 0.0,
+            rate: None,
             width: 0,
             height: 0,
             buffer: Vec::new(),
+            size_policy: SizePolicy::default(),
+            size: None,
+            settings: EncoderSettings::default(),
         }
     }
+
+    /// How to fit frames whose width or height is odd, which openh264 rejects.
+    pub fn with_size_policy(mut self, policy: SizePolicy) -> Self {
+        self.size_policy = policy;
+        self
+    }
+
+    /// Only the bitrate applies, openh264 has no crf, preset or profile to choose.
+    pub fn with_encoder_settings(mut self, settings: EncoderSettings) -> Self {
+        self.settings = settings;
+        self
+    }
 }
 
 impl Converter for H264Converter {
-    fn prepare(&mut self, width: u32, height: u32, fps: f32) -> Result<()> {
-        self.fps = fps;
-        self.width = width;
-        self.height = height;
-        self.encoder = Some(Encoder::new()?);
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        let size = FittedSize::new(width, height, 2, self.size_policy, "h264")?;
+        self.rate = Some(rate);
+        self.width = size.width;
+        self.height = size.height;
+        self.size = Some(size);
+        let ignored = self.settings.ffmpeg_only();
+        if !ignored.is_empty() {
+            log::warn!(
+                "h264 ignores the {} setting(s), use ffmpeg for them",
+                ignored.join(", ")
+            );
+        }
+        let mut config = EncoderConfig::new();
+        if let Some(bitrate) = self.settings.bitrate {
+            config = config.bitrate(BitRate::from_bps(bitrate.0));
+        }
+        let encoder = Encoder::with_api_config(OpenH264API::from_source(), config)
+            .map_err(|e| Error::EncoderUnavailable(format!("openh264: {}", e)))?;
+        self.encoder = Some(encoder);
         Ok(())
     }
 
-    fn convert_frame(&mut self, image: image::RgbaImage, _frame_id: u32) -> Result<()> {
-        let image = DynamicImage::ImageRgba8(image);
+    fn convert_frame(&mut self, image: image::RgbaImage, frame_id: u32) -> Result<()> {
+        let size = self
+            .size
+            .ok_or_else(|| anyhow::anyhow!("Encoder not initialized"))?;
+        let image = DynamicImage::ImageRgba8(size.fit(image, frame_id)?);
         let image = RgbaSliceU8::new(
             image.as_bytes(),
             (self.width as usize, self.height as usize),
@@ -50,7 +92,9 @@ impl Converter for H264Converter {
         let yuv = YUVBuffer::from_rgb_source(image);
         match &mut self.encoder {
             Some(encoder) => {
-                let bitstream = encoder.encode(&yuv)?;
+                let bitstream = encoder
+                    .encode(&yuv)
+                    .map_err(|e| Error::EncoderFailed(format!("openh264: {}", e)))?;
                 bitstream.write(&mut self.buffer)?;
             }
             None => {
@@ -62,17 +106,27 @@ impl Converter for H264Converter {
     }
 
     fn finish(&self) -> Result<()> {
-
         // TODO:
         // bad design, will comsume a lot of memory
         let mut video_buffer = Cursor::new(Vec::new());
         let mut mp4muxer = Mp4Muxer::new(&mut video_buffer);
-        mp4muxer.init_video(self.width as i32, self.height as i32, false, "");
-        mp4muxer.write_video_with_fps(&self.buffer, self.fps as u32);
+        // the track name records the size frames had before fitting
+        let track_name = match self.size {
+            Some(size) if size.is_adjusted() => size.original_size_tag(),
+            _ => String::new(),
+        };
+        mp4muxer.init_video(self.width as i32, self.height as i32, false, &track_name);
+        let rate = self
+            .rate
+            .ok_or_else(|| anyhow::anyhow!("Encoder not initialized"))?;
+        // the muxer only takes whole frame rates, the exact one is patched in below
+        mp4muxer.write_video_with_fps(&self.buffer, rate.rounded());
         mp4muxer.close();
 
+        let mut video = video_buffer.into_inner();
+        set_mp4_frame_rate(&mut video, rate)?;
         let path = self.path.with_extension("mp4");
-        std::fs::write(path, video_buffer.into_inner())?;
+        std::fs::write(&path, video).map_err(|e| Error::io(path, e))?;
         Ok(())
     }
 }
@@ -1,7 +1,9 @@
 use anyhow::Result;
+use image::DynamicImage;
 use std::path::{Path, PathBuf};
 
 use super::Converter;
+use crate::frame_rate::FrameRate;
 
 pub struct JpgConverter {
     path: PathBuf,
@@ -16,7 +18,7 @@ impl JpgConverter {
 }
 
 impl Converter for JpgConverter {
-    fn prepare(&mut self, _width: u32, _height: u32, _fps: f32) -> Result<()> {
+    fn prepare(&mut self, _width: u32, _height: u32, _rate: FrameRate) -> Result<()> {
         if !self.path.exists() {
             std::fs::create_dir_all(&self.path)?;
         }
@@ -24,12 +26,13 @@ impl Converter for JpgConverter {
     }
 
     fn convert_frame(&mut self, image: image::RgbaImage, frame_id: u32) -> Result<()> {
-        let path = self.path.with_file_name(format!("frame_{:04}.jpg", frame_id));
-        image.save(path)?;
+        let path = self.path.join(format!("frame_{:04}.jpg", frame_id));
+        // JPEG has no alpha channel
+        DynamicImage::ImageRgba8(image).into_rgb8().save(path)?;
         Ok(())
     }
 
     fn finish(&self) -> Result<()> {
         Ok(())
     }
-}
\ No newline at end of file
+}
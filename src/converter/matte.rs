@@ -0,0 +1,109 @@
+use anyhow::Result;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use super::Converter;
+use crate::decoder::RawPlanes;
+use crate::frame_rate::FrameRate;
+
+/// How the matte carries the alpha channel.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatteKind {
+    /// Opaque grayscale, white where the source is opaque.
+    #[default]
+    Luma,
+    /// White with the source alpha in the alpha channel, PNG only.
+    Alpha,
+}
+
+/// Colour output that goes with a matte.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatteColour {
+    /// Colour as decoded, independent of alpha.
+    #[default]
+    Straight,
+    /// Colour multiplied by alpha, black where the source is transparent.
+    Premultiplied,
+}
+
+/// Splits frames into an opaque colour frame and a separate matte frame, each fed
+/// to its own converter.
+pub struct MatteConverter {
+    colour: Box<dyn Converter>,
+    matte: Box<dyn Converter>,
+    kind: MatteKind,
+    colour_mode: MatteColour,
+}
+
+impl MatteConverter {
+    pub fn new(colour: Box<dyn Converter>, matte: Box<dyn Converter>) -> Self {
+        Self {
+            colour,
+            matte,
+            kind: MatteKind::default(),
+            colour_mode: MatteColour::default(),
+        }
+    }
+
+    pub fn with_kind(mut self, kind: MatteKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_colour(mut self, colour: MatteColour) -> Self {
+        self.colour_mode = colour;
+        self
+    }
+
+    /// Splits `image` into its colour and matte frames.
+    pub fn split(&self, image: &RgbaImage) -> (RgbaImage, RgbaImage) {
+        let mut colour = image.clone();
+        let mut matte = RgbaImage::new(image.width(), image.height());
+        for (c, m) in colour.pixels_mut().zip(matte.pixels_mut()) {
+            let [r, g, b, a] = c.0;
+            *c = match self.colour_mode {
+                MatteColour::Straight => Rgba([r, g, b, 0xff]),
+                MatteColour::Premultiplied => Rgba([
+                    premultiply(r, a),
+                    premultiply(g, a),
+                    premultiply(b, a),
+                    0xff,
+                ]),
+            };
+            *m = match self.kind {
+                MatteKind::Luma => Rgba([a, a, a, 0xff]),
+                MatteKind::Alpha => Rgba([0xff, 0xff, 0xff, a]),
+            };
+        }
+        (colour, matte)
+    }
+}
+
+fn premultiply(value: u8, alpha: u8) -> u8 {
+    ((value as u32 * alpha as u32 + 127) / 255) as u8
+}
+
+impl Converter for MatteConverter {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        self.colour.prepare(width, height, rate)?;
+        self.matte.prepare(width, height, rate)
+    }
+
+    fn convert_frame(&mut self, image: RgbaImage, frame_id: u32) -> Result<()> {
+        let (colour, matte) = self.split(&image);
+        self.colour.convert_frame(colour, frame_id)?;
+        self.matte.convert_frame(matte, frame_id)
+    }
+
+    fn convert_planes(&mut self, planes: &RawPlanes, frame_id: u32) -> Result<()> {
+        self.colour.convert_planes(planes, frame_id)?;
+        self.matte.convert_planes(planes, frame_id)
+    }
+
+    fn finish(&self) -> Result<()> {
+        self.colour.finish()?;
+        self.matte.finish()
+    }
+}
@@ -1,15 +1,56 @@
 use anyhow::Result;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::decoder::RawPlanes;
+use crate::frame_rate::FrameRate;
 
 mod png;
 mod jpg;
 mod h264;
 mod ffmpeg;
 mod piped;
+mod matte;
+mod packed;
+mod background;
+mod filtered;
+mod size;
+mod mp4;
+mod framehash;
+mod encoding;
+
+pub use {png::PngConverter, jpg::JpgConverter, h264::H264Converter, ffmpeg::FfmepgConverter, piped::PipedPngConverter, piped::PipedPayload, piped::PipedOutput, matte::MatteConverter, matte::MatteKind, matte::MatteColour, packed::PackedAlphaConverter, packed::AlphaLayout, background::BackgroundConverter, background::Background, filtered::FilteredConverter, size::SizePolicy, size::FittedSize, mp4::set_mp4_frame_rate, framehash::FrameHashConverter, encoding::EncoderSettings, encoding::Bitrate, encoding::Preset, encoding::H264Profile};
 
-pub use {png::PngConverter, jpg::JpgConverter, h264::H264Converter, ffmpeg::FfmepgConverter, piped::PipedPngConverter};
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    H264,
+    PngPicture,
+    #[default]
+    JpgPicture,
+    Ffmpeg,
+    PipedPng,
+    /// Copy the embedded Ogg/Theora stream without re-encoding
+    Ogv,
+    /// One MD5 line per frame, like ffmpeg's framemd5
+    Framehash,
+}
 
 pub trait Converter {
-    fn prepare(&mut self, width: u32, height: u32, fps: f32) -> Result<()>;
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()>;
     fn convert_frame(&mut self, image: image::RgbaImage, frame_id: u32) -> Result<()>;
+    /// Receives the decoded planes of the frame `convert_frame` gets next, when
+    /// the reader keeps them. Most converters only look at the RGBA frames.
+    fn convert_planes(&mut self, _planes: &RawPlanes, _frame_id: u32) -> Result<()> {
+        Ok(())
+    }
     fn finish(&self) -> Result<()>;
 }
+
+pub(crate) fn rgba_to_rgb(img: RgbaImage) -> Vec<u8> {
+    let mut out = Vec::with_capacity((img.width() * img.height() * 3) as usize);
+    for pixel in img.pixels() {
+        out.extend_from_slice(&pixel.0[0..3]);
+    }
+    out
+}
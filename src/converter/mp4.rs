@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+
+use crate::frame_rate::FrameRate;
+
+/// Boxes on the way from `moov` to `mdhd`, `stts` and `elst`.
+const CONTAINERS: [&[u8; 4]; 6] = [b"moov", b"trak", b"edts", b"mdia", b"minf", b"stbl"];
+
+/// One box: its type and the byte range of its payload in the file.
+struct Mp4Box {
+    kind: [u8; 4],
+    payload: std::ops::Range<usize>,
+}
+
+fn boxes(data: &[u8], range: std::ops::Range<usize>) -> Result<Vec<Mp4Box>> {
+    let mut found = Vec::new();
+    let mut pos = range.start;
+    while pos + 8 <= range.end {
+        let size = be32(data, pos) as u64;
+        let kind = data[pos + 4..pos + 8].try_into().unwrap();
+        let (header, size) = match size {
+            0 => (8, (range.end - pos) as u64),
+            1 if pos + 16 <= range.end => (16, be64(data, pos + 8)),
+            _ => (8, size),
+        };
+        let end = pos as u64 + size;
+        if size < header || end > range.end as u64 {
+            return Err(anyhow!("broken mp4 box at {:#x}", pos));
+        }
+        found.push(Mp4Box {
+            kind,
+            payload: pos + header as usize..end as usize,
+        });
+        pos = end as usize;
+    }
+    Ok(found)
+}
+
+/// Rewrites the timing of the video track of a muxed MP4 in place so frames
+/// last exactly `rate.den() / rate.num()` seconds: the media timescale becomes
+/// `rate.num()`, every sample `rate.den()` ticks long, and the movie and track
+/// durations follow. An edit list is scaled along with the frames, as long as
+/// every frame of the track had the same duration before.
+///
+/// MP4 muxers that take a whole number of frames per second get the timing of
+/// rates like 30000/1001 wrong; this fixes it after the fact without touching
+/// the sample data. Returns the number of samples retimed.
+pub fn set_mp4_frame_rate(data: &mut [u8], rate: FrameRate) -> Result<u64> {
+    let mut stack = Vec::new();
+    stack.push(0..data.len());
+    let (mut mvhd, mut tkhd, mut mdhd, mut stts, mut elst) = (None, None, None, None, None);
+    while let Some(range) = stack.pop() {
+        for b in boxes(data, range)? {
+            match &b.kind {
+                b"mvhd" => mvhd = Some(b.payload),
+                b"tkhd" => tkhd = Some(b.payload),
+                b"mdhd" => mdhd = Some(b.payload),
+                b"stts" => stts = Some(b.payload),
+                b"elst" => elst = Some(b.payload),
+                kind if CONTAINERS.contains(&kind) => stack.push(b.payload),
+                _ => {}
+            }
+        }
+    }
+    let (Some(mvhd), Some(tkhd), Some(mdhd), Some(stts)) = (mvhd, tkhd, mdhd, stts) else {
+        return Err(anyhow!("no video track in the mp4"));
+    };
+
+    // stts: version and flags, entry count, then (sample count, delta) pairs
+    if stts.len() < 8 {
+        return Err(anyhow!("broken stts box"));
+    }
+    let entries = be32(data, stts.start + 4) as usize;
+    if entries > (stts.len() - 8) / 8 {
+        return Err(anyhow!("broken stts box"));
+    }
+    let mut samples = 0u64;
+    // the frame duration before, if it was the same for every frame
+    let mut old_delta = None;
+    let mut uniform = true;
+    for i in 0..entries {
+        let entry = stts.start + 8 + i * 8;
+        samples += be32(data, entry) as u64;
+        let delta = be32(data, entry + 4);
+        uniform &= old_delta.is_none_or(|old| old == delta);
+        old_delta = Some(delta);
+        data[entry + 4..entry + 8].copy_from_slice(&rate.den().to_be_bytes());
+    }
+
+    let media_duration = samples * rate.den() as u64;
+    let (timescale, duration) = field_offsets(data, &mdhd, 12, 20)?;
+    let old_timescale = be32(data, timescale) as u64;
+    data[timescale..timescale + 4].copy_from_slice(&rate.num().to_be_bytes());
+    write_duration(data, &mdhd, duration, media_duration)?;
+
+    // movie and track durations count in the movie timescale
+    let (movie_timescale, movie_duration) = field_offsets(data, &mvhd, 12, 20)?;
+    let movie_timescale = be32(data, movie_timescale) as u64;
+    let duration = media_duration * movie_timescale / rate.num() as u64;
+    write_duration(data, &mvhd, movie_duration, duration)?;
+    let (track_duration, _) = field_offsets(data, &tkhd, 20, 28)?;
+    write_duration(data, &tkhd, track_duration, duration)?;
+
+    if let Some(elst) = elst {
+        match old_delta {
+            _ if !uniform => {
+                return Err(anyhow!(
+                    "cannot retime the edit list of a track whose frames differ in duration"
+                ))
+            }
+            Some(old_delta) if old_delta > 0 && old_timescale > 0 => {
+                // segments count in the movie timescale, which stays, so they
+                // change by the ratio of the frame durations in seconds
+                let segment = (
+                    rate.den() as u64 * old_timescale,
+                    rate.num() as u64 * old_delta as u64,
+                );
+                retime_edits(data, &elst, segment, (rate.den() as u64, old_delta as u64))?;
+            }
+            // no frames, nothing for the edits to point at
+            _ => {}
+        }
+    }
+    Ok(samples)
+}
+
+/// Multiplies the segment durations of an `elst` box by `segment.0 / segment.1`
+/// and the media times by `media.0 / media.1`. Empty edits keep their media
+/// time of -1.
+fn retime_edits(
+    data: &mut [u8],
+    elst: &std::ops::Range<usize>,
+    segment: (u64, u64),
+    media: (u64, u64),
+) -> Result<()> {
+    if elst.len() < 8 {
+        return Err(anyhow!("broken elst box"));
+    }
+    // version 1 has 64-bit durations and media times, then the 32-bit rate
+    let width = if data[elst.start] == 1 { 8 } else { 4 };
+    let entry_len = 2 * width + 4;
+    let entries = be32(data, elst.start + 4) as usize;
+    if entries > (elst.len() - 8) / entry_len {
+        return Err(anyhow!("broken elst box"));
+    }
+    let scale = |value: u64, (num, den): (u64, u64)| {
+        (value as u128 * num as u128 / den as u128).min(u64::MAX as u128) as u64
+    };
+    for i in 0..entries {
+        let entry = elst.start + 8 + i * entry_len;
+        let duration = read_uint(data, entry, width);
+        write_uint(data, entry, width, scale(duration, segment), u64::MAX);
+        let media_time = entry + width;
+        // a negative media time in either width marks an empty edit
+        if data[media_time] & 0x80 == 0 {
+            let time = read_uint(data, media_time, width);
+            let max = if width == 8 {
+                i64::MAX as u64
+            } else {
+                i32::MAX as u64
+            };
+            write_uint(data, media_time, width, scale(time, media), max);
+        }
+    }
+    Ok(())
+}
+
+fn read_uint(data: &[u8], pos: usize, width: usize) -> u64 {
+    if width == 8 {
+        be64(data, pos)
+    } else {
+        be32(data, pos) as u64
+    }
+}
+
+/// Writes `value`, clamped to `max` and to the field width.
+fn write_uint(data: &mut [u8], pos: usize, width: usize, value: u64, max: u64) {
+    let value = value.min(max);
+    if width == 8 {
+        data[pos..pos + 8].copy_from_slice(&value.to_be_bytes());
+    } else {
+        let value = value.min(u32::MAX as u64) as u32;
+        data[pos..pos + 4].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Offsets of the field at `v0` in a version 0 box (32-bit times) or `v1` in a
+/// version 1 box (64-bit times), and of the duration right after it.
+fn field_offsets(
+    data: &[u8],
+    payload: &std::ops::Range<usize>,
+    v0: usize,
+    v1: usize,
+) -> Result<(usize, usize)> {
+    if payload.is_empty() {
+        return Err(anyhow!("mp4 box too short"));
+    }
+    let offset = if data[payload.start] == 1 { v1 } else { v0 };
+    let field = payload.start + offset;
+    if field + 4 > payload.end {
+        return Err(anyhow!("mp4 box too short"));
+    }
+    Ok((field, field + 4))
+}
+
+fn write_duration(
+    data: &mut [u8],
+    payload: &std::ops::Range<usize>,
+    offset: usize,
+    duration: u64,
+) -> Result<()> {
+    if data[payload.start] == 1 {
+        if offset + 8 > payload.end {
+            return Err(anyhow!("mp4 box too short"));
+        }
+        data[offset..offset + 8].copy_from_slice(&duration.to_be_bytes());
+    } else {
+        if offset + 4 > payload.end {
+            return Err(anyhow!("mp4 box too short"));
+        }
+        let duration = duration.min(u32::MAX as u64) as u32;
+        data[offset..offset + 4].copy_from_slice(&duration.to_be_bytes());
+    }
+    Ok(())
+}
+
+fn be32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn be64(data: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap())
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use super::Converter;
+use crate::decoder::RawPlanes;
+use crate::frame_rate::FrameRate;
+
+/// Where the alpha channel goes in a packed opaque frame.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlphaLayout {
+    /// Colour on the left, grayscale mask on the right.
+    SideBySide,
+    /// Colour on top, grayscale mask below.
+    TopBottom,
+    /// Colour on top, the mask split in three bands stored in the blue, green and
+    /// red channels below it, as in OMV shader videos.
+    SiglusBands,
+}
+
+impl AlphaLayout {
+    /// Size of the packed frame for a `width` x `height` source.
+    pub fn packed_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            AlphaLayout::SideBySide => (width * 2, height),
+            AlphaLayout::TopBottom => (width, height * 2),
+            AlphaLayout::SiglusBands => (width, height + height.div_ceil(3)),
+        }
+    }
+
+    /// Packs the colour and alpha of `image` into one opaque frame.
+    pub fn pack(self, image: &RgbaImage) -> RgbaImage {
+        let (width, height) = image.dimensions();
+        let (packed_width, packed_height) = self.packed_size(width, height);
+        let mut packed = RgbaImage::from_pixel(packed_width, packed_height, Rgba([0, 0, 0, 0xff]));
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let [r, g, b, a] = pixel.0;
+            packed.put_pixel(x, y, Rgba([r, g, b, 0xff]));
+            match self {
+                AlphaLayout::SideBySide => packed.put_pixel(x + width, y, Rgba([a, a, a, 0xff])),
+                AlphaLayout::TopBottom => packed.put_pixel(x, y + height, Rgba([a, a, a, 0xff])),
+                AlphaLayout::SiglusBands => {
+                    // band k of the mask goes to the rows below the colour, in
+                    // the blue, green and red plane respectively
+                    let band = height.div_ceil(3);
+                    let row = height + y % band;
+                    let channel = 2 - (y / band) as usize;
+                    packed.get_pixel_mut(x, row).0[channel] = a;
+                }
+            }
+        }
+        packed
+    }
+}
+
+/// Packs RGBA frames into opaque frames with the mask beside or below the colour,
+/// so encoders without alpha support can carry transparency.
+pub struct PackedAlphaConverter {
+    inner: Box<dyn Converter>,
+    layout: AlphaLayout,
+}
+
+impl PackedAlphaConverter {
+    pub fn new(inner: Box<dyn Converter>, layout: AlphaLayout) -> Self {
+        Self { inner, layout }
+    }
+}
+
+impl Converter for PackedAlphaConverter {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
+        let (width, height) = self.layout.packed_size(width, height);
+        self.inner.prepare(width, height, rate)
+    }
+
+    fn convert_frame(&mut self, image: RgbaImage, frame_id: u32) -> Result<()> {
+        let packed = self.layout.pack(&image);
+        self.inner.convert_frame(packed, frame_id)
+    }
+
+    fn convert_planes(&mut self, planes: &RawPlanes, frame_id: u32) -> Result<()> {
+        self.inner.convert_planes(planes, frame_id)
+    }
+
+    fn finish(&self) -> Result<()> {
+        self.inner.finish()
+    }
+}
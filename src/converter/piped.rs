@@ -1,51 +1,259 @@
 use anyhow::{anyhow, Result};
-use image::{ImageEncoder, RgbaImage};
 use image::codecs::png::PngEncoder;
-use std::io::{Write};
-use std::path::Path;
+use image::codecs::qoi::QoiEncoder;
 use image::ExtendedColorType;
+use image::{ImageEncoder, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::{rgba_to_rgb, Converter};
+use crate::error::Error;
+use crate::frame_rate::FrameRate;
+
+/// Version announced in the `PXY4M` header line.
+pub const PIPED_PROTOCOL_VERSION: u32 = 2;
+
+/// Per-frame payload encoding of the piped stream.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PipedPayload {
+    /// Raw RGBA8 pixels, row-major, no padding.
+    RawRgba,
+    /// Raw RGB8 pixels, alpha is dropped.
+    RawRgb,
+    /// QOI image, RGB or RGBA depending on whether the source has alpha.
+    Qoi,
+    /// PNG image, RGB or RGBA depending on whether the source has alpha.
+    #[default]
+    Png,
+}
+
+impl PipedPayload {
+    /// Value of the `Enc:` header token.
+    pub fn encoding_name(self) -> &'static str {
+        match self {
+            PipedPayload::RawRgba | PipedPayload::RawRgb => "raw",
+            PipedPayload::Qoi => "qoi",
+            PipedPayload::Png => "png",
+        }
+    }
+
+    /// Whether frames carry an alpha channel, given whether the source has one.
+    pub fn keeps_alpha(self, has_alpha: bool) -> bool {
+        match self {
+            PipedPayload::RawRgba => true,
+            PipedPayload::RawRgb => false,
+            PipedPayload::Qoi | PipedPayload::Png => has_alpha,
+        }
+    }
+}
 
-use crate::Converter;
+/// Destination of the piped stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipedOutput {
+    Stdout,
+    /// Regular file or named pipe. The pipe must already exist.
+    File(PathBuf),
+    /// Unix domain socket the consumer is listening on.
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
+}
+
+impl PipedOutput {
+    /// Parses an output path: `-` is stdout, `unix:<path>` a Unix domain socket,
+    /// anything else a file or named pipe.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        if path.as_os_str() == "-" {
+            return PipedOutput::Stdout;
+        }
+        #[cfg(unix)]
+        if let Some(socket) = path.to_str().and_then(|p| p.strip_prefix("unix:")) {
+            return PipedOutput::UnixSocket(PathBuf::from(socket));
+        }
+        PipedOutput::File(path.to_path_buf())
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Write>> {
+        let out: Box<dyn Write> = match self {
+            PipedOutput::Stdout => Box::new(std::io::stdout()),
+            PipedOutput::File(path) => {
+                // pipes are never created here, opening one blocks until a reader connects
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(!is_named_pipe(path))
+                    .truncate(true)
+                    .open(path)
+                    .map_err(|e| Error::io(path, e))?;
+                Box::new(file)
+            }
+            #[cfg(unix)]
+            PipedOutput::UnixSocket(path) => {
+                let socket = std::os::unix::net::UnixStream::connect(path)
+                    .map_err(|e| Error::io(path, e))?;
+                Box::new(socket)
+            }
+        };
+        Ok(Box::new(BufWriter::new(out)))
+    }
+}
+
+#[cfg(unix)]
+fn is_named_pipe(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path)
+        .map(|m| m.file_type().is_fifo())
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn is_named_pipe(path: &Path) -> bool {
+    path.to_string_lossy().starts_with(r"\\.\pipe\")
+}
 
 pub struct PipedPngConverter {
-    fps: f32,
+    target: PipedOutput,
+    /// Opened in `prepare` unless a writer was supplied up front.
+    out: RefCell<Option<Box<dyn Write>>>,
+    rate: Option<FrameRate>,
     width: u32,
     height: u32,
+    payload: PipedPayload,
+    has_alpha: bool,
+    frame_count: Option<u32>,
+    source_name: Option<String>,
+    frames_written: u32,
     header_written: bool,
 }
 
 impl PipedPngConverter {
-    pub fn new(_dummy_out: impl AsRef<Path>) -> Self {
+    /// Streams to the destination named by `path`, see [`PipedOutput::from_path`].
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::for_output(PipedOutput::from_path(path))
+    }
+
+    pub fn for_output(target: PipedOutput) -> Self {
         Self {
-            fps: 0.0,
+            target,
+            out: RefCell::new(None),
+            rate: None,
             width: 0,
             height: 0,
+            payload: PipedPayload::default(),
+            has_alpha: true,
+            frame_count: None,
+            source_name: None,
+            frames_written: 0,
             header_written: false,
         }
     }
 
+    /// Streams into an arbitrary `writer`.
+    pub fn from_writer(writer: impl Write + 'static) -> Self {
+        let cvt = Self::for_output(PipedOutput::Stdout);
+        *cvt.out.borrow_mut() = Some(Box::new(writer));
+        cvt
+    }
+
+    pub fn with_payload(mut self, payload: PipedPayload) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Whether the source carries meaningful alpha. Opaque sources are sent as RGB
+    /// when the payload allows it.
+    pub fn with_alpha(mut self, has_alpha: bool) -> Self {
+        self.has_alpha = has_alpha;
+        self
+    }
+
+    /// Expected number of frames, announced as `META FRAMES`.
+    pub fn with_frame_count(mut self, frame_count: u32) -> Self {
+        self.frame_count = Some(frame_count);
+        self
+    }
+
+    /// Name of the input, announced as `META SOURCE`.
+    pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+        self.source_name = Some(name.into());
+        self
+    }
+
     #[inline]
     fn write_header_if_needed(&mut self) -> Result<()> {
         if self.header_written {
             return Ok(());
         }
-        let (num, den) = fps_to_rational(self.fps);
-        let mut out = std::io::stdout().lock();
+        let rate = self
+            .rate
+            .ok_or_else(|| anyhow!("Piped stream not prepared"))?;
+        let keeps_alpha = self.payload.keeps_alpha(self.has_alpha);
+        let mut out = self.out.borrow_mut();
+        let out = out.as_mut().ok_or_else(|| anyhow!("Piped stream not opened"))?;
         // container header（y4m-like）
-        // PNG payload
-        writeln!(out, "PXY4M W{} H{} F{}/{} Crgba Enc:png", self.width, self.height, num, den)?;
+        writeln!(
+            out,
+            "PXY4M V{} W{} H{} F{}/{} C{} Enc:{}",
+            PIPED_PROTOCOL_VERSION,
+            self.width,
+            self.height,
+            rate.num(),
+            rate.den(),
+            if keeps_alpha { "rgba" } else { "rgb" },
+            self.payload.encoding_name()
+        )?;
+        // metadata, terminated by the first FRAME or END line
+        writeln!(out, "META ALPHA {}", self.has_alpha as u8)?;
+        if let Some(frame_count) = self.frame_count {
+            writeln!(out, "META FRAMES {}", frame_count)?;
+        }
+        if let Some(name) = &self.source_name {
+            // keep the record on a single line
+            let name = name.replace(['\r', '\n'], " ");
+            writeln!(out, "META SOURCE {}", name)?;
+        }
         out.flush()?;
         self.header_written = true;
         Ok(())
     }
-}
 
+    fn encode_payload(&self, image: RgbaImage) -> Result<Vec<u8>> {
+        let keeps_alpha = self.payload.keeps_alpha(self.has_alpha);
+        let (pixels, color) = if keeps_alpha {
+            (image.into_raw(), ExtendedColorType::Rgba8)
+        } else {
+            (rgba_to_rgb(image), ExtendedColorType::Rgb8)
+        };
+
+        let buf = match self.payload {
+            PipedPayload::RawRgba | PipedPayload::RawRgb => pixels,
+            PipedPayload::Qoi => {
+                let mut buf = Vec::with_capacity(pixels.len() / 2);
+                QoiEncoder::new(&mut buf).write_image(&pixels, self.width, self.height, color)?;
+                buf
+            }
+            PipedPayload::Png => {
+                let mut buf = Vec::with_capacity(pixels.len() / 2);
+                PngEncoder::new(&mut buf).write_image(&pixels, self.width, self.height, color)?;
+                buf
+            }
+        };
+        Ok(buf)
+    }
+}
 
 impl Converter for PipedPngConverter {
-    fn prepare(&mut self, width: u32, height: u32, fps: f32) -> Result<()> {
+    fn prepare(&mut self, width: u32, height: u32, rate: FrameRate) -> Result<()> {
         self.width = width;
         self.height = height;
-        self.fps = fps;
+        self.rate = Some(rate);
+        let out = self.out.get_mut();
+        if out.is_none() {
+            *out = Some(self.target.open()?);
+        }
         self.write_header_if_needed()
     }
 
@@ -53,50 +261,34 @@ impl Converter for PipedPngConverter {
         if image.width() != self.width || image.height() != self.height {
             return Err(anyhow!(
                 "frame size mismatch: expected {}x{}, got {}x{}",
-                self.width, self.height, image.width(), image.height()
+                self.width,
+                self.height,
+                image.width(),
+                image.height()
             ));
         }
 
-        let png_buf = Vec::with_capacity((self.width * self.height * 4) as usize);
-        // image to png
-        let mut png_buf = png_buf;
-        let encoder = PngEncoder::new(&mut png_buf);
-        encoder.write_image(&image.into_raw(), self.width, self.height, ExtendedColorType::Rgba8)?;
+        let payload = self.encode_payload(image)?;
 
-        // write the frame header + PNG
-        let mut out = std::io::stdout().lock();
+        // write the frame header + payload
+        let mut out = self.out.borrow_mut();
+        let out = out.as_mut().ok_or_else(|| anyhow!("Piped stream not prepared"))?;
         writeln!(out, "FRAME")?;
         writeln!(out, "PTS {}", frame_id)?;
-        writeln!(out, "BYTES {}", png_buf.len())?;
-        out.write_all(&png_buf)?;
-        out.flush()?; 
+        writeln!(out, "BYTES {}", payload.len())?;
+        out.write_all(&payload)?;
+        out.flush()?;
+        self.frames_written += 1;
 
         Ok(())
     }
 
     fn finish(&self) -> Result<()> {
-        let mut out = std::io::stdout();
+        // the trailer tells a finished stream apart from a truncated one
+        let mut out = self.out.borrow_mut();
+        let out = out.as_mut().ok_or_else(|| anyhow!("Piped stream not prepared"))?;
+        writeln!(out, "END FRAMES {}", self.frames_written)?;
         out.flush()?;
         Ok(())
     }
 }
-
-
-fn fps_to_rational(fps: f32) -> (u32, u32) {
-    if (fps.fract()).abs() < 1e-6 {
-        return (fps.round() as u32, 1);
-    }
-    let den = 1000u32;
-    let num = (fps * den as f32).round() as u32;
-    let g = gcd_u32(num, den);
-    (num / g, den / g)
-}
-
-fn gcd_u32(mut a: u32, mut b: u32) -> u32 {
-    while b != 0 {
-        let r = a % b;
-        a = b;
-        b = r;
-    }
-    a.max(1)
-}
@@ -1,22 +1,32 @@
 use anyhow::Result;
+use image::DynamicImage;
 use std::path::{Path, PathBuf};
 
 use super::Converter;
+use crate::frame_rate::FrameRate;
 
 pub struct PngConverter {
     path: PathBuf,
+    grayscale: Option<bool>,
 }
 
 impl PngConverter {
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
+            grayscale: None,
         }
     }
+
+    /// Save frames as grayscale, with an alpha channel if `keep_alpha` is set.
+    pub fn with_grayscale(mut self, keep_alpha: bool) -> Self {
+        self.grayscale = Some(keep_alpha);
+        self
+    }
 }
 
 impl Converter for PngConverter {
-    fn prepare(&mut self, _width: u32, _height: u32, _fps: f32) -> Result<()> {
+    fn prepare(&mut self, _width: u32, _height: u32, _rate: FrameRate) -> Result<()> {
         if !self.path.exists() {
             std::fs::create_dir_all(&self.path)?;
         }
@@ -24,12 +34,18 @@ impl Converter for PngConverter {
     }
 
     fn convert_frame(&mut self, image: image::RgbaImage, frame_id: u32) -> Result<()> {
-        let path = self.path.with_file_name(format!("frame_{:04}.png", frame_id));
-        image.save(path)?;
+        let path = self.path.join(format!("frame_{:04}.png", frame_id));
+        match self.grayscale {
+            Some(false) => DynamicImage::ImageRgba8(image).into_luma8().save(path)?,
+            Some(true) => DynamicImage::ImageRgba8(image)
+                .into_luma_alpha8()
+                .save(path)?,
+            None => image.save(path)?,
+        }
         Ok(())
     }
 
     fn finish(&self) -> Result<()> {
         Ok(())
     }
-}
\ No newline at end of file
+}
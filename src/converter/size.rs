@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+/// What to do with a frame size the encoder cannot take.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizePolicy {
+    /// Repeat the last row or column.
+    #[default]
+    Pad,
+    /// Drop the last row or column.
+    Crop,
+    /// Refuse to encode.
+    Error,
+}
+
+/// The size an encoder works at, next to the size frames arrive in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FittedSize {
+    pub width: u32,
+    pub height: u32,
+    pub source_width: u32,
+    pub source_height: u32,
+}
+
+impl FittedSize {
+    /// Rounds `width` x `height` to multiples of `align` under `policy`.
+    pub fn new(
+        width: u32,
+        height: u32,
+        align: u32,
+        policy: SizePolicy,
+        encoder: &str,
+    ) -> Result<Self> {
+        let fit = |side: u32| match policy {
+            SizePolicy::Pad => side.div_ceil(align) * align,
+            SizePolicy::Crop | SizePolicy::Error => side / align * align,
+        };
+        let size = Self {
+            width: fit(width),
+            height: fit(height),
+            source_width: width,
+            source_height: height,
+        };
+        if size.width == 0 || size.height == 0 {
+            return Err(anyhow!(
+                "{} cannot encode {}x{} frames",
+                encoder,
+                width,
+                height
+            ));
+        }
+        if size.is_adjusted() {
+            if policy == SizePolicy::Error {
+                return Err(anyhow!(
+                    "{} needs a width and height divisible by {}, frames are {}x{}; use --odd-size pad or crop, or a scale/crop filter",
+                    encoder,
+                    align,
+                    width,
+                    height
+                ));
+            }
+            log::info!(
+                "{} needs sizes divisible by {}, {:?} {}x{} to {}x{}",
+                encoder,
+                align,
+                policy,
+                width,
+                height,
+                size.width,
+                size.height
+            );
+        }
+        Ok(size)
+    }
+
+    pub fn is_adjusted(&self) -> bool {
+        (self.width, self.height) != (self.source_width, self.source_height)
+    }
+
+    /// Value for the container metadata recording the size before fitting.
+    pub fn original_size_tag(&self) -> String {
+        format!("original_size={}x{}", self.source_width, self.source_height)
+    }
+
+    /// Pads or crops `image` to the encoder size.
+    pub fn fit(&self, image: RgbaImage, frame_id: u32) -> Result<RgbaImage> {
+        if image.dimensions() != (self.source_width, self.source_height) {
+            return Err(anyhow!(
+                "frame {} is {}x{}, the encoder was prepared for {}x{}",
+                frame_id,
+                image.width(),
+                image.height(),
+                self.source_width,
+                self.source_height
+            ));
+        }
+        if !self.is_adjusted() {
+            return Ok(image);
+        }
+        let mut fitted = RgbaImage::new(self.width, self.height);
+        for (x, y, pixel) in fitted.enumerate_pixels_mut() {
+            // clamping repeats the edge when padding and drops it when cropping
+            let sx = x.min(self.source_width - 1);
+            let sy = y.min(self.source_height - 1);
+            *pixel = *image.get_pixel(sx, sy);
+        }
+        Ok(fitted)
+    }
+}
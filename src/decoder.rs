@@ -0,0 +1,1156 @@
+use anyhow::{anyhow, Result};
+use binrw::BinRead;
+use image::RgbaImage;
+use kmpsearch::Haystack;
+use serde::{Deserialize, Serialize};
+use std::alloc::Layout;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use theorafile_rs::*;
+
+use crate::error::Error;
+use crate::frame_rate::FrameRate;
+use crate::ogg;
+use crate::pack::{AlphaBands, OmvHeader, OmvHeaderV1};
+use crate::seek::KeyframeIndex;
+
+/// Reads the header of the OMV file `source` with the layout of its version.
+///
+/// A version without a known layout is read as version 1 with a warning, unless
+/// the Ogg payload starts inside such a header.
+pub fn read_omv_header(source: &[u8]) -> Result<OmvHeader> {
+    let (major, minor) = (source.get(4).copied(), source.get(5).copied());
+    if let (Some(major), Some(minor)) = (major, minor) {
+        if !OmvHeader::KNOWN_VERSIONS.contains(&major) {
+            let payload = find_payload(source).ok();
+            if let Some(offset) = payload.filter(|&offset| offset < OmvHeaderV1::SIZE) {
+                return Err(Error::InvalidHeader(format!(
+                    "unsupported header version {}.{}, the Ogg payload starts at {:#x}",
+                    major, minor, offset
+                ))
+                .into());
+            }
+            log::warn!(
+                "unknown header version {}.{}, reading it as version 1",
+                major,
+                minor
+            );
+        }
+    }
+    let mut source = std::io::Cursor::new(source);
+    let header = OmvHeaderV1::read(&mut source)
+        .map(OmvHeader::V1)
+        .map_err(|e| {
+            let reason = if e.is_eof() {
+                "the file ends inside the header".to_string()
+            } else {
+                e.root_cause().to_string()
+            };
+            Error::InvalidHeader(reason)
+        })?;
+    Ok(header)
+}
+
+/// Offset of the embedded Ogg payload, the first capture pattern in the file.
+pub fn find_payload(mem: &[u8]) -> Result<usize> {
+    let res = mem.indexesof_needle(b"OggS").ok_or(Error::NoOggPayload)?;
+    let first_index = res.first().ok_or(Error::NoOggPayload)?.to_owned();
+    Ok(first_index)
+}
+
+/// The embedded Ogg payload, byte for byte as it sits in the OMV file.
+#[derive(Debug, Clone)]
+pub struct OggPayload<'a> {
+    /// File offset of the first capture pattern.
+    pub offset: usize,
+    /// Everything from `offset` to the end of the file.
+    pub data: &'a [u8],
+    /// Number of complete pages with a valid checksum.
+    pub pages: usize,
+    /// End of the last valid page, relative to `offset`.
+    pub pages_end: usize,
+    /// Damaged pages and garbage between pages, relative to `offset`.
+    pub damage: Vec<ogg::Damage>,
+}
+
+impl<'a> OggPayload<'a> {
+    /// Locates and scans the Ogg payload of `mem`.
+    pub fn locate(mem: &'a [u8]) -> Result<Self> {
+        let offset = find_payload(mem)?;
+        let data = &mem[offset..];
+        let mut payload = Self {
+            offset,
+            data,
+            pages: 0,
+            pages_end: 0,
+            damage: Vec::new(),
+        };
+        for item in ogg::PageScanner::new(data) {
+            match item {
+                ogg::ScanItem::Page(page) => {
+                    payload.pages += 1;
+                    payload.pages_end = page.offset + page.len();
+                }
+                ogg::ScanItem::Damaged(damage) => payload.damage.push(damage),
+                ogg::ScanItem::Trailing { .. } => {}
+            }
+        }
+        if payload.pages == 0 {
+            return Err(Error::CorruptStream(format!(
+                "no complete Ogg page at file offset {:#x}",
+                offset
+            ))
+            .into());
+        }
+        Ok(payload)
+    }
+
+    /// Bytes after the last valid page, such as padding or a truncated page.
+    pub fn trailing(&self) -> usize {
+        self.data.len() - self.pages_end
+    }
+
+    /// The payload up to the end of the last valid page.
+    pub fn trimmed(&self) -> &'a [u8] {
+        &self.data[..self.pages_end]
+    }
+}
+
+/// What to do with a frame or page that cannot be decoded.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnError {
+    /// Drop the frame and go on.
+    Skip,
+    /// Abort the conversion.
+    #[default]
+    Stop,
+    /// Emit the previous frame again, keeping the frame count and timing.
+    RepeatLast,
+}
+
+/// How the frames carry alpha.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlphaMode {
+    /// Tell from the header height and the video height.
+    #[default]
+    Auto,
+    /// Opaque frames of the full video height.
+    None,
+    /// 32bit shader video, alpha in three bands below the colour rows.
+    SiglusBands,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    pub on_error: OnError,
+    pub alpha_mode: AlphaMode,
+    /// Serial number of the Theora stream to decode. By default the first one
+    /// of every link of a chained payload is decoded, one after the other.
+    pub serial: Option<u32>,
+    /// Keep the decoded planes in every [`Frame`].
+    pub raw_planes: bool,
+}
+
+/// Parses a stream serial number, decimal or `0x` hexadecimal.
+pub fn parse_serial(s: &str) -> Result<u32> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| anyhow!("invalid stream serial '{}'", s))
+}
+
+/// Geometry and timing of the decoded frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoInfo {
+    /// Size of the reconstructed frames.
+    pub width: u32,
+    pub height: u32,
+    /// Height of the Theora picture, including the alpha bands of shader videos.
+    pub video_height: u32,
+    /// Exact rate from the Theora identification header.
+    pub frame_rate: FrameRate,
+    /// 32bit 'shader video' with alpha packed below the colour planes.
+    pub has_alpha: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Output index, counting repeated frames.
+    pub index: u32,
+    pub image: RgbaImage,
+    /// The planes `image` was reconstructed from, with [`DecodeOptions::raw_planes`].
+    pub planes: Option<RawPlanes>,
+}
+
+/// The 4:4:4 picture theorafile decodes, before [`reconstruct_frame`] puts colour and
+/// alpha together: the Y, Cb and Cr planes one after the other, each `width` by
+/// `height` samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawPlanes {
+    pub width: u32,
+    /// Height of the Theora picture, alpha bands included.
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RawPlanes {
+    /// Plane `index`: 0 for Y, 1 for Cb, 2 for Cr.
+    pub fn plane(&self, index: usize) -> &[u8] {
+        let len = self.width as usize * self.height as usize;
+        &self.data[index * len..][..len]
+    }
+}
+
+/// A decoded frame waiting to be read.
+#[derive(Clone)]
+struct Picture {
+    image: RgbaImage,
+    planes: Option<RawPlanes>,
+}
+
+pub struct DataSource {
+    data: Vec<u8>,
+    pos: usize,
+    /// End of the Ogg page `pos` is in.
+    page_end: usize,
+}
+
+impl DataSource {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            pos: 0,
+            page_end: 0,
+        }
+    }
+
+    // Implement a method to seek to a specific position
+    pub fn seek(
+        &mut self,
+        offset: ogg_int64_t,
+        origin: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int {
+        let base = match origin {
+            0 => 0,
+            1 => self.pos as ogg_int64_t,
+            2 => self.data.len() as ogg_int64_t,
+            _ => return -1, // Unsupported origin
+        };
+        // Ensure pos doesn't go out of bounds
+        self.pos = base
+            .saturating_add(offset)
+            .clamp(0, self.data.len() as ogg_int64_t) as usize;
+        self.page_end = 0;
+        0 // Success
+    }
+
+    // Implement a method to read data from the current position
+    //
+    // Reads stop at the end of a page. theorafile stops reading once it has the
+    // headers and leaves any further pages it read unparsed; should the data end
+    // there, it reports the end of the stream before decoding them.
+    pub fn read(&mut self, ptr: *mut ::std::os::raw::c_void, size: usize, nmemb: usize) -> usize {
+        if self.pos >= self.page_end {
+            self.page_end = ogg::parse_page_unchecked(&self.data, self.pos)
+                .map_or(self.data.len(), |page| self.pos + page.len());
+        }
+        let bytes_to_read = size.saturating_mul(nmemb);
+        let remaining_data = &self.data[self.pos..self.page_end.min(self.data.len())];
+        let bytes_read = std::cmp::min(remaining_data.len(), bytes_to_read);
+        unsafe {
+            std::ptr::copy_nonoverlapping(remaining_data.as_ptr(), ptr as *mut u8, bytes_read);
+        }
+        self.pos += bytes_read;
+        bytes_read
+    }
+
+    // Implement a method to close the data source
+    pub fn close(&mut self) -> ::std::os::raw::c_int {
+        // Optionally perform any cleanup here
+        0 // Success
+    }
+}
+
+unsafe extern "C" fn read_func_impl(
+    ptr: *mut ::std::os::raw::c_void,
+    size: usize,
+    nmemb: usize,
+    datasource: *mut ::std::os::raw::c_void,
+) -> usize {
+    if let Some(datasource) = (datasource as *mut DataSource).as_mut() {
+        datasource.read(ptr, size, nmemb)
+    } else {
+        0
+    }
+}
+
+unsafe extern "C" fn seek_func_impl(
+    datasource: *mut ::std::os::raw::c_void,
+    offset: ogg_int64_t,
+    origin: ::std::os::raw::c_int,
+) -> ::std::os::raw::c_int {
+    if let Some(datasource) = (datasource as *mut DataSource).as_mut() {
+        datasource.seek(offset, origin)
+    } else {
+        -1
+    }
+}
+
+unsafe extern "C" fn close_func_impl(
+    datasource: *mut ::std::os::raw::c_void,
+) -> ::std::os::raw::c_int {
+    if let Some(datasource) = (datasource as *mut DataSource).as_mut() {
+        datasource.close()
+    } else {
+        -1
+    }
+}
+
+/// Checks that a `height` row frame fits in the `video_height` rows of the
+/// Theora picture, alpha bands included.
+pub fn check_geometry(height: u32, video_height: u32, has_alpha: bool) -> Result<()> {
+    let (h, vh) = (height as u64, video_height as u64);
+    if h == 0 || h > vh {
+        return Err(Error::InvalidGeometry(format!(
+            "frame height {} does not fit video height {}",
+            height, video_height
+        ))
+        .into());
+    }
+    // the three alpha bands take a third of the frame height each
+    if has_alpha && 4 * h > 3 * vh {
+        return Err(Error::InvalidGeometry(format!(
+            "video height {} leaves no room for the alpha bands of a {} row frame",
+            video_height, height
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Rows of the alpha band below the colour rows of each plane of a shader video
+/// with `height` row frames.
+pub fn alpha_band_rows(height: u32) -> u32 {
+    height.div_ceil(3)
+}
+
+/// Decides whether the frames carry alpha, from the frame height in the header
+/// and the height of the Theora picture. Returns the frame height and whether
+/// there are alpha bands.
+///
+/// In `Auto` mode a picture that matches neither an opaque nor a shader video
+/// is an error rather than a guess.
+pub fn detect_alpha(mode: AlphaMode, height: u32, video_height: u32) -> Result<(u32, bool)> {
+    let expected = height.saturating_add(alpha_band_rows(height));
+    match mode {
+        AlphaMode::None => {
+            if height != video_height {
+                log::info!(
+                    "alpha mode none: decoding all {} video rows, the header says {}",
+                    video_height,
+                    height
+                );
+            }
+            check_geometry(video_height, video_height, false)?;
+            Ok((video_height, false))
+        }
+        AlphaMode::SiglusBands => {
+            check_geometry(height, video_height, true)?;
+            if video_height != expected {
+                log::warn!(
+                    "video height {} is not {} rows plus alpha bands ({}), the alpha channel may be misaligned",
+                    video_height,
+                    height,
+                    expected
+                );
+            }
+            log::info!("alpha mode siglus-bands: {} rows with alpha", height);
+            Ok((height, true))
+        }
+        AlphaMode::Auto if height == video_height => {
+            check_geometry(height, video_height, false)?;
+            log::info!("video height {} matches the header: no alpha", video_height);
+            Ok((height, false))
+        }
+        // the bands only line up when each plane ends right below its band
+        AlphaMode::Auto if height > 0 && video_height == expected => {
+            log::info!(
+                "video height {} is 4/3 of the header height {}: alpha bands",
+                video_height,
+                height
+            );
+            Ok((height, true))
+        }
+        AlphaMode::Auto => Err(Error::InvalidGeometry(format!(
+            "video height {} is neither the header height {} nor that plus alpha bands ({}), \
+             force a layout with --alpha-mode",
+            video_height, height, expected
+        ))
+        .into()),
+    }
+}
+
+/// Name of a Theora pixel format, for messages.
+pub(crate) fn pixel_format_name(fmt: th_pixel_fmt) -> String {
+    let name = if fmt == th_pixel_fmt_TH_PF_420 {
+        "4:2:0"
+    } else if fmt == th_pixel_fmt_TH_PF_422 {
+        "4:2:2"
+    } else if fmt == th_pixel_fmt_TH_PF_444 {
+        "4:4:4"
+    } else {
+        return format!("#{}", fmt);
+    };
+    name.to_string()
+}
+
+/// Rebuilds an RGBA frame from the three planes theorafile hands out.
+///
+/// `data` holds three `width * video_height` planes (B, G, R). For shader videos
+/// the alpha channel sits in three bands below the colour rows, one per plane.
+pub fn yuv_to_image(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    video_height: u32,
+    is24bit: bool,
+) -> Result<RgbaImage> {
+    let bands = (!is24bit).then_some(AlphaBands::PerPlane);
+    reconstruct_frame(data, width, height, video_height, bands)
+}
+
+/// Like [`yuv_to_image`], with the alpha layout of a given header version, or
+/// `None` for opaque frames.
+pub fn reconstruct_frame(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    video_height: u32,
+    bands: Option<AlphaBands>,
+) -> Result<RgbaImage> {
+    let (w, h, vh) = (width as usize, height as usize, video_height as usize);
+    if data.len() < w * vh * 3 {
+        return Err(Error::InvalidGeometry(format!(
+            "frame buffer holds {} bytes, {}x{} planes need {}",
+            data.len(),
+            width,
+            video_height,
+            w * vh * 3
+        ))
+        .into());
+    }
+    check_geometry(height, video_height, bands.is_some())?;
+
+    // for (int y = 0; y < height; y++)
+    // {
+    //     for (int x = 0; x < width; x++)
+    //     {
+    //         buf[offset + data.Stride * y + 4 * x + 0] = vidbuf[vidwidth * (vidheight * 0 + y) + x];
+    //         buf[offset + data.Stride * y + 4 * x + 1] = vidbuf[vidwidth * (vidheight * 1 + y) + x];
+    //         buf[offset + data.Stride * y + 4 * x + 2] = vidbuf[vidwidth * (vidheight * 2 + y) + x];
+    //         if (y < (height + 2) / 3)
+    //         {
+    //             buf[offset + data.Stride * y + 4 * x + 3] = vidbuf[vidwidth * (height * 1 + y) + x];
+    //         }
+    //         else if (y < (height + 2) / 3 * 2)
+    //         {
+    //             buf[offset + data.Stride * y + 4 * x + 3] = vidbuf[vidwidth * (height * 2 + y) + x];
+    //         }
+    //         else
+    //         {
+    //             buf[offset + data.Stride * y + 4 * x + 3] = vidbuf[vidwidth * (height * 3 + y) + x];
+    //         }
+    //     }
+    // }
+
+    let mut pixels = Vec::with_capacity(w * h * 4);
+    let band = h.div_ceil(3);
+    #[allow(clippy::erasing_op)]
+    #[allow(clippy::identity_op)]
+    for y in 0..h {
+        let b = &data[w * (vh * 0 + y)..][..w];
+        let g = &data[w * (vh * 1 + y)..][..w];
+        let r = &data[w * (vh * 2 + y)..][..w];
+        let a = match bands {
+            None => None,
+            Some(AlphaBands::PerPlane) if y < band => Some(&data[w * (h * 1 + y)..][..w]),
+            Some(AlphaBands::PerPlane) if y < band * 2 => Some(&data[w * (h * 2 + y)..][..w]),
+            Some(AlphaBands::PerPlane) => Some(&data[w * (h * 3 + y)..][..w]),
+        };
+
+        for x in 0..w {
+            let a = a.map_or(0xff, |a| a[x]);
+            pixels.extend_from_slice(&[r[x], g[x], b[x], a]);
+        }
+    }
+    Ok(RgbaImage::from_raw(width, height, pixels).unwrap())
+}
+
+/// Decodes the frames of an OMV file held in memory.
+pub struct OmvReader {
+    header: OmvHeader,
+    info: VideoInfo,
+    options: DecodeOptions,
+    payload_offset: usize,
+    repaired: ogg::Repaired,
+    streams: Vec<ogg::LogicalStream>,
+    /// The Theora streams to decode, one after the other.
+    selected: Vec<ogg::LogicalStream>,
+    /// The pages of every selected stream, extracted when it is first opened.
+    extracted: Vec<Option<Rc<ExtractedStream>>>,
+    /// Index of the stream `segment` decodes in `selected`.
+    current: usize,
+    segment: Segment,
+    index: Option<KeyframeIndex>,
+    buffer: Vec<u8>,
+    last_picture: Option<Picture>,
+    pending: VecDeque<Picture>,
+    frames_read: u32,
+    finished: bool,
+}
+
+impl OmvReader {
+    /// Parses the header of `mem` and opens the embedded Theora stream.
+    pub fn open(mem: &[u8], options: DecodeOptions) -> Result<Self> {
+        let header = read_omv_header(mem)?;
+        log::info!("extracting {:?}", header);
+        let first_index = find_payload(mem)?;
+        Self::from_payload(header, &mem[first_index..], first_index, options)
+    }
+
+    /// Opens `payload`, the Ogg data found at `payload_offset` of an OMV file.
+    pub fn from_payload(
+        header: OmvHeader,
+        payload: &[u8],
+        payload_offset: usize,
+        options: DecodeOptions,
+    ) -> Result<Self> {
+        let repaired = repair_payload(payload, payload_offset, options.on_error)?;
+        let streams = ogg::logical_streams(ogg::valid_pages(&repaired.data));
+        for stream in &streams {
+            log::info!(
+                "stream {:08x} (link {}): {}, {} page(s)",
+                stream.serial,
+                stream.link,
+                stream.codec.name(),
+                stream.pages
+            );
+        }
+        let selected = select_streams(&streams, options.serial)?;
+        let first = selected.first().ok_or(Error::NoVideoStream)?;
+        let mut extracted = vec![None; selected.len()];
+        let stream = ExtractedStream::new(&repaired, first);
+        extracted[0] = Some(stream.clone());
+        let segment = Segment::open(stream, first.serial)?;
+        let (width, height, fps, fmt) = segment.video_info()?;
+        if fmt != th_pixel_fmt_TH_PF_444 {
+            return Err(Error::UnsupportedPixelFormat(pixel_format_name(fmt)).into());
+        }
+        if width <= 0 || height <= 0 {
+            return Err(Error::InvalidGeometry(format!("video size {}x{}", width, height)).into());
+        }
+        let frame_rate = segment.frame_rate(fps)?;
+
+        let video_height = height as u32;
+        let (height, has_alpha) =
+            detect_alpha(options.alpha_mode, header.metadata().height, video_height)?;
+
+        let info = VideoInfo {
+            width: width as u32,
+            height,
+            video_height,
+            frame_rate,
+            has_alpha,
+        };
+        log::info!(
+            "width: {}, height: {}, fps: {} ({:.3}), fmt: {}",
+            width,
+            height,
+            frame_rate,
+            frame_rate.as_f64(),
+            fmt
+        );
+
+        let size = width as usize * video_height as usize * 3;
+        Ok(Self {
+            header,
+            info,
+            options,
+            payload_offset,
+            repaired,
+            streams,
+            selected,
+            extracted,
+            current: 0,
+            segment,
+            index: None,
+            buffer: vec![0u8; size],
+            last_picture: None,
+            pending: VecDeque::new(),
+            frames_read: 0,
+            finished: false,
+        })
+    }
+
+    pub fn header(&self) -> &OmvHeader {
+        &self.header
+    }
+
+    pub fn info(&self) -> &VideoInfo {
+        &self.info
+    }
+
+    /// Every logical stream of the payload, whether it is decoded or not.
+    pub fn streams(&self) -> &[ogg::LogicalStream] {
+        &self.streams
+    }
+
+    /// Number of frames returned so far.
+    pub fn frames_read(&self) -> u32 {
+        self.frames_read
+    }
+
+    /// Returns the next frame, or `None` at the end of the stream.
+    pub fn read_frame(&mut self) -> Result<Option<Frame>> {
+        while self.pending.is_empty() {
+            if self.finished || !self.decode_packet()? {
+                self.finish();
+                return Ok(None);
+            }
+        }
+
+        let picture = self.pending.pop_front().unwrap();
+        let frame = Frame {
+            index: self.frames_read,
+            image: picture.image,
+            planes: picture.planes,
+        };
+        self.frames_read += 1;
+        log::trace!("Decoded {} frame(s)", self.frames_read);
+        Ok(Some(frame))
+    }
+
+    /// Feeds one packet to the decoder, queueing the frames it yields. Returns
+    /// `false` once no packet is left.
+    fn decode_packet(&mut self) -> Result<bool> {
+        if unsafe { tf_eos(self.segment.file) } != 0 {
+            return self.next_segment();
+        }
+        let segment = &mut self.segment;
+        let ret = unsafe {
+            tf_readvideo(
+                segment.file,
+                self.buffer.as_mut_ptr() as *mut ::std::os::raw::c_char,
+                1,
+            )
+        };
+        let packet = segment
+            .stream
+            .theora
+            .as_ref()
+            .and_then(|s| s.packets.get(segment.packet_index))
+            .cloned();
+        segment.packet_index += 1;
+
+        if ret == 0 && unsafe { tf_eos(segment.file) } != 0 {
+            // no packet left in this stream
+            return self.next_segment();
+        }
+
+        // index of the next frame to be emitted, for messages
+        let index = self.frames_read as usize + self.pending.len();
+
+        // frames whose pages were dropped
+        let lost = packet
+            .as_ref()
+            .map_or(0, |p| p.frame.saturating_sub(self.segment.next_frame));
+        if lost > 0 {
+            log::warn!("{} frame(s) lost before frame {}", lost, index);
+            if self.options.on_error == OnError::RepeatLast {
+                if let Some(picture) = &self.last_picture {
+                    for _ in 0..lost {
+                        self.pending.push_back(picture.clone());
+                    }
+                }
+            }
+        }
+        if let Some(packet) = &packet {
+            self.segment.next_frame = packet.frame + 1;
+        }
+        let index = self.frames_read as usize + self.pending.len();
+
+        let picture = if ret != 0 {
+            self.picture()?
+        } else {
+            // an empty packet is a legitimate duplicate of the previous frame
+            let duplicate = packet.as_ref().is_some_and(|p| p.size == 0);
+            if !duplicate {
+                let offset = self.file_offset(&self.segment, packet.as_ref());
+                let message = format!(
+                    "failed to decode frame {} (packet at file offset {})",
+                    index, offset
+                );
+                match self.options.on_error {
+                    OnError::Stop => return Err(Error::CorruptStream(message).into()),
+                    OnError::Skip => {
+                        log::warn!("{}, skipping it", message);
+                        return Ok(true);
+                    }
+                    OnError::RepeatLast => log::warn!("{}, repeating the last frame", message),
+                }
+            }
+            match &self.last_picture {
+                Some(picture) => picture.clone(),
+                None => {
+                    log::warn!("no previous frame to repeat for frame {}", index);
+                    return Ok(true);
+                }
+            }
+        };
+
+        self.pending.push_back(picture.clone());
+        self.last_picture = Some(picture);
+        Ok(true)
+    }
+
+    /// The frame in the decode buffer.
+    fn picture(&self) -> Result<Picture> {
+        let image = reconstruct_frame(
+            &self.buffer,
+            self.info.width,
+            self.info.height,
+            self.info.video_height,
+            self.info.has_alpha.then(|| self.header.alpha_bands()),
+        )?;
+        let planes = self.options.raw_planes.then(|| RawPlanes {
+            width: self.info.width,
+            height: self.info.video_height,
+            data: self.buffer.clone(),
+        });
+        Ok(Picture { image, planes })
+    }
+
+    /// Where `packet` of `segment` starts in the OMV file, for messages.
+    fn file_offset(&self, segment: &Segment, packet: Option<&ogg::PacketInfo>) -> String {
+        packet.map_or("unknown".to_string(), |p| {
+            format!(
+                "{:#x}",
+                self.payload_offset
+                    + self
+                        .repaired
+                        .source_offset(segment.stream.pages.source_offset(p.page_offset))
+            )
+        })
+    }
+
+    /// The pages of `selected[i]`, extracted on first use.
+    fn extracted_stream(&mut self, i: usize) -> Rc<ExtractedStream> {
+        let (repaired, stream) = (&self.repaired, &self.selected[i]);
+        self.extracted[i]
+            .get_or_insert_with(|| ExtractedStream::new(repaired, stream))
+            .clone()
+    }
+
+    /// Moves on to the next queued stream of a chained payload. Returns `false`
+    /// if there is none.
+    fn next_segment(&mut self) -> Result<bool> {
+        self.check_eos();
+        let Some(next) = self.selected.get(self.current + 1).cloned() else {
+            return Ok(false);
+        };
+        let stream = self.extracted_stream(self.current + 1);
+        let segment = Segment::open(stream, next.serial)?;
+        let (width, video_height, fps, _) = segment.video_info()?;
+        if width as u32 != self.info.width || video_height as u32 != self.info.video_height {
+            return Err(Error::InvalidGeometry(format!(
+                "chained stream {:08x} is {}x{}, the first one {}x{}",
+                next.serial, width, video_height, self.info.width, self.info.video_height
+            ))
+            .into());
+        }
+        let frame_rate = segment.frame_rate(fps)?;
+        if frame_rate != self.info.frame_rate {
+            log::warn!(
+                "chained stream {:08x} runs at {} fps, output keeps {}",
+                next.serial,
+                frame_rate,
+                self.info.frame_rate
+            );
+        }
+        log::info!(
+            "continuing with chained stream {:08x} after {} frame(s)",
+            next.serial,
+            self.frames_read as usize + self.pending.len()
+        );
+        self.segment = segment;
+        self.current += 1;
+        Ok(true)
+    }
+
+    fn check_eos(&self) {
+        if self.segment.stream.theora.as_ref().is_some_and(|s| !s.has_eos) {
+            log::warn!(
+                "the Theora stream {:08x} has no end-of-stream page, the file may be truncated",
+                self.segment.serial
+            );
+        }
+    }
+
+    /// The keyframe index, built on first use.
+    pub fn keyframe_index(&mut self) -> &KeyframeIndex {
+        self.index
+            .get_or_insert_with(|| KeyframeIndex::build(&self.repaired.data, &self.selected))
+    }
+
+    /// Uses `index` for seeking, after checking that it was built for this file.
+    pub fn set_keyframe_index(&mut self, index: KeyframeIndex) -> Result<()> {
+        if !index.matches(&self.repaired.data, &self.selected) {
+            return Err(anyhow!("the keyframe index belongs to another file"));
+        }
+        self.index = Some(index);
+        Ok(())
+    }
+
+    /// Loads the keyframe index from the sidecar at `path`, or builds it and
+    /// writes the sidecar when it is missing or stale.
+    pub fn use_index_sidecar(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        match KeyframeIndex::load(path).and_then(|index| self.set_keyframe_index(index)) {
+            Ok(()) => {
+                log::debug!("using the keyframe index {}", path.display());
+                return Ok(());
+            }
+            Err(e) if path.exists() => log::info!("rebuilding the keyframe index: {:#}", e),
+            Err(_) => {}
+        }
+        self.keyframe_index().save(path)
+    }
+
+    /// Positions the reader so the next [`read_frame`](Self::read_frame) returns
+    /// frame `frame`: decoding restarts at the keyframe before it, and the frames
+    /// in between are decoded but not converted. A packet that fails to decode
+    /// on the way fails the seek, whatever the error policy, and leaves the
+    /// reader where it was.
+    pub fn seek_to_frame(&mut self, frame: u64) -> Result<()> {
+        let index = self.keyframe_index();
+        if frame >= index.frames {
+            return Err(anyhow!(
+                "frame {} is past the end of the video ({} frames)",
+                frame,
+                index.frames
+            ));
+        }
+        let key = *index
+            .keyframe_before(frame)
+            .ok_or_else(|| anyhow!("no keyframe before frame {}", frame))?;
+        let target = frame - index.streams[key.stream].first_frame;
+        let serial = self.selected[key.stream].serial;
+        log::debug!(
+            "seeking to frame {} from the keyframe at frame {} (stream {:08x}, packet {})",
+            frame,
+            key.frame,
+            serial,
+            key.packet
+        );
+
+        let stream = self.extracted_stream(key.stream);
+        let mut segment = Segment::open_at(stream, serial, key.packet)?;
+        let skip = segment.stream.theora.as_ref().map_or(0, |s| {
+            s.packets[segment.packet_index..]
+                .iter()
+                .take_while(|p| p.frame < target)
+                .count()
+        });
+        let picture = segment.skip_packets(&mut self.buffer, skip).map_err(|failed| {
+            let packet = segment.stream.theora.as_ref().and_then(|s| s.packets.get(failed));
+            Error::CorruptStream(format!(
+                "cannot seek to frame {}: the packet at file offset {} fails to decode",
+                frame,
+                self.file_offset(&segment, packet)
+            ))
+        })?;
+        // the picture to repeat should the target be a duplicate
+        self.last_picture = if picture {
+            self.picture().ok()
+        } else {
+            None
+        };
+        self.segment = segment;
+        self.current = key.stream;
+        self.pending.clear();
+        self.frames_read = u32::try_from(frame)?;
+        self.finished = false;
+        Ok(())
+    }
+
+    /// Decodes the frame on screen at `time`, `None` past the end.
+    pub fn frame_at(&mut self, time: Duration) -> Result<Option<Frame>> {
+        let frame = self.info.frame_rate.index_at(time);
+        if frame >= self.keyframe_index().frames {
+            return Ok(None);
+        }
+        self.seek_to_frame(frame)?;
+        self.read_frame()
+    }
+
+    fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        if self.frames_read < self.header.metadata().frame_count {
+            log::warn!(
+                "decoded {} of {} frame(s) announced by the header, the file may be truncated",
+                self.frames_read,
+                self.header.metadata().frame_count
+            );
+        }
+    }
+}
+
+impl Iterator for OmvReader {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.read_frame().transpose();
+        if let Some(Err(_)) = frame {
+            self.finished = true;
+        }
+        frame
+    }
+}
+
+/// The pages of one Theora stream and their layout, extracted once per reader.
+struct ExtractedStream {
+    pages: ogg::Repaired,
+    theora: Option<ogg::TheoraStream>,
+}
+
+impl ExtractedStream {
+    fn new(repaired: &ogg::Repaired, stream: &ogg::LogicalStream) -> Rc<Self> {
+        let pages = ogg::extract_stream(&repaired.data, stream.serial, stream.link);
+        let theora = ogg::TheoraStream::collect(ogg::valid_pages(&pages.data));
+        Rc::new(Self { pages, theora })
+    }
+}
+
+/// One Theora stream of the payload and the theorafile handle decoding it.
+struct Segment {
+    serial: u32,
+    stream: Rc<ExtractedStream>,
+    // theorafile keeps a pointer to the data source, so it must not move
+    _datasource: Box<DataSource>,
+    file: *mut OggTheora_File,
+    /// Index in `stream.theora` of the next packet theorafile reads.
+    packet_index: usize,
+    /// Presentation index the next decoded packet should have.
+    next_frame: u64,
+}
+
+impl Segment {
+    fn open(stream: Rc<ExtractedStream>, serial: u32) -> Result<Self> {
+        let data = stream.pages.data.clone();
+        Self::from_pages(stream, data, serial)
+    }
+
+    /// Opens `stream` with its data pages before the one packet `packet` starts
+    /// on left out, so a fresh decoder begins there.
+    fn open_at(stream: Rc<ExtractedStream>, serial: u32, packet: usize) -> Result<Self> {
+        let packets = stream.theora.as_ref().map_or(&[][..], |t| &t.packets[..]);
+        let (Some(first), Some(resume)) = (packets.first(), packets.get(packet)) else {
+            return Err(anyhow!("stream {:08x} has no packet {}", serial, packet));
+        };
+        // the header pages end where the first data packet begins
+        let pages = stream.pages.without(first.page_offset..resume.page_offset);
+        // theorafile starts with the first packet that begins on the resume page
+        let packet_index = packets
+            .iter()
+            .position(|p| p.page_offset == resume.page_offset)
+            .unwrap_or(packet);
+        let next_frame = packets[packet_index].frame;
+        let mut segment = Self::from_pages(stream, pages.data, serial)?;
+        segment.packet_index = packet_index;
+        segment.next_frame = next_frame;
+        Ok(segment)
+    }
+
+    /// Opens theorafile on `data`, the pages of `stream` it is to read.
+    fn from_pages(stream: Rc<ExtractedStream>, data: Vec<u8>, serial: u32) -> Result<Self> {
+        // theorafile waits forever for headers that never come
+        if let Some(theora) = stream.theora.as_ref().filter(|t| t.headers < 3) {
+            return Err(Error::CorruptStream(format!(
+                "Theora stream {:08x} ends after {} of its 3 header packets",
+                serial, theora.headers
+            ))
+            .into());
+        }
+        let mut datasource = Box::new(DataSource::new(data));
+        let file = open_theora(&mut datasource)?;
+        Ok(Self {
+            serial,
+            stream,
+            _datasource: datasource,
+            file,
+            packet_index: 0,
+            next_frame: 0,
+        })
+    }
+
+    /// Decodes the next `count` packets one at a time, leaving the last picture
+    /// in `buffer`. Returns whether there is one, or the index of the first
+    /// packet that fails to decode, where skipping stops.
+    fn skip_packets(
+        &mut self,
+        buffer: &mut [u8],
+        count: usize,
+    ) -> std::result::Result<bool, usize> {
+        let mut picture = false;
+        for _ in 0..count {
+            let ret = unsafe {
+                tf_readvideo(
+                    self.file,
+                    buffer.as_mut_ptr() as *mut ::std::os::raw::c_char,
+                    1,
+                )
+            };
+            let packet = self
+                .stream
+                .theora
+                .as_ref()
+                .and_then(|s| s.packets.get(self.packet_index));
+            // an empty packet is a duplicate and leaves the picture as it is
+            if ret == 0 && packet.is_none_or(|p| p.size != 0) {
+                return Err(self.packet_index);
+            }
+            picture |= ret != 0;
+            if let Some(packet) = packet {
+                self.next_frame = packet.frame + 1;
+            }
+            self.packet_index += 1;
+        }
+        Ok(picture)
+    }
+
+    /// Picture size, frame rate and pixel format as theorafile reports them.
+    fn video_info(&self) -> Result<(i32, i32, f64, th_pixel_fmt)> {
+        if unsafe { tf_hasvideo(self.file) } == 0 {
+            return Err(Error::NoVideoStream.into());
+        }
+        let mut width: ::std::os::raw::c_int = 0;
+        let mut height: ::std::os::raw::c_int = 0;
+        let mut fps: f64 = 0.0;
+        let mut fmt: th_pixel_fmt = 0;
+        unsafe { tf_videoinfo(self.file, &mut width, &mut height, &mut fps, &mut fmt) };
+        Ok((width, height, fps, fmt))
+    }
+
+    fn frame_rate(&self, fps: f64) -> Result<FrameRate> {
+        // theorafile divides the rate out, the identification header has it exact
+        match &self.stream.theora {
+            Some(stream) => FrameRate::new(stream.info.fps_num, stream.info.fps_den),
+            None => FrameRate::from_f64(fps),
+        }
+        .or_else(|_| FrameRate::from_f64(fps))
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        unsafe {
+            tf_close(self.file);
+            std::alloc::dealloc(self.file as *mut u8, Layout::new::<OggTheora_File>());
+        }
+    }
+}
+
+/// The Theora streams to decode, in order: the first of every chain link, or
+/// only the `serial` stream.
+pub(crate) fn select_streams(
+    streams: &[ogg::LogicalStream],
+    serial: Option<u32>,
+) -> Result<Vec<ogg::LogicalStream>> {
+    let is_theora = |s: &&ogg::LogicalStream| matches!(s.codec, ogg::Codec::Theora(_));
+    if let Some(serial) = serial {
+        let stream = streams
+            .iter()
+            .find(|s| s.serial == serial)
+            .ok_or_else(|| anyhow!("no logical stream with serial {:08x}", serial))?;
+        if !is_theora(&stream) {
+            return Err(anyhow!(
+                "stream {:08x} is {}, not Theora",
+                serial,
+                stream.codec.name()
+            ));
+        }
+        return Ok(vec![stream.clone()]);
+    }
+
+    let mut selected: Vec<ogg::LogicalStream> = Vec::new();
+    for stream in streams.iter().filter(is_theora) {
+        if selected.last().is_some_and(|s| s.link == stream.link) {
+            log::info!(
+                "ignoring Theora stream {:08x}, select it with --serial",
+                stream.serial
+            );
+            continue;
+        }
+        selected.push(stream.clone());
+    }
+    Ok(selected)
+}
+
+fn open_theora(datasource: &mut DataSource) -> Result<*mut OggTheora_File> {
+    let tf_cbs = tf_callbacks {
+        read_func: Some(read_func_impl),
+        seek_func: Some(seek_func_impl),
+        close_func: Some(close_func_impl),
+    };
+    let datasource_ptr = datasource as *mut DataSource as *mut ::std::os::raw::c_void;
+
+    let layout = Layout::new::<OggTheora_File>();
+    let ptr = unsafe { std::alloc::alloc(layout) };
+    if ptr.is_null() {
+        return Err(anyhow!("Failed to allocate memory"));
+    }
+
+    let ogg_file = ptr as *mut OggTheora_File;
+    let ret = unsafe { tf_open_callbacks(datasource_ptr, ogg_file, tf_cbs) };
+    if ret < 0 {
+        unsafe { std::alloc::dealloc(ptr, layout) };
+        return Err(Error::NoVideoStream.into());
+    }
+    Ok(ogg_file)
+}
+
+/// Drops damaged pages from the payload, or fails under `OnError::Stop`.
+fn repair_payload(
+    ogv_content: &[u8],
+    payload_offset: usize,
+    on_error: OnError,
+) -> Result<ogg::Repaired> {
+    let repaired = ogg::repair(ogv_content);
+    for damage in &repaired.damage {
+        let message = format!(
+            "corrupt Ogg payload: {} (file offset {:#x})",
+            damage,
+            payload_offset + damage.offset
+        );
+        if on_error == OnError::Stop {
+            return Err(Error::CorruptStream(message).into());
+        }
+        log::warn!("{}, resyncing to the next good page", message);
+    }
+    if repaired.trailing > 0 {
+        log::debug!(
+            "ignoring {} byte(s) after the last Ogg page",
+            repaired.trailing
+        );
+    }
+    Ok(repaired)
+}
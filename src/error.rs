@@ -0,0 +1,117 @@
+//! Failures a caller may want to tell apart, and the exit codes the command line
+//! tool maps them to.
+//!
+//! Functions still return [`anyhow::Result`]; these errors travel inside it and
+//! can be found with [`Error::find`], whatever context was added on the way up.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file is too short or otherwise not an OMV header.
+    InvalidHeader(String),
+    /// The file has no Ogg capture pattern after the header.
+    NoOggPayload,
+    /// The Ogg payload holds no Theora stream theorafile can open.
+    NoVideoStream,
+    /// A Theora pixel format other than 4:4:4.
+    UnsupportedPixelFormat(String),
+    /// The header and the stream disagree about the frame size.
+    InvalidGeometry(String),
+    /// Damaged pages or undecodable frames, under `--on-error stop`.
+    CorruptStream(String),
+    /// The encoder cannot be started, e.g. ffmpeg is not installed.
+    EncoderUnavailable(String),
+    /// The encoder failed while encoding.
+    EncoderFailed(String),
+    /// Reading or writing `path` failed.
+    Io { path: PathBuf, source: io::Error },
+    /// `verify` found problems in `failed` of `total` files.
+    VerificationFailed { failed: usize, total: usize },
+}
+
+impl Error {
+    /// Process exit code of the command line tool. 1 is any other failure, 2 an
+    /// invalid command line.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidHeader(_) => 3,
+            Error::NoOggPayload => 4,
+            Error::NoVideoStream => 5,
+            Error::UnsupportedPixelFormat(_) => 6,
+            Error::InvalidGeometry(_) => 7,
+            Error::CorruptStream(_) => 8,
+            Error::EncoderUnavailable(_) => 9,
+            Error::EncoderFailed(_) => 10,
+            Error::Io { .. } => IO_EXIT_CODE,
+            Error::VerificationFailed { .. } => 12,
+        }
+    }
+
+    /// The outermost `Error` in the chain of `error`.
+    pub fn find(error: &anyhow::Error) -> Option<&Error> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+    }
+
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+const IO_EXIT_CODE: i32 = 11;
+
+/// Exit code for `error`: that of the outermost [`Error`] in its chain, the I/O
+/// code for a bare I/O error, 1 for anything else.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<Error>() {
+            return error.exit_code();
+        }
+        if cause.is::<io::Error>() {
+            return IO_EXIT_CODE;
+        }
+        if let Some(image::ImageError::IoError(_)) = cause.downcast_ref::<image::ImageError>() {
+            return IO_EXIT_CODE;
+        }
+    }
+    1
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidHeader(reason) => write!(f, "not an OMV file: {}", reason),
+            Error::NoOggPayload => write!(f, "no Ogg payload (OggS not found)"),
+            Error::NoVideoStream => write!(f, "no Theora video stream found"),
+            Error::UnsupportedPixelFormat(format) => write!(
+                f,
+                "unsupported pixel format {}, OMV videos are 4:4:4",
+                format
+            ),
+            Error::InvalidGeometry(reason) => write!(f, "invalid frame geometry: {}", reason),
+            Error::CorruptStream(reason) => write!(f, "{}", reason),
+            Error::EncoderUnavailable(reason) => write!(f, "encoder unavailable: {}", reason),
+            Error::EncoderFailed(reason) => write!(f, "encoder failed: {}", reason),
+            Error::Io { path, .. } => write!(f, "I/O error on {}", path.display()),
+            Error::VerificationFailed { failed, total } => {
+                write!(f, "{} of {} file(s) failed verification", failed, total)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
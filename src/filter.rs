@@ -0,0 +1,477 @@
+//! Frame filters that run between the decoder and a converter.
+//!
+//! A chain is written like ffmpeg's: `scale=1280:-1:lanczos,crop=1280:540,hflip`.
+
+use anyhow::{anyhow, Result};
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::fmt;
+use std::str::FromStr;
+
+/// One step of the pipeline.
+pub trait Filter: fmt::Debug {
+    /// Size of the frames this filter produces from `width` x `height` frames.
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)>;
+    fn apply(&self, image: RgbaImage) -> Result<RgbaImage>;
+}
+
+/// Cuts a `width` x `height` region out of the frame, centred unless `x`/`y` are given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: Option<u32>,
+    pub y: Option<u32>,
+}
+
+impl Crop {
+    fn origin(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        let x = self.x.unwrap_or(width.saturating_sub(self.width) / 2);
+        let y = self.y.unwrap_or(height.saturating_sub(self.height) / 2);
+        if self.width == 0
+            || self.height == 0
+            || x as u64 + self.width as u64 > width as u64
+            || y as u64 + self.height as u64 > height as u64
+        {
+            return Err(anyhow!(
+                "crop {}x{} at {},{} does not fit a {}x{} frame",
+                self.width,
+                self.height,
+                x,
+                y,
+                width,
+                height
+            ));
+        }
+        Ok((x, y))
+    }
+}
+
+impl Filter for Crop {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        self.origin(width, height)?;
+        Ok((self.width, self.height))
+    }
+
+    fn apply(&self, image: RgbaImage) -> Result<RgbaImage> {
+        let (x, y) = self.origin(image.width(), image.height())?;
+        Ok(imageops::crop_imm(&image, x, y, self.width, self.height).to_image())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleAlgorithm {
+    Nearest,
+    #[default]
+    Bilinear,
+    Lanczos,
+}
+
+impl FromStr for ScaleAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nearest" => Ok(ScaleAlgorithm::Nearest),
+            "bilinear" => Ok(ScaleAlgorithm::Bilinear),
+            "lanczos" => Ok(ScaleAlgorithm::Lanczos),
+            _ => Err(anyhow!(
+                "unknown scale algorithm {:?}, expected nearest, bilinear or lanczos",
+                s
+            )),
+        }
+    }
+}
+
+/// Resizes the frame. A `None` side follows the aspect ratio of the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scale {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub algorithm: ScaleAlgorithm,
+}
+
+impl Filter for Scale {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        let keep = |side: u32, from: u32, to: u32| {
+            ((side as u64 * to as u64 + from as u64 / 2) / from.max(1) as u64).max(1) as u32
+        };
+        let size = match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, keep(height, width, w)),
+            (None, Some(h)) => (keep(width, height, h), h),
+            (None, None) => (width, height),
+        };
+        if size.0 == 0 || size.1 == 0 {
+            return Err(anyhow!("cannot scale to {}x{}", size.0, size.1));
+        }
+        Ok(size)
+    }
+
+    fn apply(&self, image: RgbaImage) -> Result<RgbaImage> {
+        let (width, height) = self.output_size(image.width(), image.height())?;
+        if (width, height) == image.dimensions() {
+            return Ok(image);
+        }
+        let filter = match self.algorithm {
+            ScaleAlgorithm::Nearest => FilterType::Nearest,
+            ScaleAlgorithm::Bilinear => FilterType::Triangle,
+            ScaleAlgorithm::Lanczos => FilterType::Lanczos3,
+        };
+        Ok(imageops::resize(&image, width, height, filter))
+    }
+}
+
+/// Places the frame on a `width` x `height` canvas, centred unless `x`/`y` are given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pad {
+    pub width: u32,
+    pub height: u32,
+    pub x: Option<u32>,
+    pub y: Option<u32>,
+    pub color: [u8; 4],
+}
+
+impl Pad {
+    fn origin(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        let x = self.x.unwrap_or(self.width.saturating_sub(width) / 2);
+        let y = self.y.unwrap_or(self.height.saturating_sub(height) / 2);
+        if x as u64 + width as u64 > self.width as u64
+            || y as u64 + height as u64 > self.height as u64
+        {
+            return Err(anyhow!(
+                "a {}x{} frame at {},{} does not fit the {}x{} pad",
+                width,
+                height,
+                x,
+                y,
+                self.width,
+                self.height
+            ));
+        }
+        Ok((x, y))
+    }
+}
+
+impl Filter for Pad {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        self.origin(width, height)?;
+        Ok((self.width, self.height))
+    }
+
+    fn apply(&self, image: RgbaImage) -> Result<RgbaImage> {
+        let (x, y) = self.origin(image.width(), image.height())?;
+        let mut canvas = RgbaImage::from_pixel(self.width, self.height, Rgba(self.color));
+        imageops::replace(&mut canvas, &image, x as i64, y as i64);
+        Ok(canvas)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+impl Filter for Flip {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        Ok((width, height))
+    }
+
+    fn apply(&self, image: RgbaImage) -> Result<RgbaImage> {
+        Ok(match self {
+            Flip::Horizontal => imageops::flip_horizontal(&image),
+            Flip::Vertical => imageops::flip_vertical(&image),
+        })
+    }
+}
+
+/// Clockwise rotation by a multiple of 90 degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotate {
+    pub degrees: u32,
+}
+
+impl Filter for Rotate {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        Ok(match self.degrees {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        })
+    }
+
+    fn apply(&self, image: RgbaImage) -> Result<RgbaImage> {
+        Ok(match self.degrees {
+            90 => imageops::rotate90(&image),
+            180 => imageops::rotate180(&image),
+            270 => imageops::rotate270(&image),
+            _ => image,
+        })
+    }
+}
+
+/// Converts between straight and premultiplied colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Premultiply {
+    Multiply,
+    Divide,
+}
+
+impl Filter for Premultiply {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        Ok((width, height))
+    }
+
+    fn apply(&self, mut image: RgbaImage) -> Result<RgbaImage> {
+        for pixel in image.pixels_mut() {
+            let a = pixel.0[3] as u32;
+            for c in &mut pixel.0[..3] {
+                *c = match self {
+                    Premultiply::Multiply => ((*c as u32 * a + 127) / 255) as u8,
+                    Premultiply::Divide if a == 0 => 0,
+                    Premultiply::Divide => ((*c as u32 * 255 + a / 2) / a).min(255) as u8,
+                };
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// Brightness offset, contrast and saturation factors and gamma, applied to colour.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjust {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub gamma: f32,
+}
+
+impl Default for Adjust {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Filter for Adjust {
+    fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        Ok((width, height))
+    }
+
+    fn apply(&self, mut image: RgbaImage) -> Result<RgbaImage> {
+        // the curve is the same for every channel, so tabulate it
+        let curve: Vec<f32> = (0..256)
+            .map(|v| {
+                let v = v as f32 / 255.0;
+                let v = (v - 0.5) * self.contrast + 0.5 + self.brightness;
+                v.clamp(0.0, 1.0).powf(1.0 / self.gamma)
+            })
+            .collect();
+        for pixel in image.pixels_mut() {
+            let [r, g, b, _] = pixel.0;
+            let rgb = [curve[r as usize], curve[g as usize], curve[b as usize]];
+            let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+            for (c, value) in pixel.0[..3].iter_mut().zip(rgb) {
+                let value = luma + (value - luma) * self.saturation;
+                *c = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// Filters applied in order.
+#[derive(Debug, Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, filter: Box<dyn Filter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn with(mut self, filter: impl Filter + 'static) -> Self {
+        self.push(Box::new(filter));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Appends the filters of a comma separated chain.
+    pub fn extend_from_str(&mut self, s: &str) -> Result<()> {
+        for spec in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            self.push(parse_filter(spec)?);
+        }
+        Ok(())
+    }
+
+    /// Size of the frames leaving the chain, checking every step on the way.
+    pub fn output_size(&self, width: u32, height: u32) -> Result<(u32, u32)> {
+        self.filters
+            .iter()
+            .try_fold((width, height), |(w, h), filter| {
+                filter
+                    .output_size(w, h)
+                    .map_err(|e| anyhow!("{:?}: {}", filter, e))
+            })
+    }
+
+    pub fn apply(&self, image: RgbaImage) -> Result<RgbaImage> {
+        self.filters
+            .iter()
+            .try_fold(image, |image, filter| filter.apply(image))
+    }
+}
+
+impl FromStr for FilterChain {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chain = FilterChain::new();
+        chain.extend_from_str(s)?;
+        Ok(chain)
+    }
+}
+
+/// Parses one `name=arg:arg` filter.
+pub fn parse_filter(spec: &str) -> Result<Box<dyn Filter>> {
+    let (name, args) = spec.split_once('=').unwrap_or((spec, ""));
+    let args: Vec<&str> = if args.is_empty() {
+        Vec::new()
+    } else {
+        args.split(':').collect()
+    };
+    let arg = |i: usize| args.get(i).copied().filter(|a| !a.is_empty());
+    let number = |i: usize| -> Result<Option<u32>> {
+        arg(i)
+            .map(|a| {
+                a.parse::<u32>()
+                    .map_err(|_| anyhow!("{}: {:?} is not a size", name, a))
+            })
+            .transpose()
+    };
+    let required = |i: usize| -> Result<u32> {
+        number(i)?.ok_or_else(|| anyhow!("{}: argument {} is missing", name, i + 1))
+    };
+    let factor = |i: usize, default: f32| -> Result<f32> {
+        arg(i).map_or(Ok(default), |a| {
+            a.parse::<f32>()
+                .map_err(|_| anyhow!("{}: {:?} is not a number", name, a))
+        })
+    };
+    let expect_args = |max: usize| {
+        if args.len() > max {
+            Err(anyhow!("{}: too many arguments in {:?}", name, spec))
+        } else {
+            Ok(())
+        }
+    };
+
+    let filter: Box<dyn Filter> = match name {
+        "crop" => {
+            expect_args(4)?;
+            Box::new(Crop {
+                width: required(0)?,
+                height: required(1)?,
+                x: number(2)?,
+                y: number(3)?,
+            })
+        }
+        "scale" => {
+            expect_args(3)?;
+            // -1 keeps the aspect ratio, as in ffmpeg
+            let side = |i: usize| match arg(i) {
+                Some("-1") => Ok(None),
+                _ => required(i).map(Some),
+            };
+            Box::new(Scale {
+                width: side(0)?,
+                height: side(1)?,
+                algorithm: arg(2).map_or(Ok(ScaleAlgorithm::default()), str::parse)?,
+            })
+        }
+        "pad" => {
+            expect_args(5)?;
+            Box::new(Pad {
+                width: required(0)?,
+                height: required(1)?,
+                x: number(2)?,
+                y: number(3)?,
+                color: arg(4).map_or(Ok([0, 0, 0, 0]), parse_color)?,
+            })
+        }
+        "hflip" => {
+            expect_args(0)?;
+            Box::new(Flip::Horizontal)
+        }
+        "vflip" => {
+            expect_args(0)?;
+            Box::new(Flip::Vertical)
+        }
+        "rotate" => {
+            expect_args(1)?;
+            let degrees = required(0)?;
+            if degrees % 90 != 0 {
+                return Err(anyhow!(
+                    "rotate: only multiples of 90 degrees are supported"
+                ));
+            }
+            Box::new(Rotate {
+                degrees: degrees % 360,
+            })
+        }
+        "premultiply" => {
+            expect_args(0)?;
+            Box::new(Premultiply::Multiply)
+        }
+        "unpremultiply" => {
+            expect_args(0)?;
+            Box::new(Premultiply::Divide)
+        }
+        "adjust" => {
+            expect_args(4)?;
+            let gamma = factor(3, 1.0)?;
+            if gamma <= 0.0 {
+                return Err(anyhow!("adjust: gamma must be positive"));
+            }
+            Box::new(Adjust {
+                brightness: factor(0, 0.0)?,
+                contrast: factor(1, 1.0)?,
+                saturation: factor(2, 1.0)?,
+                gamma,
+            })
+        }
+        _ => return Err(anyhow!("unknown filter {:?}", name)),
+    };
+    Ok(filter)
+}
+
+/// Parses `#rrggbb` or `#rrggbbaa`.
+fn parse_color(s: &str) -> Result<[u8; 4]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6 || hex.len() == 8)
+        .ok_or_else(|| anyhow!("invalid colour {:?}, expected #rrggbb or #rrggbbaa", s))?;
+    Ok(if hex.len() == 6 {
+        let [_, r, g, b] = value.to_be_bytes();
+        [r, g, b, 0xff]
+    } else {
+        value.to_be_bytes()
+    })
+}
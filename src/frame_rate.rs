@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::time::Duration;
+
+/// Largest denominator [`FrameRate::from_f64`] tries.
+const MAX_DENOMINATOR: u64 = 100_000;
+
+/// An exact frame rate of `num / den` frames per second, kept in lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameRate {
+    num: u32,
+    den: u32,
+}
+
+impl FrameRate {
+    pub fn new(num: u32, den: u32) -> Result<Self> {
+        if num == 0 || den == 0 {
+            return Err(anyhow!("invalid frame rate {}/{}", num, den));
+        }
+        let g = gcd(num as u64, den as u64) as u32;
+        Ok(Self {
+            num: num / g,
+            den: den / g,
+        })
+    }
+
+    /// The closest rational to `fps`, for sources that only report a float.
+    /// NTSC rates come back as `30000/1001` and the like.
+    pub fn from_f64(fps: f64) -> Result<Self> {
+        if !fps.is_finite() || fps <= 0.0 || fps > u32::MAX as f64 {
+            return Err(anyhow!("invalid frame rate {}", fps));
+        }
+        // continued fraction expansion, stopping at the first close enough term
+        let (mut p0, mut q0, mut p1, mut q1) = (0u64, 1u64, 1u64, 0u64);
+        let mut x = fps;
+        loop {
+            let a = x.floor() as u64;
+            let (p2, q2) = (a * p1 + p0, a * q1 + q0);
+            if q2 > MAX_DENOMINATOR || p2 > u32::MAX as u64 {
+                break;
+            }
+            (p0, q0, p1, q1) = (p1, q1, p2, q2);
+            let fract = x - a as f64;
+            if (p1 as f64 / q1 as f64 - fps).abs() <= fps * 1e-9 || fract < 1e-12 {
+                break;
+            }
+            x = 1.0 / fract;
+        }
+        Self::new(p1.max(1) as u32, q1.max(1) as u32)
+    }
+
+    pub fn num(self) -> u32 {
+        self.num
+    }
+
+    pub fn den(self) -> u32 {
+        self.den
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Frames per second rounded to a whole number, at least 1, for APIs that
+    /// cannot take anything finer.
+    pub fn rounded(self) -> u32 {
+        ((self.num as u64 + self.den as u64 / 2) / self.den as u64).max(1) as u32
+    }
+
+    /// Presentation time of frame `index`, exact to the nanosecond.
+    pub fn timestamp(self, index: u64) -> Duration {
+        let nanos = index as u128 * self.den as u128 * 1_000_000_000 / self.num as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    /// Index of the frame on screen at `time`, the inverse of [`timestamp`](Self::timestamp).
+    pub fn index_at(self, time: Duration) -> u64 {
+        // the last frame whose rounded-down timestamp is not after `time`
+        let frames =
+            ((time.as_nanos() + 1) * self.num as u128 - 1) / (self.den as u128 * 1_000_000_000);
+        frames.min(u64::MAX as u128) as u64
+    }
+
+    pub fn frame_duration(self) -> Duration {
+        self.timestamp(1)
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
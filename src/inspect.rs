@@ -0,0 +1,302 @@
+//! Page-level listing of the Ogg payload of an OMV file, for files that do not
+//! decode as expected.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use crate::decoder;
+use crate::ogg::{self, Codec, PageError, ScanItem};
+
+/// One page as it sits in the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageEntry {
+    /// File offset of the capture pattern.
+    pub offset: usize,
+    pub serial: u32,
+    pub sequence: u32,
+    pub granule_position: i64,
+    pub continued: bool,
+    pub bos: bool,
+    pub eos: bool,
+    pub checksum_ok: bool,
+    /// Whole page, header included.
+    pub size: usize,
+    /// Sizes of the packet fragments on the page.
+    pub packets: Vec<usize>,
+    /// Whether the last packet continues on the next page.
+    pub open_end: bool,
+    /// For Theora: the keyframe and the frame the granule position points at,
+    /// zero-based.
+    pub keyframe: Option<u64>,
+    pub frame: Option<u64>,
+}
+
+/// Bytes that hold no page at all.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DamageEntry {
+    pub offset: usize,
+    pub len: usize,
+    pub reason: String,
+}
+
+/// Totals of one logical stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamEntry {
+    pub serial: u32,
+    pub codec: &'static str,
+    /// Zero-based link of a chained payload the stream belongs to.
+    pub link: usize,
+    /// Codec parameters, e.g. `640x480 4:4:4 30/1 fps`.
+    pub details: String,
+    pub pages: usize,
+    pub bytes: usize,
+    /// Playback time up to the last granule position, where the codec has one.
+    pub duration: Option<f64>,
+    /// Average over `duration`, in kbit/s.
+    pub bitrate: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Inspection {
+    /// File offset of the Ogg payload.
+    pub payload_offset: usize,
+    pub pages: Vec<PageEntry>,
+    pub damage: Vec<DamageEntry>,
+    /// Bytes after the last page.
+    pub trailing: usize,
+    pub streams: Vec<StreamEntry>,
+}
+
+/// Lists every page of the Ogg payload in `mem`, damaged ones included.
+pub fn inspect(mem: &[u8]) -> Result<Inspection> {
+    let payload_offset = decoder::find_payload(mem)?;
+    let data = &mem[payload_offset..];
+    let mut inspection = Inspection {
+        payload_offset,
+        pages: Vec::new(),
+        damage: Vec::new(),
+        trailing: 0,
+        streams: Vec::new(),
+    };
+    // streams are told apart by chain link and serial, a serial can come back
+    // in a later link
+    let mut links = ogg::Links::default();
+    let mut codecs: BTreeMap<(usize, u32), Codec> = BTreeMap::new();
+    // (link, serial) -> (pages, bytes, last granule position)
+    let mut totals: BTreeMap<(usize, u32), (usize, usize, i64)> = BTreeMap::new();
+
+    for item in ogg::PageScanner::new(data) {
+        let (page, checksum_ok) = match item {
+            ScanItem::Page(page) => (page, true),
+            ScanItem::Damaged(damage) => {
+                // list pages that are whole but fail their checksum
+                let page = matches!(damage.error, Some(PageError::BadChecksum { .. }))
+                    .then(|| ogg::parse_page_unchecked(data, damage.offset).ok())
+                    .flatten()
+                    .filter(|page| page.len() <= damage.len);
+                let Some(page) = page else {
+                    inspection
+                        .damage
+                        .push(damage_entry(payload_offset, &damage));
+                    continue;
+                };
+                if page.len() < damage.len {
+                    inspection.damage.push(DamageEntry {
+                        offset: payload_offset + damage.offset + page.len(),
+                        len: damage.len - page.len(),
+                        reason: "non-Ogg data".to_string(),
+                    });
+                }
+                (page, false)
+            }
+            ScanItem::Trailing { len, .. } => {
+                inspection.trailing = len;
+                continue;
+            }
+        };
+
+        // links are counted over valid pages only, like the decoder does
+        let link = if checksum_ok {
+            links.of_page(&page)
+        } else {
+            links.current()
+        };
+        let key = (link, page.serial);
+        if page.is_bos() {
+            codecs.entry(key).or_insert_with(|| Codec::of_page(&page));
+        }
+        // header pages of 3.2.1 streams have granule 0, before the first frame
+        let theora = match codecs.get(&key) {
+            Some(Codec::Theora(info)) if page.granule_position > 0 || info.version < (3, 2, 1) => {
+                Some(info)
+            }
+            _ => None,
+        };
+        let fragments = page.fragments();
+        let total = totals.entry(key).or_insert((0, 0, -1));
+        total.0 += 1;
+        total.1 += page.len();
+        if page.granule_position >= 0 {
+            total.2 = page.granule_position;
+        }
+        inspection.pages.push(PageEntry {
+            offset: payload_offset + page.offset,
+            serial: page.serial,
+            sequence: page.sequence,
+            granule_position: page.granule_position,
+            continued: page.is_continued(),
+            bos: page.is_bos(),
+            eos: page.is_eos(),
+            checksum_ok,
+            size: page.len(),
+            packets: fragments.iter().map(|f| f.0).collect(),
+            open_end: fragments.last().is_some_and(|f| !f.1),
+            keyframe: theora.and_then(|info| info.granule_keyframe(page.granule_position)),
+            frame: theora.and_then(|info| info.granule_frame(page.granule_position)),
+        });
+    }
+
+    for ((link, serial), (pages, bytes, granule)) in totals {
+        let codec = codecs.remove(&(link, serial)).unwrap_or(Codec::Unknown);
+        let duration = codec.granule_time(granule).filter(|&d| d > 0.0);
+        inspection.streams.push(StreamEntry {
+            serial,
+            codec: codec.name(),
+            link,
+            details: codec_details(&codec),
+            pages,
+            bytes,
+            duration,
+            bitrate: duration.map(|d| bytes as f64 * 8.0 / d / 1000.0),
+        });
+    }
+    Ok(inspection)
+}
+
+fn damage_entry(payload_offset: usize, damage: &ogg::Damage) -> DamageEntry {
+    DamageEntry {
+        offset: payload_offset + damage.offset,
+        len: damage.len,
+        reason: match &damage.error {
+            Some(e) => e.to_string(),
+            None => "non-Ogg data".to_string(),
+        },
+    }
+}
+
+fn codec_details(codec: &Codec) -> String {
+    match codec {
+        Codec::Theora(info) => format!(
+            "{}x{} {} {}/{} fps, version {}.{}.{}, keyframe shift {}",
+            info.pic_width,
+            info.pic_height,
+            decoder::pixel_format_name(info.pixel_format.into()),
+            info.fps_num,
+            info.fps_den,
+            info.version.0,
+            info.version.1,
+            info.version.2,
+            info.keyframe_granule_shift
+        ),
+        Codec::Vorbis {
+            channels,
+            sample_rate,
+        } => format!("{} channel(s), {} Hz", channels, sample_rate),
+        Codec::Skeleton | Codec::Unknown => String::new(),
+    }
+}
+
+impl PageEntry {
+    /// Header flags as `c`ontinued, `b`os and `e`os letters, `-` where unset.
+    pub fn flags(&self) -> String {
+        [(self.continued, 'c'), (self.bos, 'b'), (self.eos, 'e')]
+            .iter()
+            .map(|&(set, flag)| if set { flag } else { '-' })
+            .collect()
+    }
+}
+
+impl fmt::Display for PageEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let granule = match (self.keyframe, self.frame) {
+            (Some(keyframe), Some(frame)) => {
+                format!(
+                    "{} (key {} frame {})",
+                    self.granule_position, keyframe, frame
+                )
+            }
+            _ => self.granule_position.to_string(),
+        };
+        let mut packets = self
+            .packets
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if self.open_end {
+            packets.push('+');
+        }
+        write!(
+            f,
+            "{:#010x} {:08x} {:6} {:<28} {} {:4} {:6} {}",
+            self.offset,
+            self.serial,
+            self.sequence,
+            granule,
+            self.flags(),
+            if self.checksum_ok { "ok" } else { "BAD" },
+            self.size,
+            packets
+        )
+    }
+}
+
+impl Inspection {
+    /// Writes a table of the pages, then the damage and the stream totals.
+    pub fn write_text(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(
+            out,
+            "Ogg payload at file offset {:#x}, {} page(s)",
+            self.payload_offset,
+            self.pages.len()
+        )?;
+        writeln!(
+            out,
+            "{:10} {:8} {:>6} {:<28} cbe {:4} {:>6} packets",
+            "offset", "serial", "seqno", "granulepos", "crc", "size"
+        )?;
+        for page in &self.pages {
+            writeln!(out, "{}", page)?;
+        }
+        for damage in &self.damage {
+            writeln!(
+                out,
+                "damage at {:#x}, {} byte(s): {}",
+                damage.offset, damage.len, damage.reason
+            )?;
+        }
+        if self.trailing > 0 {
+            writeln!(out, "{} byte(s) after the last page", self.trailing)?;
+        }
+        let chained = self.streams.iter().any(|s| s.link > 0);
+        for stream in &self.streams {
+            write!(out, "stream {:08x} {}", stream.serial, stream.codec)?;
+            if chained {
+                write!(out, " (link {})", stream.link)?;
+            }
+            write!(out, ": {} page(s), {} bytes", stream.pages, stream.bytes)?;
+            if let (Some(duration), Some(bitrate)) = (stream.duration, stream.bitrate) {
+                write!(out, ", {:.3} s, {:.1} kbit/s", duration, bitrate)?;
+            }
+            if !stream.details.is_empty() {
+                write!(out, ", {}", stream.details)?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! Job files: a list of conversions with shared defaults and per-input overrides,
+//! read from TOML or JSON.
+//!
+//! ```toml
+//! output-dir = "out"
+//!
+//! [defaults]
+//! format = "ffmpeg"
+//! filter = ["scale=1280:-1"]
+//!
+//! [[input]]
+//! path = "movie/op.omv"
+//!
+//! [[input]]
+//! path = "movie/ed.omv"
+//! format = "png-picture"
+//! output = "frames/ed"
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::converter::{
+    AlphaLayout, Background, EncoderSettings, H264Profile, MatteColour, MatteKind, OutputFormat,
+    PipedPayload, Preset, SizePolicy,
+};
+use crate::decoder::{AlphaMode, OnError};
+use crate::filter::FilterChain;
+
+/// Conversion settings; every field left out falls back to `[defaults]`, then to
+/// the command line default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct JobSettings {
+    /// The .omv to convert, only valid in an `[[input]]` entry.
+    pub path: Option<PathBuf>,
+    /// Output path, only valid in an `[[input]]` entry. Defaults to the input's
+    /// name in `output-dir`.
+    pub output: Option<PathBuf>,
+    pub format: Option<OutputFormat>,
+    pub piped_payload: Option<PipedPayload>,
+    pub on_error: Option<OnError>,
+    pub alpha_mode: Option<AlphaMode>,
+    pub trim_trailing: Option<bool>,
+    pub matte: Option<PathBuf>,
+    pub matte_format: Option<OutputFormat>,
+    pub matte_kind: Option<MatteKind>,
+    pub matte_colour: Option<MatteColour>,
+    pub alpha_layout: Option<AlphaLayout>,
+    /// Same syntax as `--background`.
+    pub background: Option<String>,
+    /// Filters in `--filter` syntax. An entry's list replaces the default one.
+    pub filter: Option<Vec<String>>,
+    pub odd_size: Option<SizePolicy>,
+    pub crf: Option<u8>,
+    /// Same syntax as `--bitrate`.
+    pub bitrate: Option<String>,
+    pub preset: Option<Preset>,
+    pub profile: Option<H264Profile>,
+    pub framehash_channels: Option<bool>,
+    pub framehash_planes: Option<bool>,
+    /// Serial number of the logical stream to decode.
+    pub serial: Option<u32>,
+}
+
+impl JobSettings {
+    /// Fills every field `self` leaves out from `defaults`.
+    pub fn or(self, defaults: &JobSettings) -> JobSettings {
+        let defaults = defaults.clone();
+        JobSettings {
+            path: self.path.or(defaults.path),
+            output: self.output.or(defaults.output),
+            format: self.format.or(defaults.format),
+            piped_payload: self.piped_payload.or(defaults.piped_payload),
+            on_error: self.on_error.or(defaults.on_error),
+            alpha_mode: self.alpha_mode.or(defaults.alpha_mode),
+            trim_trailing: self.trim_trailing.or(defaults.trim_trailing),
+            matte: self.matte.or(defaults.matte),
+            matte_format: self.matte_format.or(defaults.matte_format),
+            matte_kind: self.matte_kind.or(defaults.matte_kind),
+            matte_colour: self.matte_colour.or(defaults.matte_colour),
+            alpha_layout: self.alpha_layout.or(defaults.alpha_layout),
+            background: self.background.or(defaults.background),
+            filter: self.filter.or(defaults.filter),
+            odd_size: self.odd_size.or(defaults.odd_size),
+            crf: self.crf.or(defaults.crf),
+            bitrate: self.bitrate.or(defaults.bitrate),
+            preset: self.preset.or(defaults.preset),
+            profile: self.profile.or(defaults.profile),
+            framehash_channels: self.framehash_channels.or(defaults.framehash_channels),
+            framehash_planes: self.framehash_planes.or(defaults.framehash_planes),
+            serial: self.serial.or(defaults.serial),
+        }
+    }
+
+    /// Parses the background, if any.
+    pub fn background(&self) -> Result<Option<Background>> {
+        self.background.as_deref().map(str::parse).transpose()
+    }
+
+    /// Parses and checks the encoder settings.
+    pub fn encoder(&self) -> Result<EncoderSettings> {
+        EncoderSettings {
+            crf: self.crf,
+            bitrate: self.bitrate.as_deref().map(str::parse).transpose()?,
+            preset: self.preset,
+            profile: self.profile.unwrap_or_default(),
+        }
+        .check()
+    }
+
+    /// Parses the filter list into one chain.
+    pub fn filter_chain(&self) -> Result<FilterChain> {
+        let mut chain = FilterChain::new();
+        for spec in self.filter.iter().flatten() {
+            chain.extend_from_str(spec)?;
+        }
+        Ok(chain)
+    }
+}
+
+/// A whole job file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Job {
+    /// Directory for entries without an `output`.
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+    /// Run the remaining entries after one fails.
+    #[serde(default)]
+    pub keep_going: bool,
+    #[serde(default)]
+    pub defaults: JobSettings,
+    #[serde(default, rename = "input")]
+    pub inputs: Vec<JobSettings>,
+    /// Relative paths in the job are resolved against this directory, the one the
+    /// job file is in.
+    #[serde(skip)]
+    pub base: PathBuf,
+}
+
+/// One conversion of a job, with paths resolved and defaults applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobEntry {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    pub settings: JobSettings,
+}
+
+impl Job {
+    pub fn from_toml(text: &str) -> Result<Job> {
+        toml::from_str(text).map_err(|e| anyhow!("invalid job: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<Job> {
+        serde_json::from_str(text).map_err(|e| anyhow!("invalid job: {}", e))
+    }
+
+    /// Reads a job file, JSON if it ends in `.json`, TOML otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Job> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read job {}", path.display()))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let mut job = if is_json {
+            Job::from_json(&text)
+        } else {
+            Job::from_toml(&text)
+        }
+        .with_context(|| path.display().to_string())?;
+        job.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(job)
+    }
+
+    /// Resolves every entry and checks its settings, so a mistake anywhere in the
+    /// job is reported before anything is converted.
+    pub fn entries(&self) -> Result<Vec<JobEntry>> {
+        if self.defaults.path.is_some() || self.defaults.output.is_some() {
+            return Err(anyhow!(
+                "`path` and `output` belong in [[input]] entries, use `output-dir` for a shared directory"
+            ));
+        }
+        let mut entries = Vec::with_capacity(self.inputs.len());
+        let mut outputs = HashSet::new();
+        for (i, input) in self.inputs.iter().enumerate() {
+            let entry = self
+                .entry(input)
+                .with_context(|| format!("input {}", i + 1))?;
+            for output in std::iter::once(&entry.output).chain(&entry.settings.matte) {
+                if !outputs.insert(output.clone()) {
+                    return Err(anyhow!(
+                        "input {}: {} is written by an earlier entry",
+                        i + 1,
+                        output.display()
+                    ));
+                }
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn entry(&self, input: &JobSettings) -> Result<JobEntry> {
+        let mut settings = input.clone().or(&self.defaults);
+        let path = settings
+            .path
+            .take()
+            .ok_or_else(|| anyhow!("missing `path`"))?;
+        let format = settings.format.unwrap_or_default();
+        let output = match settings.output.take() {
+            Some(output) => self.base.join(output),
+            None => self.default_output(&path, format)?,
+        };
+        settings.matte = settings.matte.map(|matte| self.base.join(matte));
+        if let Some(Background::Image(image)) = settings.background()? {
+            settings.background = Some(self.base.join(image).to_string_lossy().into_owned());
+        }
+        settings.filter_chain()?;
+        settings.encoder()?;
+        // the same combinations the command line rejects
+        let alpha_outputs = [
+            settings.matte.is_some(),
+            settings.alpha_layout.is_some(),
+            settings.background.is_some(),
+        ];
+        if alpha_outputs.iter().filter(|&&set| set).count() > 1 {
+            return Err(anyhow!(
+                "`matte`, `alpha-layout` and `background` cannot be combined"
+            ));
+        }
+        Ok(JobEntry {
+            input: self.base.join(path),
+            output,
+            format,
+            settings,
+        })
+    }
+
+    fn default_output(&self, input: &Path, format: OutputFormat) -> Result<PathBuf> {
+        let dir = self
+            .output_dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("{}: no `output` and no `output-dir`", input.display()))?;
+        let stem = input
+            .file_stem()
+            .ok_or_else(|| anyhow!("{}: no file name", input.display()))?;
+        let mut name = stem.to_os_string();
+        match format {
+            OutputFormat::H264 | OutputFormat::Ffmpeg => name.push(".mp4"),
+            OutputFormat::Ogv => name.push(".ogv"),
+            OutputFormat::Framehash => name.push(".framemd5"),
+            // picture formats write a directory of frames
+            OutputFormat::PngPicture | OutputFormat::JpgPicture => {}
+            OutputFormat::PipedPng => {
+                return Err(anyhow!(
+                    "{}: piped-png needs an explicit `output`",
+                    input.display()
+                ))
+            }
+        }
+        Ok(self.base.join(dir).join(name))
+    }
+}
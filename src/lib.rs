@@ -0,0 +1,15 @@
+pub mod compare;
+pub mod converter;
+pub mod decoder;
+pub mod error;
+pub mod filter;
+pub mod frame_rate;
+pub mod inspect;
+pub mod job;
+pub mod ogg;
+pub mod pack;
+pub mod progress;
+pub mod reader;
+pub mod repack;
+pub mod seek;
+pub mod verify;
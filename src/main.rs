@@ -1,226 +1,173 @@
 use anyhow::Result;
-use binrw::BinRead;
-use clap::Parser;
-use kmpsearch::Haystack;
-use memmap::MmapOptions;
-use pack::OmvHeader;
-use serde::{Deserialize, Serialize};
-use std::alloc::Layout;
+use clap::{Parser, Subcommand};
+use indicatif::MultiProgress;
+use indicatif_log_bridge::LogWrapper;
+use log::LevelFilter;
+use memmap::{Mmap, MmapOptions};
+use omvdecoder::compare::{self, Reference, ReportFormat};
+use omvdecoder::converter::{
+    self, AlphaLayout, Background, Bitrate, Converter, EncoderSettings, H264Profile, MatteColour,
+    MatteKind, OutputFormat, PipedOutput, PipedPayload, Preset, SizePolicy,
+};
+use omvdecoder::decoder::{
+    self, AlphaMode, DecodeOptions, OggPayload, OmvReader, OnError, VideoInfo,
+};
+use omvdecoder::error::{self, Error};
+use omvdecoder::filter::FilterChain;
+use omvdecoder::inspect;
+use omvdecoder::job::{Job, JobSettings};
+use omvdecoder::ogg;
+use omvdecoder::pack::OmvHeader;
+use omvdecoder::progress::{Progress, ProgressMode};
+use omvdecoder::repack::{self, RepackOptions};
+use omvdecoder::verify::{self, Status, VerifyOptions};
 use std::fs::File;
+use std::io::{IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use theorafile_rs::*;
-
-use crate::converter::Converter;
-
-mod converter;
-mod pack;
-
-fn read_omv_header(source: &[u8]) -> Result<pack::OmvHeader> {
-    let mut source = std::io::Cursor::new(source);
-    let header = pack::OmvHeader::read(&mut source)?;
-    Ok(header)
-}
 
 fn convert_file(
     path: impl AsRef<Path>,
     output_format: OutputFormat,
     output: impl AsRef<Path>,
+    options: &ConvertOptions,
+    progress: &mut Progress,
 ) -> Result<()> {
-    let file = File::open(path)?;
-    let mmap = unsafe { MmapOptions::new().map(&file)? };
+    let source_name = path
+        .as_ref()
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mmap = map_file(path.as_ref())?;
     let mem = mmap.as_ref();
-    let header = read_omv_header(mem)?;
-    log::info!("extracting {:?}", header);
-    let res = mem
-        .indexesof_needle(b"OggS")
-        .ok_or(anyhow::anyhow!("OggS not found"))?;
-    let first_index = res
-        .first()
-        .ok_or(anyhow::anyhow!("OggS not found"))?
-        .to_owned();
-    let ogv_content = &mem[first_index..];
-
-    convert_embedded_ogv(&header, ogv_content, output_format, output)?;
-
-    Ok(())
-}
-
-pub struct DataSource {
-    data: Vec<u8>,
-    pos: usize,
-}
-
-impl DataSource {
-    pub fn new(data: Vec<u8>) -> Self {
-        Self { data, pos: 0 }
-    }
-
-    // Implement a method to seek to a specific position
-    pub fn seek(
-        &mut self,
-        offset: ogg_int64_t,
-        origin: ::std::os::raw::c_int,
-    ) -> ::std::os::raw::c_int {
-        match origin {
-            0 => self.pos = offset as usize,
-            1 => self.pos = (self.pos as ogg_int64_t + offset) as usize,
-            2 => self.pos = (self.data.len() as ogg_int64_t + offset) as usize,
-            _ => return -1, // Unsupported origin
-        }
-        // Ensure pos doesn't go out of bounds
-        if self.pos > self.data.len() {
-            self.pos = self.data.len();
-        }
-        0 // Success
-    }
-
-    // Implement a method to read data from the current position
-    pub fn read(&mut self, ptr: *mut ::std::os::raw::c_void, size: usize, nmemb: usize) -> usize {
-        let bytes_to_read = size * nmemb;
-        let remaining_data = &self.data[self.pos..];
-        let bytes_read = std::cmp::min(remaining_data.len(), bytes_to_read);
-        unsafe {
-            std::ptr::copy_nonoverlapping(remaining_data.as_ptr(), ptr as *mut u8, bytes_read);
-        }
-        self.pos += bytes_read;
-        bytes_read
-    }
-
-    // Implement a method to close the data source
-    pub fn close(&mut self) -> ::std::os::raw::c_int {
-        // Optionally perform any cleanup here
-        0 // Success
-    }
-}
-
-unsafe extern "C" fn read_func_impl(
-    ptr: *mut ::std::os::raw::c_void,
-    size: usize,
-    nmemb: usize,
-    datasource: *mut ::std::os::raw::c_void,
-) -> usize {
-    if let Some(datasource) = (datasource as *mut DataSource).as_mut() {
-        datasource.read(ptr, size, nmemb)
+    // a broken header is reported by the conversion itself
+    let total = decoder::read_omv_header(mem)
+        .map(|header| header.metadata().frame_count)
+        .unwrap_or(0);
+    progress.begin(&source_name, total);
+    let result = if let OutputFormat::Ogv = output_format {
+        extract_ogv(mem, output, options)
     } else {
-        0
+        let decode_options = DecodeOptions {
+            on_error: options.on_error,
+            alpha_mode: options.alpha_mode,
+            serial: options.serial,
+            raw_planes: options.framehash_planes
+                && (output_format == OutputFormat::Framehash
+                    || options
+                        .matte
+                        .as_ref()
+                        .is_some_and(|matte| matte.format == OutputFormat::Framehash)),
+        };
+        OmvReader::open(mem, decode_options).and_then(|reader| {
+            convert_embedded_ogv(
+                reader,
+                &source_name,
+                output_format,
+                output,
+                options,
+                progress,
+            )
+        })
+    };
+    match &result {
+        Ok(()) => progress.finish(),
+        Err(e) => progress.fail(e),
     }
+    result
 }
 
-unsafe extern "C" fn seek_func_impl(
-    datasource: *mut ::std::os::raw::c_void,
-    offset: ogg_int64_t,
-    origin: ::std::os::raw::c_int,
-) -> ::std::os::raw::c_int {
-    if let Some(datasource) = (datasource as *mut DataSource).as_mut() {
-        datasource.seek(offset, origin)
-    } else {
-        -1
+fn map_file(path: &Path) -> Result<Mmap> {
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
+    // an empty file cannot be mapped
+    if file.metadata().map_err(|e| Error::io(path, e))?.len() == 0 {
+        return Err(Error::InvalidHeader("the file is empty".to_string()).into());
     }
+    let mmap = unsafe { MmapOptions::new().map(&file) }.map_err(|e| Error::io(path, e))?;
+    Ok(mmap)
 }
 
-unsafe extern "C" fn close_func_impl(
-    datasource: *mut ::std::os::raw::c_void,
-) -> ::std::os::raw::c_int {
-    if let Some(datasource) = (datasource as *mut DataSource).as_mut() {
-        datasource.close()
-    } else {
-        -1
+/// Writes the embedded Ogg payload to `output` as it is, without decoding it.
+fn extract_ogv(mem: &[u8], output: impl AsRef<Path>, options: &ConvertOptions) -> Result<()> {
+    let header = decoder::read_omv_header(mem)?;
+    log::info!("extracting {:?}", header);
+    let payload = OggPayload::locate(mem)?;
+
+    for damage in payload
+        .damage
+        .iter()
+        .filter(|d| d.offset < payload.pages_end)
+    {
+        let message = format!(
+            "corrupt Ogg payload: {} (file offset {:#x})",
+            damage,
+            payload.offset + damage.offset
+        );
+        if options.on_error == OnError::Stop {
+            return Err(Error::CorruptStream(message).into());
+        }
+        log::warn!("{}, copying it as is", message);
     }
-}
 
-#[inline]
-fn clamp(val: f32) -> u8 {
-    if val < 0.0 {
-        0
-    } else if val > 255.0 {
-        255
+    let data = if options.trim_trailing {
+        if payload.trailing() > 0 {
+            log::info!(
+                "trimming {} byte(s) after the last Ogg page",
+                payload.trailing()
+            );
+        }
+        payload.trimmed()
     } else {
-        val.round() as u8
-    }
-}
-
-fn yuv2rgb(y: u8, u: u8, v: u8) -> (u8, u8, u8) {
-    let r = y as f32 + (1.370705 * (v as f32 - 128.0));
-    let g = y as f32 - (0.698001 * (v as f32 - 128.0)) - (0.337633 * (u as f32 - 128.0));
-    let b = y as f32 + (1.732446 * (u as f32 - 128.0));
-    let r = clamp(r);
-    let g = clamp(g);
-    let b = clamp(b);
-    return (r, g, b);
-}
-
-fn yuv_to_image(data: *mut i8, width: i32, height: i32, video_height: i32, is24bit: bool) -> image::RgbaImage {
-    let mut img = image::RgbaImage::new(width as u32, height as u32);
-
-    // for (int y = 0; y < height; y++)
-    // {
-    //     for (int x = 0; x < width; x++)
-    //     {
-    //         buf[offset + data.Stride * y + 4 * x + 0] = vidbuf[vidwidth * (vidheight * 0 + y) + x];
-    //         buf[offset + data.Stride * y + 4 * x + 1] = vidbuf[vidwidth * (vidheight * 1 + y) + x];
-    //         buf[offset + data.Stride * y + 4 * x + 2] = vidbuf[vidwidth * (vidheight * 2 + y) + x];
-    //         if (y < (height + 2) / 3)
-    //         {
-    //             buf[offset + data.Stride * y + 4 * x + 3] = vidbuf[vidwidth * (height * 1 + y) + x];
-    //         }
-    //         else if (y < (height + 2) / 3 * 2)
-    //         {
-    //             buf[offset + data.Stride * y + 4 * x + 3] = vidbuf[vidwidth * (height * 2 + y) + x];
-    //         }
-    //         else
-    //         {
-    //             buf[offset + data.Stride * y + 4 * x + 3] = vidbuf[vidwidth * (height * 3 + y) + x];
-    //         }
-    //     }
-    // }
-
-    #[allow(clippy::erasing_op)]
-    #[allow(clippy::identity_op)]
-    for y in 0..height {
-        for x in 0..width {
-            let b = unsafe { *data.offset((width * (video_height * 0 + y) + x) as isize) };
-            let g = unsafe { *data.offset((width * (video_height * 1 + y) + x) as isize) };
-            let r = unsafe { *data.offset((width * (video_height * 2 + y) + x) as isize) };
-
-            let a = if is24bit {
-                0xff
-            } else {
-                let a = if y < (height + 2) / 3 {
-                    unsafe { *data.offset((x + width * (height * 1 + y)) as isize) }
-                } else if y < (height + 2) / 3 * 2 {
-                    unsafe { *data.offset((x + width * (height * 2 + y)) as isize) }
-                } else {
-                    unsafe { *data.offset((x + width * (height * 3 + y)) as isize) }
-                };
-                a as u8
-            };
-
-            img.put_pixel(
-                x as u32,
-                y as u32,
-                image::Rgba([r as u8, g as u8, b as u8, a]),
+        if payload.trailing() > 0 {
+            log::warn!(
+                "keeping {} byte(s) of non-Ogg data after the last page, use --trim-trailing to drop them",
+                payload.trailing()
             );
         }
-    }
-    img
+        payload.data
+    };
+    let extracted;
+    let data = match options.serial {
+        Some(serial) => {
+            let stream = ogg::logical_streams(ogg::valid_pages(data))
+                .into_iter()
+                .find(|s| s.serial == serial)
+                .ok_or_else(|| anyhow::anyhow!("no logical stream with serial {:08x}", serial))?;
+            extracted = ogg::extract_stream(data, serial, stream.link);
+            log::info!(
+                "keeping only the {} stream {:08x}",
+                stream.codec.name(),
+                serial
+            );
+            &extracted.data[..]
+        }
+        None => data,
+    };
+
+    std::fs::write(output.as_ref(), data).map_err(|e| Error::io(output.as_ref(), e))?;
+    log::info!(
+        "wrote {} Ogg page(s), {} bytes from file offset {:#x} to {}",
+        payload.pages,
+        data.len(),
+        payload.offset,
+        output.as_ref().display()
+    );
+    Ok(())
 }
 
-fn convert_embedded_ogv(
-    header: &OmvHeader,
-    ogv_content: &[u8],
+fn build_converter(
     output_format: OutputFormat,
     output: impl AsRef<Path>,
-) -> Result<()> {
-    let mut tf_cbs = tf_callbacks {
-        read_func: Some(read_func_impl),
-        seek_func: Some(seek_func_impl),
-        close_func: Some(close_func_impl),
-    };
-
-    let mut converter: Box<dyn Converter> = match output_format {
+    info: &VideoInfo,
+    header: &OmvHeader,
+    source_name: &str,
+    options: &ConvertOptions,
+) -> Result<Box<dyn Converter>> {
+    let converter: Box<dyn Converter> = match output_format {
         OutputFormat::H264 => {
             log::info!("Converting to H264");
-            let cvt = converter::H264Converter::new(output);
+            let cvt = converter::H264Converter::new(output)
+                .with_size_policy(options.odd_size)
+                .with_encoder_settings(options.encoder);
             Box::new(cvt)
         }
         OutputFormat::PngPicture => {
@@ -235,153 +182,718 @@ fn convert_embedded_ogv(
         }
         OutputFormat::Ffmpeg => {
             log::info!("Converting using Ffmpeg");
-            let cvt = converter::FfmepgConverter::new(output);
+            let cvt = converter::FfmepgConverter::new(output)
+                .with_size_policy(options.odd_size)
+                .with_encoder_settings(options.encoder);
             Box::new(cvt)
         }
         OutputFormat::PipedPng => {
-            log::info!("Converting to Piped PNG");
-            let cvt = converter::PipedPngConverter::new(output);
+            log::info!("Converting to Piped {:?}", options.piped_payload);
+            let cvt = converter::PipedPngConverter::new(output)
+                .with_payload(options.piped_payload)
+                .with_alpha(info.has_alpha)
+                .with_frame_count(header.metadata().frame_count)
+                .with_source_name(source_name);
+            Box::new(cvt)
+        }
+        OutputFormat::Framehash => {
+            log::info!("Hashing frames");
+            let cvt = converter::FrameHashConverter::new(output)
+                .with_channels(options.framehash_channels)
+                .with_planes(options.framehash_planes);
             Box::new(cvt)
         }
+        OutputFormat::Ogv => {
+            return Err(anyhow::anyhow!("ogv is not a frame output"));
+        }
     };
+    Ok(converter)
+}
 
-    let datasource = DataSource::new(ogv_content.to_vec());
-    let datasource_ptr =
-        &datasource as *const DataSource as *mut DataSource as *mut ::std::os::raw::c_void;
-
-    let layout = Layout::new::<OggTheora_File>();
-    let ptr = unsafe { std::alloc::alloc(layout) };
-    if ptr.is_null() {
-        return Err(anyhow::anyhow!("Failed to allocate memory"));
+fn convert_embedded_ogv(
+    mut reader: OmvReader,
+    source_name: &str,
+    output_format: OutputFormat,
+    output: impl AsRef<Path>,
+    options: &ConvertOptions,
+    progress: &mut Progress,
+) -> Result<()> {
+    let info = reader.info().clone();
+    let header = reader.header().clone();
+
+    let mut converter =
+        build_converter(output_format, output, &info, &header, source_name, options)?;
+    if let Some(matte) = &options.matte {
+        if !info.has_alpha {
+            log::warn!("the source is a 24bit video, the matte will be opaque");
+        }
+        if matte.kind == MatteKind::Alpha && !matches!(matte.format, OutputFormat::PngPicture) {
+            return Err(anyhow::anyhow!(
+                "an alpha matte needs png-picture, {:?} has no alpha channel",
+                matte.format
+            ));
+        }
+        log::info!(
+            "Writing a {:?} matte to {}",
+            matte.kind,
+            matte.output.display()
+        );
+        let matte_converter: Box<dyn Converter> = match matte.format {
+            OutputFormat::PngPicture => Box::new(
+                converter::PngConverter::new(&matte.output)
+                    .with_grayscale(matte.kind == MatteKind::Alpha),
+            ),
+            format => build_converter(format, &matte.output, &info, &header, source_name, options)?,
+        };
+        converter = Box::new(
+            converter::MatteConverter::new(converter, matte_converter)
+                .with_kind(matte.kind)
+                .with_colour(matte.colour),
+        );
     }
-
-    let ogg_file = ptr as *mut OggTheora_File;
-    let ret = unsafe { tf_open_callbacks(datasource_ptr, ogg_file, tf_cbs) };
-    if ret < 0 {
-        if !ptr.is_null() {
-            unsafe { std::alloc::dealloc(ptr, layout) };
+    match &options.background {
+        Some(background) => {
+            log::info!("Flattening frames onto {}", background);
+            converter = Box::new(converter::BackgroundConverter::new(
+                converter,
+                background.clone(),
+            ));
         }
-        return Err(anyhow::anyhow!("Failed to open OggTheora file"));
+        None if info.has_alpha
+            && options.matte.is_none()
+            && options.alpha_layout.is_none()
+            && matches!(
+                output_format,
+                OutputFormat::H264 | OutputFormat::JpgPicture | OutputFormat::Ffmpeg
+            ) =>
+        {
+            log::info!(
+                "{:?} drops alpha, use --background to flatten it",
+                output_format
+            );
+        }
+        None => {}
     }
-
-    let ret = unsafe { tf_hasvideo(ogg_file) };
-    if ret == 0 {
-        unsafe { tf_close(ogg_file) };
-        if !ptr.is_null() {
-            unsafe { std::alloc::dealloc(ptr, layout) };
+    if let Some(layout) = options.alpha_layout {
+        if !info.has_alpha {
+            log::warn!("the source is a 24bit video, the packed mask will be opaque");
         }
-        return Err(anyhow::anyhow!("No video stream found"));
+        let (width, height) = layout.packed_size(info.width, info.height);
+        log::info!(
+            "Packing alpha {:?} into {}x{} frames",
+            layout,
+            width,
+            height
+        );
+        converter = Box::new(converter::PackedAlphaConverter::new(converter, layout));
     }
 
-    let mut width: ::std::os::raw::c_int = 0;
-    let mut height: ::std::os::raw::c_int = 0;
-    let mut fps: f64 = 0.0;
-    let mut fmt: th_pixel_fmt = 0;
-    let mut frame_count = 0;
+    if !options.filters.is_empty() {
+        let mut chain = FilterChain::new();
+        for spec in &options.filters {
+            chain.extend_from_str(spec)?;
+        }
+        let (width, height) = chain.output_size(info.width, info.height)?;
+        log::info!(
+            "Filtering frames through {} filter(s) to {}x{}",
+            chain.len(),
+            width,
+            height
+        );
+        converter = Box::new(converter::FilteredConverter::new(converter, chain));
+    }
 
-    unsafe { tf_videoinfo(ogg_file, &mut width, &mut height, &mut fps, &mut fmt) };
+    converter.prepare(info.width, info.height, info.frame_rate)?;
 
-    if fmt != th_pixel_fmt_TH_PF_444 {
-        unsafe { tf_close(ogg_file) };
-        if !ptr.is_null() {
-            unsafe { std::alloc::dealloc(ptr, layout) };
+    while let Some(frame) = reader.read_frame()? {
+        if let Some(planes) = &frame.planes {
+            converter.convert_planes(planes, frame.index)?;
         }
-        return Err(anyhow::anyhow!("Unsupported pixel format"));
+        converter.convert_frame(frame.image, frame.index)?;
+        progress.advance();
     }
 
-    let video_height = height;
-    let mut is24bit = true;
-    if header.metadata.height != height as u32 {
-        is24bit = false;
-        height = header.metadata.height as i32;
-    }
+    converter.finish()?;
 
-    converter.prepare(width as u32, height as u32, fps as f32)?;
+    Ok(())
+}
 
-    let size = width as usize * video_height as usize * 3;
-    let alignment = 1024;
-    let mem_layout = unsafe { Layout::from_size_align_unchecked(size, alignment) };
+/// Where and how to write the alpha channel as its own output.
+#[derive(Debug, Clone)]
+struct MatteOptions {
+    output: PathBuf,
+    format: OutputFormat,
+    kind: MatteKind,
+    colour: MatteColour,
+}
 
-    let data_blob = unsafe { std::alloc::alloc(mem_layout) as *mut i8 };
+/// Settings that tune a conversion beyond the output format.
+#[derive(Debug, Clone, Default)]
+struct ConvertOptions {
+    piped_payload: PipedPayload,
+    on_error: OnError,
+    alpha_mode: AlphaMode,
+    trim_trailing: bool,
+    matte: Option<MatteOptions>,
+    alpha_layout: Option<AlphaLayout>,
+    background: Option<Background>,
+    filters: Vec<String>,
+    odd_size: SizePolicy,
+    encoder: EncoderSettings,
+    framehash_channels: bool,
+    framehash_planes: bool,
+    serial: Option<u32>,
+}
 
-    log::info!(
-        "width: {}, height: {}, fps: {}, fmt: {}",
-        width,
-        height,
-        fps,
-        fmt
-    );
+impl ConvertOptions {
+    /// Options for a job entry, with the command line defaults for what it leaves out.
+    fn from_settings(settings: &JobSettings) -> Result<Self> {
+        Ok(ConvertOptions {
+            piped_payload: settings.piped_payload.unwrap_or_default(),
+            on_error: settings.on_error.unwrap_or_default(),
+            alpha_mode: settings.alpha_mode.unwrap_or_default(),
+            trim_trailing: settings.trim_trailing.unwrap_or_default(),
+            matte: settings.matte.clone().map(|output| MatteOptions {
+                output,
+                format: settings.matte_format.unwrap_or(OutputFormat::PngPicture),
+                kind: settings.matte_kind.unwrap_or_default(),
+                colour: settings.matte_colour.unwrap_or_default(),
+            }),
+            alpha_layout: settings.alpha_layout,
+            background: settings.background()?,
+            filters: settings.filter.clone().unwrap_or_default(),
+            odd_size: settings.odd_size.unwrap_or_default(),
+            encoder: settings.encoder()?,
+            framehash_channels: settings.framehash_channels.unwrap_or_default(),
+            framehash_planes: settings.framehash_planes.unwrap_or_default(),
+            serial: settings.serial,
+        })
+    }
+}
 
-    if data_blob.is_null() {
-        unsafe { tf_close(ogg_file) };
-        if !ptr.is_null() {
-            unsafe { std::alloc::dealloc(ptr, layout) };
+/// Runs every conversion of a job file in order.
+fn run_job(path: impl AsRef<Path>, progress: &mut Progress) -> Result<()> {
+    let job = Job::load(path)?;
+    let entries = job.entries()?;
+    let mut failed = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        log::info!(
+            "[{}/{}] {} -> {}",
+            i + 1,
+            entries.len(),
+            entry.input.display(),
+            entry.output.display()
+        );
+        let result = ConvertOptions::from_settings(&entry.settings).and_then(|options| {
+            convert_file(
+                &entry.input,
+                entry.format,
+                &entry.output,
+                &options,
+                progress,
+            )
+        });
+        if let Err(e) = result {
+            let e = e.context(entry.input.display().to_string());
+            if !job.keep_going {
+                return Err(e);
+            }
+            log::error!("{:#}", e);
+            failed += 1;
         }
-        return Err(anyhow::anyhow!("Failed to allocate memory for video data"));
     }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} conversions failed",
+            failed,
+            entries.len()
+        ));
+    }
+    Ok(())
+}
 
-    loop {
-        if unsafe { tf_eos(ogg_file) } != 0 {
-            break;
-        }
-        // fill data_blob with 0
-        unsafe { std::ptr::write_bytes(data_blob as *mut u8, 0, size) };
-        unsafe { tf_readvideo(ogg_file, data_blob, 1) };
-        let frame_slice = unsafe { std::slice::from_raw_parts(data_blob as *const i8, size) };
-        let mut owned_buf = vec![0i8; size];
-        owned_buf.copy_from_slice(frame_slice);
-        let image = yuv_to_image(
-            owned_buf.as_mut_ptr(),
-            width,
-            height,
-            video_height,
-            is24bit,
-        );
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, required_unless_present = "config")]
+    input: Option<PathBuf>,
+
+    /// Output path. For piped-png and framehash: `-` for stdout, a file or named pipe, or
+    /// `unix:<socket path>`
+    #[arg(short, long, required_unless_present = "config")]
+    output: Option<PathBuf>,
+
+    #[arg(short, long, required_unless_present = "config")]
+    format: Option<OutputFormat>,
+
+    /// Run the conversions described in a TOML or JSON job file instead
+    #[arg(long, conflicts_with_all = [
+        "input", "output", "format", "piped_payload", "on_error", "alpha_mode", "trim_trailing",
+        "matte", "matte_format", "matte_kind", "matte_colour", "alpha_layout", "background",
+        "filter", "odd_size", "crf", "bitrate", "preset", "profile", "framehash_channels",
+        "framehash_planes", "serial",
+    ])]
+    config: Option<PathBuf>,
+
+    /// Per-frame payload of the piped stream
+    #[arg(long, default_value = "png")]
+    piped_payload: PipedPayload,
+
+    /// How to handle corrupt pages and frames that fail to decode
+    #[arg(long, value_enum, default_value_t = OnError::Stop)]
+    on_error: OnError,
+
+    /// Whether the video carries alpha bands: `auto` tells from the header and video
+    /// heights and refuses inconsistent geometry
+    #[arg(long, value_enum, default_value_t = AlphaMode::Auto)]
+    alpha_mode: AlphaMode,
+
+    /// For ogv: drop the bytes after the last complete Ogg page
+    #[arg(long)]
+    trim_trailing: bool,
+
+    /// Also write the alpha channel to this path, the colour output becomes opaque
+    #[arg(long)]
+    matte: Option<PathBuf>,
+
+    /// Format of the matte output
+    #[arg(long, default_value = "png-picture")]
+    matte_format: OutputFormat,
+
+    /// How the matte carries alpha
+    #[arg(long, value_enum, default_value_t = MatteKind::Luma)]
+    matte_kind: MatteKind,
+
+    /// Colour output that goes with the matte
+    #[arg(long, value_enum, default_value_t = MatteColour::Straight)]
+    matte_colour: MatteColour,
+
+    /// Pack colour and alpha into one opaque frame, for encoders without alpha
+    #[arg(long, value_enum, conflicts_with = "matte")]
+    alpha_layout: Option<AlphaLayout>,
+
+    /// Flatten alpha onto `checkerboard`, a colour (`black`, `#rrggbb`) or an image
+    #[arg(long, conflicts_with_all = ["matte", "alpha_layout"])]
+    background: Option<Background>,
+
+    /// Filter chain applied to every frame, e.g. `scale=1280:-1:lanczos,crop=1280:540`.
+    /// Filters: crop, scale, pad, hflip, vflip, rotate, premultiply, unpremultiply, adjust
+    #[arg(long)]
+    filter: Vec<String>,
+
+    /// For h264 and ffmpeg: fit odd frame sizes by repeating the edge, dropping it,
+    /// or refusing to encode
+    #[arg(long, value_enum, default_value_t = SizePolicy::Pad)]
+    odd_size: SizePolicy,
+
+    /// For ffmpeg: x264 constant rate factor, 0-51, lower is better [default: 18]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=51), conflicts_with = "bitrate")]
+    crf: Option<u8>,
+
+    /// For h264 and ffmpeg: target bitrate in bits per second, e.g. `2500k` or `4M`,
+    /// instead of the crf
+    #[arg(long)]
+    bitrate: Option<Bitrate>,
+
+    /// For ffmpeg: x264 speed preset, slower ones compress better
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
+    /// For ffmpeg: H.264 profile
+    #[arg(long, value_enum, default_value_t = H264Profile::Main)]
+    profile: H264Profile,
+
+    /// For framehash: also hash the R, G, B and A channels of every frame on their own
+    #[arg(long)]
+    framehash_channels: bool,
+
+    /// For framehash: also hash the Y, Cb and Cr planes the decoder produced, before
+    /// the colour and alpha are put together
+    #[arg(long)]
+    framehash_planes: bool,
+
+    /// Decode only the logical stream with this serial number (decimal or 0x hex),
+    /// instead of the first Theora stream of every chained segment. For ogv: copy
+    /// only this stream
+    #[arg(long, value_parser = decoder::parse_serial)]
+    serial: Option<u32>,
+
+    /// How to report progress: a bar on a terminal, or JSON events on stderr
+    #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+    progress: ProgressMode,
+
+    /// With `--progress json`, emit a frame event every this many frames
+    #[arg(long, default_value_t = 25, value_name = "FRAMES")]
+    progress_interval: u32,
+
+    /// Only log warnings and errors, and draw no progress bar
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more, `-vv` for every decoded frame
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Write log output to this file instead of stderr, so the standard streams
+    /// carry nothing but the piped protocol
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replace the video of an .omv with a new Ogg/Theora file, keeping its header
+    Repack {
+        /// The original .omv
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// The new Ogg/Theora stream
+        #[arg(long)]
+        ogv: PathBuf,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Frame height to record for a shader video, defaults to the original's
+        #[arg(long)]
+        height: Option<u32>,
+    },
+    /// Check .omv files for damage without writing anything, exits with 12 if any fails
+    Verify {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Only check the header and the Ogg pages, do not decode the frames
+        #[arg(long)]
+        no_decode: bool,
+
+        /// Check only the logical stream with this serial number, as `--serial`
+        /// decodes it
+        #[arg(long, value_parser = decoder::parse_serial)]
+        serial: Option<u32>,
+    },
+    /// List the Ogg pages and logical streams of an .omv
+    Inspect {
+        input: PathBuf,
+
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Measure PSNR and SSIM per channel between an .omv and a reference
+    Compare {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Another .omv, a directory of pictures, or a video ffmpeg can decode
+        #[arg(short, long)]
+        reference: PathBuf,
+
+        /// Where to write the scores, `-` for stdout
+        #[arg(short, long, default_value = "-")]
+        output: PathBuf,
+
+        #[arg(short, long, value_enum, default_value_t = ReportFormat::Csv)]
+        format: ReportFormat,
+    },
+    /// Export the header of an .omv as JSON, or write an edited one back
+    Header {
+        #[command(subcommand)]
+        action: HeaderAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HeaderAction {
+    /// Print the header as JSON
+    Dump { input: PathBuf },
+    /// Copy an .omv with its header replaced by a JSON one
+    Apply {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// The header as printed by `header dump`
+        #[arg(long)]
+        header: PathBuf,
+
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
 
-        converter.convert_frame(image, frame_count)?;
-        frame_count += 1;
-        log::info!("Decoded {} frame(s)", frame_count);
+fn repack_file(
+    input: impl AsRef<Path>,
+    ogv: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &RepackOptions,
+) -> Result<()> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| Error::io(path, e));
+    let original = read(input.as_ref())?;
+    let ogv = read(ogv.as_ref())?;
+    let repacked = repack::repack(&original, &ogv, options)?;
+    std::fs::write(output.as_ref(), repacked).map_err(|e| Error::io(output.as_ref(), e))?;
+    Ok(())
+}
+
+fn header_action(action: HeaderAction) -> Result<()> {
+    match action {
+        HeaderAction::Dump { input } => {
+            let mmap = map_file(&input)?;
+            let header = decoder::read_omv_header(mmap.as_ref())?;
+            let mut out = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut out, &header)?;
+            writeln!(out)?;
+            Ok(())
+        }
+        HeaderAction::Apply {
+            input,
+            header,
+            output,
+        } => {
+            let json = std::fs::read(&header).map_err(|e| Error::io(&header, e))?;
+            let header: OmvHeader = serde_json::from_slice(&json)
+                .map_err(|e| anyhow::anyhow!("invalid header in {}: {}", header.display(), e))?;
+            let original = std::fs::read(&input).map_err(|e| Error::io(&input, e))?;
+            let edited = repack::replace_header(&original, &header)?;
+            std::fs::write(&output, edited).map_err(|e| Error::io(&output, e))?;
+            Ok(())
+        }
     }
+}
 
-    unsafe { tf_close(ogg_file) };
-    if !ptr.is_null() {
-        unsafe { std::alloc::dealloc(ptr, layout) };
+/// Verifies every file and prints a report for each on stdout.
+fn verify_files(files: &[PathBuf], options: &VerifyOptions, progress: &mut Progress) -> Result<()> {
+    let mut failed = 0;
+    for path in files {
+        let report = map_file(path).map(|mmap| {
+            let mem = mmap.as_ref();
+            let total = decoder::read_omv_header(mem)
+                .map(|header| header.metadata().frame_count)
+                .unwrap_or(0);
+            progress.begin(&path.display().to_string(), total);
+            let report = verify::verify(mem, options, progress);
+            progress.finish();
+            report
+        });
+        match report {
+            Ok(report) => {
+                let passed = report.passed();
+                println!(
+                    "{} {}",
+                    if passed { "PASS" } else { "FAIL" },
+                    path.display()
+                );
+                for check in &report.checks {
+                    println!("  {:4}  {:<12} {}", check.status, check.name, check.detail);
+                }
+                if !passed {
+                    failed += 1;
+                }
+            }
+            Err(e) => {
+                println!("FAIL {}", path.display());
+                println!("  {:4}  {:<12} {:#}", Status::Fail, "open", e);
+                failed += 1;
+            }
+        }
     }
-    if !data_blob.is_null() {
-        unsafe { std::alloc::dealloc(data_blob as *mut u8, mem_layout) };
+    println!("{} of {} file(s) passed", files.len() - failed, files.len());
+    if failed > 0 {
+        return Err(Error::VerificationFailed {
+            failed,
+            total: files.len(),
+        }
+        .into());
     }
-
-    converter.finish()?;
-
     Ok(())
 }
 
-#[derive(Debug, clap::ValueEnum, Clone, Default, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum OutputFormat {
-    H264,
-    PngPicture,
-    #[default]
-    JpgPicture,
-    Ffmpeg,
-    PipedPng,
+/// Decodes `input` and scores it against `reference`.
+fn compare_file(
+    input: &Path,
+    reference: &Path,
+    output: &Path,
+    format: ReportFormat,
+    progress: &mut Progress,
+) -> Result<()> {
+    let mmap = map_file(input)?;
+    let reader = OmvReader::open(mmap.as_ref(), DecodeOptions::default())?;
+    let info = reader.info().clone();
+    let total = reader.header().metadata().frame_count;
+    let frames: compare::Frames = Box::new(reader.map(|frame| frame.map(|frame| frame.image)));
+    let reference = Reference::from_path(reference);
+    log::info!("Comparing {} with {:?}", input.display(), reference);
+    let reference = reference.frames(info.width, info.height)?;
+
+    progress.begin(&input.display().to_string(), total);
+    let comparison = compare::compare(frames, reference, progress);
+    match &comparison {
+        Ok(_) => progress.finish(),
+        Err(e) => progress.fail(e),
+    }
+    let comparison = comparison?;
+    let summary = &comparison.summary;
+    log::info!(
+        "{} frame(s), PSNR r {:.2} g {:.2} b {:.2} a {:.2} dB, SSIM r {:.4} g {:.4} b {:.4} a {:.4}",
+        summary.frames,
+        summary.psnr.r,
+        summary.psnr.g,
+        summary.psnr.b,
+        summary.psnr.a,
+        summary.ssim.r,
+        summary.ssim.g,
+        summary.ssim.b,
+        summary.ssim.a
+    );
+    let mut out = PipedOutput::from_path(output).open()?;
+    comparison.write(format, &mut out)
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(short, long)]
-    input: PathBuf,
-
-    #[arg(short, long)]
-    output: PathBuf,
+fn init_logger(
+    log_file: Option<&Path>,
+    level: Option<LevelFilter>,
+    multi: Option<MultiProgress>,
+) -> Result<()> {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    // never log to stdout, it may carry the piped stream
+    builder.target(env_logger::Target::Stderr);
+    if let Some(level) = level {
+        builder.filter_level(level);
+    }
+    if let Some(path) = log_file {
+        let file = File::create(path).map_err(|e| Error::io(path, e))?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+    let logger = builder.build();
+    let max_level = logger.filter();
+    match multi {
+        // print log lines above the progress bar instead of through it
+        Some(multi) if log_file.is_none() => LogWrapper::new(multi, logger).try_init()?,
+        _ => log::set_boxed_logger(Box::new(logger))?,
+    }
+    log::set_max_level(max_level);
+    Ok(())
+}
 
-    #[arg(short, long)]
-    format: OutputFormat,
+fn log_level(quiet: bool, verbose: u8) -> Option<LevelFilter> {
+    match (quiet, verbose) {
+        (true, _) => Some(LevelFilter::Warn),
+        (false, 0) => None,
+        (false, 1) => Some(LevelFilter::Debug),
+        (false, _) => Some(LevelFilter::Trace),
+    }
 }
 
 fn main() {
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
     let args = Args::parse();
-    convert_file(args.input, args.format, args.output).unwrap();
+    let log_to_file = args.log_file.is_some();
+    if let Err(e) = run(args) {
+        if log_to_file {
+            log::error!("{:#}", e);
+        }
+        eprintln!("error: {:#}", e);
+        std::process::exit(error::exit_code(&e));
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let progress_mode = match args.progress {
+        ProgressMode::Auto if !args.quiet && std::io::stderr().is_terminal() => ProgressMode::Bar,
+        ProgressMode::Auto => ProgressMode::None,
+        mode => mode,
+    };
+    let multi = (progress_mode == ProgressMode::Bar).then(MultiProgress::new);
+    init_logger(
+        args.log_file.as_deref(),
+        log_level(args.quiet, args.verbose),
+        multi.clone(),
+    )?;
+    let mut progress = match (progress_mode, multi) {
+        (ProgressMode::Bar, Some(multi)) => Progress::bar(multi),
+        (ProgressMode::Json, _) => Progress::json(std::io::stderr(), args.progress_interval),
+        _ => Progress::hidden(),
+    };
+    if let Some(config) = args.config {
+        return run_job(config, &mut progress);
+    }
+    match args.command {
+        Some(Command::Repack {
+            input,
+            ogv,
+            output,
+            height,
+        }) => {
+            let options = RepackOptions { height };
+            repack_file(input, ogv, output, &options)
+        }
+        Some(Command::Verify {
+            files,
+            no_decode,
+            serial,
+        }) => {
+            let options = VerifyOptions {
+                decode: !no_decode,
+                serial,
+            };
+            verify_files(&files, &options, &mut progress)
+        }
+        Some(Command::Inspect { input, json }) => {
+            let mmap = map_file(&input)?;
+            let inspection = inspect::inspect(mmap.as_ref())?;
+            let mut out = std::io::stdout().lock();
+            if json {
+                serde_json::to_writer_pretty(&mut out, &inspection)?;
+                writeln!(out)?;
+                Ok(())
+            } else {
+                inspection.write_text(&mut out)
+            }
+        }
+        Some(Command::Compare {
+            input,
+            reference,
+            output,
+            format,
+        }) => compare_file(&input, &reference, &output, format, &mut progress),
+        Some(Command::Header { action }) => header_action(action),
+        None => {
+            let options = ConvertOptions {
+                piped_payload: args.piped_payload,
+                on_error: args.on_error,
+                alpha_mode: args.alpha_mode,
+                trim_trailing: args.trim_trailing,
+                matte: args.matte.map(|output| MatteOptions {
+                    output,
+                    format: args.matte_format,
+                    kind: args.matte_kind,
+                    colour: args.matte_colour,
+                }),
+                alpha_layout: args.alpha_layout,
+                background: args.background,
+                filters: args.filter,
+                odd_size: args.odd_size,
+                encoder: EncoderSettings {
+                    crf: args.crf,
+                    bitrate: args.bitrate,
+                    preset: args.preset,
+                    profile: args.profile,
+                },
+                framehash_channels: args.framehash_channels,
+                framehash_planes: args.framehash_planes,
+                serial: args.serial,
+            };
+            // clap enforces these without a subcommand
+            let (Some(input), Some(output), Some(format)) = (args.input, args.output, args.format)
+            else {
+                unreachable!()
+            };
+            convert_file(input, format, output, &options, &mut progress)
+        }
+    }
 }
@@ -0,0 +1,711 @@
+//! Minimal Ogg page parser for the payload embedded in OMV files.
+//!
+//! theorafile (via libogg) quietly skips pages it cannot use. This module walks the
+//! same bytes up front so damage can be reported with offsets, and so the decoder
+//! can be fed only pages that passed their checksum.
+
+use std::fmt;
+use std::ops::Range;
+
+pub const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const HEADER_LEN: usize = 27;
+
+const FLAG_CONTINUED: u8 = 0x01;
+const FLAG_BOS: u8 = 0x02;
+const FLAG_EOS: u8 = 0x04;
+
+/// One page, borrowing from the scanned buffer.
+#[derive(Debug, Clone)]
+pub struct Page<'a> {
+    /// Offset of the capture pattern in the scanned buffer.
+    pub offset: usize,
+    pub header_type: u8,
+    pub granule_position: i64,
+    pub serial: u32,
+    pub sequence: u32,
+    pub checksum: u32,
+    pub lacing: &'a [u8],
+    pub body: &'a [u8],
+    /// Whole page, header included.
+    pub raw: &'a [u8],
+}
+
+impl Page<'_> {
+    pub fn is_continued(&self) -> bool {
+        self.header_type & FLAG_CONTINUED != 0
+    }
+
+    pub fn is_bos(&self) -> bool {
+        self.header_type & FLAG_BOS != 0
+    }
+
+    pub fn is_eos(&self) -> bool {
+        self.header_type & FLAG_EOS != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Packet fragments on this page as `(size, completed)`. The last fragment is
+    /// not completed when the packet continues on the next page.
+    pub fn fragments(&self) -> Vec<(usize, bool)> {
+        let mut fragments = Vec::new();
+        let mut size = 0;
+        for &lace in self.lacing {
+            size += lace as usize;
+            if lace < 255 {
+                fragments.push((size, true));
+                size = 0;
+            }
+        }
+        if self.lacing.last() == Some(&255) {
+            fragments.push((size, false));
+        }
+        fragments
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageError {
+    /// The buffer ends inside the page.
+    Truncated,
+    /// No capture pattern at the page offset.
+    NoCapturePattern,
+    /// Stream structure version other than 0.
+    BadVersion(u8),
+    BadChecksum {
+        stored: u32,
+        computed: u32,
+    },
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageError::Truncated => write!(f, "truncated page"),
+            PageError::NoCapturePattern => write!(f, "no capture pattern"),
+            PageError::BadVersion(v) => write!(f, "unsupported page version {}", v),
+            PageError::BadChecksum { stored, computed } => write!(
+                f,
+                "checksum mismatch (stored {:08x}, computed {:08x})",
+                stored, computed
+            ),
+        }
+    }
+}
+
+/// Parses the page starting at `offset`, where the capture pattern should be.
+pub fn parse_page(data: &[u8], offset: usize) -> Result<Page<'_>, PageError> {
+    let page = parse_page_unchecked(data, offset)?;
+    let computed = page_checksum(page.raw);
+    if page.checksum != computed {
+        return Err(PageError::BadChecksum {
+            stored: page.checksum,
+            computed,
+        });
+    }
+    Ok(page)
+}
+
+/// Like [`parse_page`], but keeps a page whose checksum does not match, for
+/// tools that list damaged pages rather than skip them.
+pub fn parse_page_unchecked(data: &[u8], offset: usize) -> Result<Page<'_>, PageError> {
+    let rest = data.get(offset..).ok_or(PageError::Truncated)?;
+    if rest.len() < HEADER_LEN {
+        return Err(PageError::Truncated);
+    }
+    if &rest[..4] != CAPTURE_PATTERN {
+        return Err(PageError::NoCapturePattern);
+    }
+    if rest[4] != 0 {
+        return Err(PageError::BadVersion(rest[4]));
+    }
+
+    let segments = rest[26] as usize;
+    let header_len = HEADER_LEN + segments;
+    if rest.len() < header_len {
+        return Err(PageError::Truncated);
+    }
+    let lacing = &rest[HEADER_LEN..header_len];
+    let body_len: usize = lacing.iter().map(|&l| l as usize).sum();
+    if rest.len() < header_len + body_len {
+        return Err(PageError::Truncated);
+    }
+    let raw = &rest[..header_len + body_len];
+
+    Ok(Page {
+        offset,
+        header_type: raw[5],
+        granule_position: i64::from_le_bytes(raw[6..14].try_into().unwrap()),
+        serial: u32::from_le_bytes(raw[14..18].try_into().unwrap()),
+        sequence: u32::from_le_bytes(raw[18..22].try_into().unwrap()),
+        checksum: u32::from_le_bytes(raw[22..26].try_into().unwrap()),
+        lacing,
+        body: &raw[header_len..],
+        raw,
+    })
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut r = (i as u32) << 24;
+        let mut j = 0;
+        while j < 8 {
+            r = if r & 0x8000_0000 != 0 {
+                (r << 1) ^ 0x04c1_1db7
+            } else {
+                r << 1
+            };
+            j += 1;
+        }
+        table[i] = r;
+        i += 1;
+    }
+    table
+};
+
+/// Ogg CRC-32 of a whole page, computed with the checksum field zeroed.
+pub fn page_checksum(raw: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for (i, &b) in raw.iter().enumerate() {
+        let b = if (22..26).contains(&i) { 0 } else { b };
+        crc = (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize];
+    }
+    crc
+}
+
+/// A byte range that could not be used as a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    pub offset: usize,
+    pub len: usize,
+    /// Why the page at `offset` was rejected, `None` for bytes between pages.
+    pub error: Option<PageError>,
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            Some(e) => write!(f, "{} at offset {:#x} ({} bytes)", e, self.offset, self.len),
+            None => write!(
+                f,
+                "{} bytes of non-Ogg data at offset {:#x}",
+                self.len, self.offset
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ScanItem<'a> {
+    Page(Page<'a>),
+    Damaged(Damage),
+    /// Bytes after the last page that contain no further capture pattern.
+    Trailing {
+        offset: usize,
+        len: usize,
+    },
+}
+
+/// Walks a buffer page by page, resynchronising on the next capture pattern
+/// after anything that is not a valid page.
+pub struct PageScanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PageScanner<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn find_capture(&self, from: usize) -> Option<usize> {
+        self.data
+            .get(from..)?
+            .windows(CAPTURE_PATTERN.len())
+            .position(|w| w == CAPTURE_PATTERN)
+            .map(|i| i + from)
+    }
+}
+
+impl<'a> Iterator for PageScanner<'a> {
+    type Item = ScanItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.pos;
+        if start >= self.data.len() {
+            return None;
+        }
+
+        if !self.data[start..].starts_with(CAPTURE_PATTERN) {
+            return Some(match self.find_capture(start + 1) {
+                Some(next) => {
+                    self.pos = next;
+                    ScanItem::Damaged(Damage {
+                        offset: start,
+                        len: next - start,
+                        error: None,
+                    })
+                }
+                None => {
+                    self.pos = self.data.len();
+                    ScanItem::Trailing {
+                        offset: start,
+                        len: self.data.len() - start,
+                    }
+                }
+            });
+        }
+
+        match parse_page(self.data, start) {
+            Ok(page) => {
+                self.pos = start + page.len();
+                Some(ScanItem::Page(page))
+            }
+            Err(error) => {
+                let next = self.find_capture(start + 1).unwrap_or(self.data.len());
+                self.pos = next;
+                Some(ScanItem::Damaged(Damage {
+                    offset: start,
+                    len: next - start,
+                    error: Some(error),
+                }))
+            }
+        }
+    }
+}
+
+/// The pages of `data` that parse and pass their checksum.
+pub fn valid_pages(data: &[u8]) -> impl Iterator<Item = Page<'_>> {
+    PageScanner::new(data).filter_map(|item| match item {
+        ScanItem::Page(page) => Some(page),
+        _ => None,
+    })
+}
+
+/// Valid pages of a payload, concatenated, plus what had to be dropped.
+#[derive(Debug, Clone, Default)]
+pub struct Repaired {
+    pub data: Vec<u8>,
+    pub damage: Vec<Damage>,
+    /// Length of the non-Ogg tail after the last page.
+    pub trailing: usize,
+    /// `(offset in data, offset in source)` of every kept page.
+    page_map: Vec<(usize, usize)>,
+}
+
+impl Repaired {
+    /// The pages that do not start inside `skip`, a range of offsets in `data`.
+    /// Offsets keep mapping back to the scanned buffer.
+    pub fn without(&self, skip: Range<usize>) -> Repaired {
+        let mut kept = Repaired::default();
+        for (i, &(offset, source)) in self.page_map.iter().enumerate() {
+            if skip.contains(&offset) {
+                continue;
+            }
+            let end = self
+                .page_map
+                .get(i + 1)
+                .map_or(self.data.len(), |next| next.0);
+            kept.page_map.push((kept.data.len(), source));
+            kept.data.extend_from_slice(&self.data[offset..end]);
+        }
+        kept
+    }
+
+    /// Maps an offset in `data` back to the scanned buffer.
+    pub fn source_offset(&self, offset: usize) -> usize {
+        let i = self.page_map.partition_point(|&(kept, _)| kept <= offset);
+        match i.checked_sub(1) {
+            Some(i) => self.page_map[i].1 + (offset - self.page_map[i].0),
+            None => offset,
+        }
+    }
+}
+
+/// Keeps the pages that parse and pass their checksum, dropping everything else.
+pub fn repair(data: &[u8]) -> Repaired {
+    let mut repaired = Repaired {
+        data: Vec::with_capacity(data.len()),
+        ..Default::default()
+    };
+    for item in PageScanner::new(data) {
+        match item {
+            ScanItem::Page(page) => {
+                repaired.page_map.push((repaired.data.len(), page.offset));
+                repaired.data.extend_from_slice(page.raw);
+            }
+            ScanItem::Damaged(damage) => repaired.damage.push(damage),
+            ScanItem::Trailing { len, .. } => repaired.trailing = len,
+        }
+    }
+    repaired
+}
+
+/// A logical stream of the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogicalStream {
+    pub serial: u32,
+    pub codec: Codec,
+    /// Zero-based link of a chained stream the stream belongs to.
+    pub link: usize,
+    pub pages: usize,
+    /// Whether the stream ends with an EOS page.
+    pub has_eos: bool,
+}
+
+/// Counts the links of a chained physical stream: a BOS page that follows data
+/// pages starts a new one.
+#[derive(Debug, Default)]
+pub(crate) struct Links {
+    link: usize,
+    in_data: bool,
+}
+
+impl Links {
+    /// The link `page` belongs to, counting it in.
+    pub(crate) fn of_page(&mut self, page: &Page<'_>) -> usize {
+        if page.is_bos() {
+            if self.in_data {
+                self.link += 1;
+            }
+            self.in_data = false;
+        } else {
+            self.in_data = true;
+        }
+        self.link
+    }
+
+    /// The link the last page counted belongs to.
+    pub(crate) fn current(&self) -> usize {
+        self.link
+    }
+}
+
+/// Lists the logical streams of `pages` in the order they begin.
+pub fn logical_streams<'a>(pages: impl IntoIterator<Item = Page<'a>>) -> Vec<LogicalStream> {
+    let mut streams: Vec<LogicalStream> = Vec::new();
+    let mut links = Links::default();
+    for page in pages {
+        let link = links.of_page(&page);
+        if page.is_bos()
+            && !streams
+                .iter()
+                .any(|s| s.serial == page.serial && s.link == link)
+        {
+            streams.push(LogicalStream {
+                serial: page.serial,
+                codec: Codec::of_page(&page),
+                link,
+                pages: 0,
+                has_eos: false,
+            });
+        }
+        if let Some(stream) = streams
+            .iter_mut()
+            .find(|s| s.serial == page.serial && s.link == link)
+        {
+            stream.pages += 1;
+            stream.has_eos |= page.is_eos();
+        }
+    }
+    streams
+}
+
+/// The pages of one logical stream of `data`, the `serial` stream of chain link
+/// `link`. Damaged pages and garbage between pages are left out.
+pub fn extract_stream(data: &[u8], serial: u32, link: usize) -> Repaired {
+    let mut extracted = Repaired::default();
+    let mut links = Links::default();
+    for page in valid_pages(data) {
+        if links.of_page(&page) == link && page.serial == serial {
+            extracted.page_map.push((extracted.data.len(), page.offset));
+            extracted.data.extend_from_slice(page.raw);
+        }
+    }
+    extracted
+}
+
+/// Fields of the Theora identification header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TheoraInfo {
+    pub serial: u32,
+    pub version: (u8, u8, u8),
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub pic_width: u32,
+    pub pic_height: u32,
+    pub pic_x: u32,
+    pub pic_y: u32,
+    pub fps_num: u32,
+    pub fps_den: u32,
+    pub keyframe_granule_shift: u8,
+    /// 0 = 4:2:0, 2 = 4:2:2, 3 = 4:4:4.
+    pub pixel_format: u8,
+}
+
+impl TheoraInfo {
+    pub fn parse(serial: u32, packet: &[u8]) -> Option<Self> {
+        if packet.len() < 42 || &packet[..7] != b"\x80theora" {
+            return None;
+        }
+        let be24 = |i: usize| u32::from_be_bytes([0, packet[i], packet[i + 1], packet[i + 2]]);
+        let be32 = |i: usize| u32::from_be_bytes(packet[i..i + 4].try_into().unwrap());
+        let bits = u16::from_be_bytes([packet[40], packet[41]]);
+        Some(Self {
+            serial,
+            version: (packet[7], packet[8], packet[9]),
+            frame_width: u16::from_be_bytes([packet[10], packet[11]]) as u32 * 16,
+            frame_height: u16::from_be_bytes([packet[12], packet[13]]) as u32 * 16,
+            pic_width: be24(14),
+            pic_height: be24(17),
+            pic_x: packet[20] as u32,
+            pic_y: packet[21] as u32,
+            fps_num: be32(22),
+            fps_den: be32(26),
+            keyframe_granule_shift: ((bits >> 5) & 0x1f) as u8,
+            pixel_format: ((bits >> 3) & 0x3) as u8,
+        })
+    }
+
+    /// Zero-based index of the keyframe a granule position counts from.
+    pub fn granule_keyframe(&self, granule_position: i64) -> Option<u64> {
+        if granule_position < 0 {
+            return None;
+        }
+        let keyframe = granule_position as u64 >> self.keyframe_granule_shift;
+        let base = (self.version >= (3, 2, 1)) as u64;
+        Some(keyframe.saturating_sub(base))
+    }
+
+    /// Zero-based index of the frame a granule position ends at.
+    pub fn granule_frame(&self, granule_position: i64) -> Option<u64> {
+        if granule_position < 0 {
+            return None;
+        }
+        let gp = granule_position as u64;
+        let shift = self.keyframe_granule_shift as u32;
+        let frames = (gp >> shift) + (gp & ((1u64 << shift) - 1));
+        // streams from 3.2.1 on count frames from one
+        let base = (self.version >= (3, 2, 1)) as u64;
+        Some(frames.saturating_sub(base))
+    }
+}
+
+/// What a logical stream carries, from the first packet of its BOS page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Codec {
+    Theora(TheoraInfo),
+    Vorbis {
+        channels: u8,
+        sample_rate: u32,
+    },
+    /// Ogg Skeleton, an index of the other streams.
+    Skeleton,
+    Unknown,
+}
+
+impl Codec {
+    pub fn identify(serial: u32, packet: &[u8]) -> Self {
+        if let Some(info) = TheoraInfo::parse(serial, packet) {
+            Codec::Theora(info)
+        } else if packet.len() >= 16 && packet.starts_with(b"\x01vorbis") {
+            Codec::Vorbis {
+                channels: packet[11],
+                sample_rate: u32::from_le_bytes(packet[12..16].try_into().unwrap()),
+            }
+        } else if packet.starts_with(b"fishead\0") {
+            Codec::Skeleton
+        } else {
+            Codec::Unknown
+        }
+    }
+
+    /// Identifies the stream a BOS page starts.
+    pub fn of_page(page: &Page<'_>) -> Self {
+        let first = page.fragments().first().map_or(0, |f| f.0);
+        Self::identify(page.serial, &page.body[..first])
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Theora(_) => "theora",
+            Codec::Vorbis { .. } => "vorbis",
+            Codec::Skeleton => "skeleton",
+            Codec::Unknown => "unknown",
+        }
+    }
+
+    /// Playback time of `granule_position`, for codecs whose granules count time.
+    pub fn granule_time(&self, granule_position: i64) -> Option<f64> {
+        match self {
+            // granule 0 of a 3.2.1 stream is before the first frame
+            Codec::Theora(info)
+                if info.fps_num > 0 && (granule_position > 0 || info.version < (3, 2, 1)) =>
+            {
+                let frames = info.granule_frame(granule_position)? + 1;
+                Some(frames as f64 * info.fps_den as f64 / info.fps_num as f64)
+            }
+            Codec::Vorbis { sample_rate, .. } if *sample_rate > 0 && granule_position >= 0 => {
+                Some(granule_position as f64 / *sample_rate as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A Theora data packet (headers excluded), in decode order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketInfo {
+    /// Offset of the page the packet starts on.
+    pub page_offset: usize,
+    pub size: usize,
+    /// Presentation index derived from granule positions.
+    pub frame: u64,
+    /// Whether the packet holds an intra frame, where decoding can start.
+    pub keyframe: bool,
+}
+
+/// Theora stream layout, as libogg will hand it to the decoder.
+#[derive(Debug, Clone)]
+pub struct TheoraStream {
+    pub info: TheoraInfo,
+    /// Header packets found, 3 in a stream that can be decoded.
+    pub headers: usize,
+    pub packets: Vec<PacketInfo>,
+    /// Whether the stream ends with an EOS page.
+    pub has_eos: bool,
+}
+
+impl TheoraStream {
+    /// Collects the first Theora stream out of `pages`. Packets broken by a missing
+    /// page are dropped, matching what libogg delivers to the decoder.
+    pub fn collect<'a>(pages: impl IntoIterator<Item = Page<'a>>) -> Option<Self> {
+        let mut info: Option<TheoraInfo> = None;
+        let mut headers = 0;
+        let mut packets: Vec<PacketInfo> = Vec::new();
+        // (start page offset, size, first byte) of the packet being assembled
+        let mut partial: Option<(usize, usize, Option<u8>)> = None;
+        let mut next_sequence = None;
+        let mut has_eos = false;
+        // packet index and frame of the last packet finished on a page with a granule
+        let mut anchors: Vec<(usize, u64)> = Vec::new();
+
+        for page in pages {
+            let serial = match &info {
+                Some(info) => info.serial,
+                None if page.is_bos() => {
+                    let first = page.fragments().first().map_or(0, |f| f.0);
+                    match TheoraInfo::parse(page.serial, &page.body[..first]) {
+                        Some(parsed) => {
+                            info = Some(parsed);
+                            page.serial
+                        }
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
+            if page.serial != serial {
+                continue;
+            }
+
+            let mut fragments = page.fragments().into_iter();
+            // body offset of the next fragment
+            let mut pos = 0;
+            if next_sequence.is_some_and(|seq| seq != page.sequence) {
+                // a page is missing: libogg discards the broken packet
+                partial = None;
+                if page.is_continued() {
+                    pos += fragments.next().map_or(0, |f| f.0);
+                }
+            } else if !page.is_continued() {
+                partial = None;
+            }
+            next_sequence = Some(page.sequence.wrapping_add(1));
+            has_eos |= page.is_eos();
+
+            let mut finished_on_page = false;
+            for (size, completed) in fragments {
+                let first = page.body.get(pos).copied().filter(|_| size > 0);
+                pos += size;
+                let (start, total, head) = match partial.take() {
+                    Some((start, so_far, head)) => (start, so_far + size, head),
+                    None => (page.offset, size, first),
+                };
+                if !completed {
+                    partial = Some((start, total, head));
+                    continue;
+                }
+                if headers < 3 {
+                    headers += 1;
+                    continue;
+                }
+                packets.push(PacketInfo {
+                    page_offset: start,
+                    size: total,
+                    frame: 0,
+                    // a data packet with the frame type bit clear
+                    keyframe: head.is_some_and(|byte| byte & 0xc0 == 0),
+                });
+                finished_on_page = true;
+            }
+
+            if finished_on_page {
+                let frame = info.as_ref().unwrap().granule_frame(page.granule_position);
+                if let Some(frame) = frame {
+                    anchors.push((packets.len() - 1, frame));
+                }
+            }
+        }
+
+        let info = info?;
+        assign_frames(&mut packets, &anchors);
+        Some(Self {
+            info,
+            headers,
+            packets,
+            has_eos,
+        })
+    }
+
+    /// Frames the granule positions account for, gaps included.
+    pub fn frame_span(&self) -> u64 {
+        self.packets.last().map_or(0, |p| p.frame + 1)
+    }
+}
+
+fn assign_frames(packets: &mut [PacketInfo], anchors: &[(usize, u64)]) {
+    let Some(&(last_anchor, _)) = anchors.last() else {
+        for (i, packet) in packets.iter_mut().enumerate() {
+            packet.frame = i as u64;
+        }
+        return;
+    };
+
+    // packets up to the last anchor count back from the anchor that follows them
+    let mut anchors = anchors.iter().rev().peekable();
+    let mut frame = 0;
+    for i in (0..=last_anchor).rev() {
+        frame = match anchors.peek() {
+            Some(&&(index, anchor)) if index == i => {
+                anchors.next();
+                anchor
+            }
+            _ => frame.saturating_sub(1),
+        };
+        packets[i].frame = frame;
+    }
+
+    // packets after it count forward
+    for i in last_anchor + 1..packets.len() {
+        packets[i].frame = packets[i - 1].frame + 1;
+    }
+}
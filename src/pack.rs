@@ -1,26 +1,143 @@
-use binrw::{prelude::*, Endian};
+use binrw::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(BinRead, BinWrite, Debug)]
+use crate::frame_rate::FrameRate;
+
+/// Header of an OMV file, by layout version. Read it with
+/// [`read_omv_header`](crate::decoder::read_omv_header), which picks the layout.
+///
+/// Serializes to an object tagged with its `layout`, bytes of unknown meaning as
+/// hex strings, so a header can be edited as JSON and written back unchanged
+/// otherwise.
+#[derive(BinWrite, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[bw(little)]
+#[serde(tag = "layout", rename_all = "lowercase")]
+pub enum OmvHeader {
+    /// `major_version` 1, the layout of every release seen so far. Unknown
+    /// versions are read with it too, on a best-effort basis.
+    V1(OmvHeaderV1),
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[brw(little)]
-pub struct OmvHeader {
+#[serde(deny_unknown_fields)]
+pub struct OmvHeaderV1 {
     offset: u32,
-    major_version: u8,
-    minor_version: u8,
+    pub major_version: u8,
+    pub minor_version: u8,
+    #[serde(with = "hex_bytes")]
     padding: [u8; 2],
     // padding to 0x2c
+    #[serde(with = "hex_bytes")]
     padding2: [u8; 0x24],
     pub metadata: OmvMetadata,
 }
 
-#[derive(BinRead, BinWrite, Debug)]
+impl OmvHeaderV1 {
+    /// Bytes the header takes up.
+    pub const SIZE: usize = 0x4c;
+}
+
+#[derive(BinRead, BinWrite, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[brw(little)]
+#[serde(deny_unknown_fields)]
 pub struct OmvMetadata {
     pub width: u32,
     pub height: u32,
-    frame_time: u32,
-    stream_id: u32,
-    stream_id2: u32,
-    unknown: u32,
-    data_pack_count: u32,
-    frame_count: u32,
+    /// Duration of a frame in whole microseconds, rounded down: 33333 at 30 fps,
+    /// 41708 at 24000/1001.
+    pub frame_time: u32,
+    pub stream_id: u32,
+    pub stream_id2: u32,
+    pub unknown: u32,
+    pub data_pack_count: u32,
+    pub frame_count: u32,
+}
+
+impl OmvMetadata {
+    /// The `frame_time` of a stream at `rate`.
+    pub fn frame_time_for(rate: FrameRate) -> u32 {
+        let micros = 1_000_000 * rate.den() as u64 / rate.num() as u64;
+        micros.min(u32::MAX as u64) as u32
+    }
+}
+
+/// Where a header version keeps the alpha channel of shader videos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaBands {
+    /// One band below each of the B, G and R planes, holding the top, middle and
+    /// bottom third of the alpha rows.
+    PerPlane,
+}
+
+impl OmvHeader {
+    /// Major versions with a known layout.
+    pub const KNOWN_VERSIONS: &'static [u8] = &[1];
+
+    pub fn metadata(&self) -> &OmvMetadata {
+        match self {
+            OmvHeader::V1(header) => &header.metadata,
+        }
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut OmvMetadata {
+        match self {
+            OmvHeader::V1(header) => &mut header.metadata,
+        }
+    }
+
+    /// `(major, minor)` as stored in the file.
+    pub fn version(&self) -> (u8, u8) {
+        match self {
+            OmvHeader::V1(header) => (header.major_version, header.minor_version),
+        }
+    }
+
+    pub fn alpha_bands(&self) -> AlphaBands {
+        match self {
+            OmvHeader::V1(_) => AlphaBands::PerPlane,
+        }
+    }
+
+    /// The header as it is stored in the file.
+    pub fn to_bytes(&self) -> BinResult<Vec<u8>> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        self.write(&mut out)?;
+        Ok(out.into_inner())
+    }
+}
+
+/// A byte array as a hex string, for bytes whose meaning is unknown. Whitespace
+/// is ignored when reading, so long strings can be grouped by hand.
+mod hex_bytes {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let hex: String = String::deserialize(deserializer)?
+            .split_whitespace()
+            .collect();
+        if hex.len() != N * 2 || !hex.is_ascii() {
+            return Err(D::Error::custom(format!(
+                "expected {} hex digits, got {:?}",
+                N * 2,
+                hex
+            )));
+        }
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(D::Error::custom)?;
+        }
+        Ok(bytes)
+    }
 }
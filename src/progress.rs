@@ -0,0 +1,235 @@
+//! Conversion progress: a terminal progress bar, or JSON events for programs
+//! that drive the converter.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::time::Instant;
+
+/// How progress is reported.
+#[derive(Debug, clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProgressMode {
+    /// A bar when stderr is a terminal, nothing otherwise.
+    #[default]
+    Auto,
+    Bar,
+    /// One JSON object per line on stderr.
+    Json,
+    None,
+}
+
+/// One line of `--progress json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ProgressEvent {
+    /// A file is opened; `total` is the frame count from its metadata.
+    Start {
+        file: String,
+        total: Option<u64>,
+    },
+    /// `frame` frames are done, at `fps` frames per second over `elapsed` seconds.
+    Frame {
+        file: String,
+        frame: u64,
+        total: Option<u64>,
+        fps: f64,
+        elapsed: f64,
+        eta: Option<f64>,
+    },
+    Finish {
+        file: String,
+        frames: u64,
+        fps: f64,
+        elapsed: f64,
+    },
+    Error {
+        file: String,
+        message: String,
+    },
+}
+
+enum Output {
+    Hidden,
+    Bar(MultiProgress),
+    Json {
+        writer: Box<dyn Write + Send>,
+        interval: u64,
+    },
+}
+
+struct Current {
+    file: String,
+    total: Option<u64>,
+    frames: u64,
+    started: Instant,
+    bar: Option<ProgressBar>,
+}
+
+impl Current {
+    fn elapsed(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    fn fps(&self) -> f64 {
+        let elapsed = self.elapsed();
+        if elapsed > 0.0 {
+            self.frames as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn eta(&self) -> Option<f64> {
+        let left = self.total?.checked_sub(self.frames)?;
+        let fps = self.fps();
+        (fps > 0.0).then(|| left as f64 / fps)
+    }
+}
+
+/// Tracks the file being converted and reports its frames.
+pub struct Progress {
+    output: Output,
+    current: Option<Current>,
+}
+
+impl Progress {
+    /// Reports nothing but the summary line of each file in the log.
+    pub fn hidden() -> Self {
+        Self {
+            output: Output::Hidden,
+            current: None,
+        }
+    }
+
+    /// Draws a bar for each file in `multi`, which the logger should also print
+    /// through so log lines do not tear the bar.
+    pub fn bar(multi: MultiProgress) -> Self {
+        Self {
+            output: Output::Bar(multi),
+            current: None,
+        }
+    }
+
+    /// Writes a [`ProgressEvent`] per line to `writer`: at the start and end of
+    /// each file, and every `interval` frames.
+    pub fn json(writer: impl Write + Send + 'static, interval: u32) -> Self {
+        Self {
+            output: Output::Json {
+                writer: Box::new(writer),
+                interval: interval.max(1) as u64,
+            },
+            current: None,
+        }
+    }
+
+    /// Starts a file of `total` frames, 0 if unknown.
+    pub fn begin(&mut self, file: &str, total: u32) {
+        let total = (total > 0).then_some(total as u64);
+        let bar = match &self.output {
+            Output::Bar(multi) => {
+                let bar = match total {
+                    Some(total) => ProgressBar::new(total).with_style(
+                        ProgressStyle::with_template(
+                            "{msg} [{bar:40}] {pos}/{len} frames, {per_sec}, ETA {eta}",
+                        )
+                        .unwrap()
+                        .progress_chars("=> "),
+                    ),
+                    None => ProgressBar::new_spinner().with_style(
+                        ProgressStyle::with_template("{spinner} {msg} {pos} frames, {per_sec}")
+                            .unwrap(),
+                    ),
+                };
+                Some(multi.add(bar.with_message(file.to_string())))
+            }
+            _ => None,
+        };
+        self.current = Some(Current {
+            file: file.to_string(),
+            total,
+            frames: 0,
+            started: Instant::now(),
+            bar,
+        });
+        self.emit(ProgressEvent::Start {
+            file: file.to_string(),
+            total,
+        });
+    }
+
+    /// Counts one more frame of the current file.
+    pub fn advance(&mut self) {
+        let Some(current) = &mut self.current else {
+            return;
+        };
+        current.frames += 1;
+        if let Some(bar) = &current.bar {
+            bar.inc(1);
+        }
+        let due = match &self.output {
+            Output::Json { interval, .. } => current.frames % interval == 0,
+            _ => false,
+        };
+        if due {
+            let event = ProgressEvent::Frame {
+                file: current.file.clone(),
+                frame: current.frames,
+                total: current.total,
+                fps: current.fps(),
+                elapsed: current.elapsed(),
+                eta: current.eta(),
+            };
+            self.emit(event);
+        }
+    }
+
+    /// Ends the current file.
+    pub fn finish(&mut self) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        if let Some(bar) = &current.bar {
+            bar.finish_and_clear();
+        }
+        // ogv extraction copies pages and counts no frames
+        if current.frames > 0 {
+            log::info!(
+                "{}: {} frame(s) in {:.1}s, {:.1} fps",
+                current.file,
+                current.frames,
+                current.elapsed(),
+                current.fps()
+            );
+        }
+        self.emit(ProgressEvent::Finish {
+            file: current.file.clone(),
+            frames: current.frames,
+            fps: current.fps(),
+            elapsed: current.elapsed(),
+        });
+    }
+
+    /// Ends the current file with an error.
+    pub fn fail(&mut self, error: &anyhow::Error) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        if let Some(bar) = &current.bar {
+            bar.abandon();
+        }
+        self.emit(ProgressEvent::Error {
+            file: current.file,
+            message: format!("{:#}", error),
+        });
+    }
+
+    fn emit(&mut self, event: ProgressEvent) {
+        if let Output::Json { writer, .. } = &mut self.output {
+            // progress is best effort, a closed stderr must not stop the conversion
+            if let Ok(line) = serde_json::to_string(&event) {
+                let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+            }
+        }
+    }
+}
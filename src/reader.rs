@@ -0,0 +1,341 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageFormat, RgbImage, RgbaImage};
+use std::io::{BufRead, Read};
+
+/// Parsed `PXY4M` header line plus the `META` lines that follow it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipedStreamHeader {
+    /// Protocol version, `1` for streams without a `V` token.
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    pub fps_num: u32,
+    pub fps_den: u32,
+    /// Channel layout of the payloads, `rgba` or `rgb`.
+    pub channels: String,
+    /// Payload encoding, `raw`, `qoi` or `png`.
+    pub encoding: String,
+    /// `META` records in stream order.
+    pub meta: Vec<(String, String)>,
+}
+
+impl PipedStreamHeader {
+    pub fn fps(&self) -> f64 {
+        self.fps_num as f64 / self.fps_den.max(1) as f64
+    }
+
+    /// Value of the first `META` record named `key`.
+    pub fn meta(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether the source carries alpha. Version 1 streams do not say, so assume it does.
+    pub fn has_alpha(&self) -> bool {
+        self.meta("ALPHA").map(|v| v == "1").unwrap_or(true)
+    }
+
+    pub fn frame_count(&self) -> Option<u32> {
+        self.meta("FRAMES").and_then(|v| v.parse().ok())
+    }
+
+    pub fn source_name(&self) -> Option<&str> {
+        self.meta("SOURCE")
+    }
+
+    /// Bytes of one frame of raw pixels.
+    pub fn raw_frame_len(&self) -> u64 {
+        let channels = if self.channels == "rgb" { 3 } else { 4 };
+        self.width as u64 * self.height as u64 * channels
+    }
+
+    /// Largest payload a frame may have: the raw size for `raw`, twice that plus
+    /// 1 MiB for QOI and PNG, far above what either encoder produces.
+    pub fn max_payload_len(&self) -> u64 {
+        match self.encoding.as_str() {
+            "raw" => self.raw_frame_len(),
+            _ => self
+                .raw_frame_len()
+                .saturating_mul(2)
+                .saturating_add(1 << 20),
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let mut tokens = line.split(' ');
+        if tokens.next() != Some("PXY4M") {
+            return Err(anyhow!("not a PXY4M stream: {:?}", line));
+        }
+
+        let mut header = Self {
+            version: 1,
+            width: 0,
+            height: 0,
+            fps_num: 0,
+            fps_den: 1,
+            channels: "rgba".to_string(),
+            encoding: "png".to_string(),
+            meta: Vec::new(),
+        };
+        for token in tokens {
+            if let Some(enc) = token.strip_prefix("Enc:") {
+                header.encoding = enc.to_string();
+                continue;
+            }
+            let (tag, value) = token.split_at(token.chars().next().map_or(0, |c| c.len_utf8()));
+            match tag {
+                "V" => header.version = parse_number(value, "version")?,
+                "W" => header.width = parse_number(value, "width")?,
+                "H" => header.height = parse_number(value, "height")?,
+                "F" => {
+                    let (num, den) = value
+                        .split_once('/')
+                        .ok_or_else(|| anyhow!("invalid frame rate: {:?}", value))?;
+                    header.fps_num = parse_number(num, "frame rate")?;
+                    header.fps_den = parse_number(den, "frame rate")?;
+                }
+                "C" => header.channels = value.to_string(),
+                // unknown tokens are reserved for future versions
+                _ => {}
+            }
+        }
+
+        if header.width == 0 || header.height == 0 {
+            return Err(anyhow!("invalid frame size in header: {:?}", line));
+        }
+        if header.channels != "rgba" && header.channels != "rgb" {
+            return Err(anyhow!("unsupported channel layout: {}", header.channels));
+        }
+        if !matches!(header.encoding.as_str(), "raw" | "qoi" | "png") {
+            return Err(anyhow!("unsupported payload encoding: {}", header.encoding));
+        }
+        Ok(header)
+    }
+}
+
+/// One decoded frame of the piped stream.
+#[derive(Debug, Clone)]
+pub struct PipedFrame {
+    pub pts: u64,
+    /// Frame pixels, RGB payloads are expanded with opaque alpha.
+    pub image: RgbaImage,
+}
+
+/// Reads the `PXY4M` stream written by `PipedPngConverter`.
+///
+/// Iterating yields frames until the `END` trailer. On version 2 streams, reaching
+/// EOF before the trailer yields an error, so a truncated stream never looks finished.
+pub struct PipedStreamReader<R: BufRead> {
+    inner: R,
+    header: PipedStreamHeader,
+    /// First control line after the metadata, already consumed from `inner`.
+    pending: Option<String>,
+    frames_read: u32,
+    end_frames: Option<u32>,
+    failed: bool,
+}
+
+impl<R: BufRead> PipedStreamReader<R> {
+    /// Reads the header and metadata lines from `inner`.
+    pub fn new(mut inner: R) -> Result<Self> {
+        let line = read_line(&mut inner)?.ok_or_else(|| anyhow!("empty stream"))?;
+        let mut header = PipedStreamHeader::parse(&line)?;
+
+        let mut pending = None;
+        while let Some(line) = read_line(&mut inner)? {
+            match line.strip_prefix("META ") {
+                Some(record) => {
+                    let (key, value) = record.split_once(' ').unwrap_or((record, ""));
+                    header.meta.push((key.to_string(), value.to_string()));
+                }
+                None => {
+                    pending = Some(line);
+                    break;
+                }
+            }
+        }
+
+        Ok(Self {
+            inner,
+            header,
+            pending,
+            frames_read: 0,
+            end_frames: None,
+            failed: false,
+        })
+    }
+
+    pub fn header(&self) -> &PipedStreamHeader {
+        &self.header
+    }
+
+    /// Frame total from the `END` trailer, `None` until it has been read.
+    pub fn end_frames(&self) -> Option<u32> {
+        self.end_frames
+    }
+
+    /// Whether the `END` trailer has been read.
+    pub fn is_finished(&self) -> bool {
+        self.end_frames.is_some()
+    }
+
+    /// Reads the next frame, `Ok(None)` after the `END` trailer.
+    pub fn read_frame(&mut self) -> Result<Option<PipedFrame>> {
+        if self.end_frames.is_some() {
+            return Ok(None);
+        }
+
+        let line = match self.pending.take() {
+            Some(line) => Some(line),
+            None => read_line(&mut self.inner)?,
+        };
+        if line.is_none() && self.header.version < 2 {
+            // version 1 has no trailer, EOF at a frame boundary is the end
+            self.end_frames = Some(self.frames_read);
+            return Ok(None);
+        }
+        let line = line.ok_or_else(|| {
+            anyhow!(
+                "stream truncated after {} frame(s): missing END trailer",
+                self.frames_read
+            )
+        })?;
+
+        if let Some(rest) = line.strip_prefix("END") {
+            let total = match rest.trim().strip_prefix("FRAMES") {
+                Some(n) => parse_number(n.trim(), "frame total")?,
+                // accept a bare END without a total
+                None => self.frames_read,
+            };
+            if total != self.frames_read {
+                return Err(anyhow!(
+                    "END announces {} frame(s) but {} were read",
+                    total,
+                    self.frames_read
+                ));
+            }
+            self.end_frames = Some(total);
+            return Ok(None);
+        }
+
+        if line != "FRAME" {
+            return Err(anyhow!("expected FRAME, got {:?}", line));
+        }
+        let pts = self.read_field("PTS")?;
+        let len: u64 = self.read_field("BYTES")?;
+        let expected = self.header.max_payload_len();
+        if self.header.encoding == "raw" && len != expected {
+            return Err(anyhow!(
+                "raw payload of frame {} is {} bytes, {}x{} {} needs {}",
+                self.frames_read,
+                len,
+                self.header.width,
+                self.header.height,
+                self.header.channels,
+                expected
+            ));
+        }
+        if len > expected {
+            return Err(anyhow!(
+                "payload of frame {} is {} bytes, more than {} allowed for {}x{}",
+                self.frames_read,
+                len,
+                expected,
+                self.header.width,
+                self.header.height
+            ));
+        }
+
+        // grows with the data actually read, not with what BYTES claims
+        let mut payload = Vec::new();
+        (&mut self.inner).take(len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < len {
+            return Err(anyhow!(
+                "stream truncated in payload of frame {}: {} of {} bytes",
+                self.frames_read,
+                payload.len(),
+                len
+            ));
+        }
+
+        let image = self.decode_payload(payload)?;
+        self.frames_read += 1;
+        Ok(Some(PipedFrame { pts, image }))
+    }
+
+    fn read_field<T: std::str::FromStr>(&mut self, name: &str) -> Result<T> {
+        let line = read_line(&mut self.inner)?
+            .ok_or_else(|| anyhow!("stream truncated: missing {} line", name))?;
+        let value = line
+            .strip_prefix(name)
+            .and_then(|v| v.strip_prefix(' '))
+            .ok_or_else(|| anyhow!("expected {}, got {:?}", name, line))?;
+        parse_number(value, name)
+    }
+
+    fn decode_payload(&self, payload: Vec<u8>) -> Result<RgbaImage> {
+        let (width, height) = (self.header.width, self.header.height);
+        let image = match self.header.encoding.as_str() {
+            "raw" if self.header.channels == "rgba" => {
+                RgbaImage::from_raw(width, height, payload).map(DynamicImage::ImageRgba8)
+            }
+            "raw" => RgbImage::from_raw(width, height, payload).map(DynamicImage::ImageRgb8),
+            "qoi" => Some(image::load_from_memory_with_format(
+                &payload,
+                ImageFormat::Qoi,
+            )?),
+            _ => Some(image::load_from_memory_with_format(
+                &payload,
+                ImageFormat::Png,
+            )?),
+        }
+        .ok_or_else(|| anyhow!("raw payload does not match {}x{}", width, height))?;
+
+        if image.width() != width || image.height() != height {
+            return Err(anyhow!(
+                "frame size mismatch: expected {}x{}, got {}x{}",
+                width,
+                height,
+                image.width(),
+                image.height()
+            ));
+        }
+        Ok(image.into_rgba8())
+    }
+}
+
+impl<R: BufRead> Iterator for PipedStreamReader<R> {
+    type Item = Result<PipedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let frame = self.read_frame().transpose();
+        if let Some(Err(_)) = frame {
+            // the stream cannot be resynchronised after a framing error
+            self.failed = true;
+        }
+        frame
+    }
+}
+
+fn read_line(inner: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    if inner.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(anyhow!("stream truncated inside a control line"));
+    }
+    line.pop();
+    Ok(Some(String::from_utf8(line)?))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, what: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid {}: {:?}", what, value))
+}
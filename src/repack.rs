@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+
+use crate::decoder::{self, OggPayload};
+use crate::error::Error;
+use crate::frame_rate::FrameRate;
+use crate::ogg;
+use crate::pack::{OmvHeader, OmvMetadata};
+
+#[derive(Debug, Clone, Default)]
+pub struct RepackOptions {
+    /// Frame height to record for a shader video. Defaults to the height of the
+    /// original when it had alpha bands.
+    pub height: Option<u32>,
+}
+
+/// A scanned Ogg stream and the first Theora stream in it.
+struct Scanned {
+    pages: usize,
+    stream: ogg::TheoraStream,
+}
+
+fn scan(data: &[u8]) -> Result<Scanned> {
+    let mut pages = 0;
+    let stream =
+        ogg::TheoraStream::collect(ogg::PageScanner::new(data).filter_map(|item| match item {
+            ogg::ScanItem::Page(page) => {
+                pages += 1;
+                Some(page)
+            }
+            _ => None,
+        }))
+        .ok_or(Error::NoVideoStream)?;
+    Ok(Scanned { pages, stream })
+}
+
+/// Replaces the Ogg payload of the OMV file `original` with `ogv`.
+///
+/// Every byte before the payload is kept, `padding2` included, and the metadata
+/// fields that describe the stream are updated to match the new one.
+pub fn repack(original: &[u8], ogv: &[u8], options: &RepackOptions) -> Result<Vec<u8>> {
+    let mut header = decoder::read_omv_header(original)?;
+    let old_payload = OggPayload::locate(original)?;
+    let old = scan(old_payload.data)?;
+    log::info!("repacking {:?}", header);
+
+    let new_payload = ogg::repair(ogv);
+    if let Some(damage) = new_payload.damage.first() {
+        return Err(Error::CorruptStream(format!("the new stream is corrupt: {}", damage)).into());
+    }
+    if new_payload.trailing > 0 {
+        return Err(anyhow!(
+            "the new stream has {} byte(s) of non-Ogg data after its last page",
+            new_payload.trailing
+        ));
+    }
+    let new = scan(ogv)?;
+    if !new.stream.has_eos {
+        log::warn!("the new stream has no end-of-stream page");
+    }
+
+    let old_info = &old.stream.info;
+    let info = &new.stream.info;
+    if info.pixel_format != old_info.pixel_format {
+        return Err(anyhow!(
+            "the new stream uses pixel format {}, the original {}",
+            decoder::pixel_format_name(info.pixel_format.into()),
+            decoder::pixel_format_name(old_info.pixel_format.into())
+        ));
+    }
+    let rate = FrameRate::new(info.fps_num, info.fps_den)
+        .map_err(|_| anyhow!("invalid frame rate in the Theora header"))?;
+
+    let metadata = header.metadata_mut();
+    let had_alpha = metadata.height != old_info.pic_height;
+    let height = match options.height {
+        Some(height) => height,
+        None if had_alpha => metadata.height,
+        None => info.pic_height,
+    };
+    let has_alpha = height != info.pic_height;
+    decoder::check_geometry(height, info.pic_height, has_alpha)?;
+    if has_alpha != had_alpha {
+        log::warn!(
+            "the original {} alpha bands but the new stream {}",
+            if had_alpha { "has" } else { "has no" },
+            if has_alpha { "has" } else { "has none" }
+        );
+    }
+
+    let frame_count = new.stream.frame_span() as u32;
+
+    let old_frames = old.stream.frame_span() as u32;
+    if metadata.data_pack_count == old.pages as u32 {
+        metadata.data_pack_count = new.pages as u32;
+    } else if metadata.data_pack_count == old_frames {
+        metadata.data_pack_count = frame_count;
+    } else {
+        log::warn!(
+            "keeping data_pack_count {}, it matches neither the page nor the frame count of the original",
+            metadata.data_pack_count
+        );
+    }
+    metadata.width = info.pic_width;
+    metadata.height = height;
+    metadata.frame_time = OmvMetadata::frame_time_for(rate);
+    metadata.frame_count = frame_count;
+
+    let mut out = write_header(&header, &original[..old_payload.offset])?;
+    out.extend_from_slice(ogv);
+    log::info!("repacked {:?}", header);
+    Ok(out)
+}
+
+/// Writes `header` over the header of the OMV file `original`, keeping every
+/// other byte, for headers edited by hand.
+pub fn replace_header(original: &[u8], header: &OmvHeader) -> Result<Vec<u8>> {
+    let old = decoder::read_omv_header(original)?;
+    if old.version() != header.version() {
+        log::warn!(
+            "changing the header version from {}.{} to {}.{}",
+            old.version().0,
+            old.version().1,
+            header.version().0,
+            header.version().1
+        );
+    }
+    let offset = decoder::find_payload(original)?;
+    let mut out = write_header(header, &original[..offset])?;
+    out.extend_from_slice(&original[offset..]);
+    log::info!("wrote {:?}", header);
+    Ok(out)
+}
+
+/// `header` followed by the bytes of `prefix`, everything before the Ogg
+/// payload, that it does not cover.
+fn write_header(header: &OmvHeader, prefix: &[u8]) -> Result<Vec<u8>> {
+    let mut out = header.to_bytes()?;
+    if out.len() > prefix.len() {
+        return Err(anyhow!(
+            "the Ogg payload starts at {:#x}, inside the OMV header",
+            prefix.len()
+        ));
+    }
+    out.extend_from_slice(&prefix[out.len()..]);
+    Ok(out)
+}
@@ -0,0 +1,128 @@
+//! Keyframe index for random access: where decoding can restart for a given
+//! frame, optionally cached in a sidecar file next to the .omv.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::ogg;
+
+/// A packet decoding can restart at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Frame index over all decoded streams.
+    pub frame: u64,
+    /// Index into [`KeyframeIndex::streams`].
+    pub stream: usize,
+    /// Index of the packet among the data packets of its stream.
+    pub packet: usize,
+}
+
+/// A decoded Theora stream, in chain order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedStream {
+    pub serial: u32,
+    pub link: usize,
+    /// Index of the stream's frame 0 over all decoded streams.
+    pub first_frame: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyframeIndex {
+    /// Length of the Ogg pages the index was built from, to tell a stale sidecar.
+    pub payload_len: usize,
+    /// [`fingerprint`](Self::fingerprint) of those pages, for a payload of the
+    /// same length with other content.
+    pub fingerprint: String,
+    pub streams: Vec<IndexedStream>,
+    /// Frames over all streams.
+    pub frames: u64,
+    /// Sorted by frame. The first packet of every stream is included, a fresh
+    /// decoder starts there whatever the frame type.
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl KeyframeIndex {
+    /// Scans `streams` out of `data`, the valid pages of the payload.
+    pub fn build(data: &[u8], streams: &[ogg::LogicalStream]) -> Self {
+        let mut index = Self {
+            payload_len: data.len(),
+            fingerprint: Self::fingerprint(data),
+            streams: Vec::new(),
+            frames: 0,
+            keyframes: Vec::new(),
+        };
+        for (i, stream) in streams.iter().enumerate() {
+            index.streams.push(IndexedStream {
+                serial: stream.serial,
+                link: stream.link,
+                first_frame: index.frames,
+            });
+            let pages = ogg::extract_stream(data, stream.serial, stream.link);
+            let Some(theora) = ogg::TheoraStream::collect(ogg::valid_pages(&pages.data)) else {
+                continue;
+            };
+            for (packet, info) in theora.packets.iter().enumerate() {
+                if packet == 0 || info.keyframe {
+                    index.keyframes.push(Keyframe {
+                        frame: index.frames + info.frame,
+                        stream: i,
+                        packet,
+                    });
+                }
+            }
+            index.frames += theora.frame_span();
+        }
+        index
+    }
+
+    /// The last keyframe at or before `frame`.
+    pub fn keyframe_before(&self, frame: u64) -> Option<&Keyframe> {
+        let i = self.keyframes.partition_point(|key| key.frame <= frame);
+        self.keyframes.get(i.checked_sub(1)?)
+    }
+
+    /// MD5 of the checksums of the pages in `data`, which tells payloads apart
+    /// without hashing every byte.
+    pub fn fingerprint(data: &[u8]) -> String {
+        let mut context = md5::Context::new();
+        for page in ogg::valid_pages(data) {
+            context.consume(page.checksum.to_le_bytes());
+        }
+        format!("{:x}", context.compute())
+    }
+
+    /// Whether the index was built for `streams` of the valid pages `data`.
+    pub fn matches(&self, data: &[u8], streams: &[ogg::LogicalStream]) -> bool {
+        self.payload_len == data.len()
+            && self.streams.len() == streams.len()
+            && self
+                .streams
+                .iter()
+                .zip(streams)
+                .all(|(a, b)| a.serial == b.serial && a.link == b.link)
+            && self.fingerprint == Self::fingerprint(data)
+    }
+
+    /// Where the index of `omv` is cached: `<omv>.keyframes.json`.
+    pub fn sidecar_path(omv: impl AsRef<Path>) -> PathBuf {
+        let mut path = omv.as_ref().as_os_str().to_owned();
+        path.push(".keyframes.json");
+        PathBuf::from(path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+        serde_json::from_slice(&json)
+            .map_err(|e| anyhow!("invalid keyframe index {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec(self)?;
+        std::fs::write(path, json).map_err(|e| Error::io(path, e))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Integrity checks for shipped OMV files: the header against the stream, Ogg
+//! checksums, alpha band geometry, and optionally a full decode.
+
+use std::fmt;
+
+use crate::decoder::{self, AlphaMode, DecodeOptions, OggPayload, OmvReader, OnError};
+use crate::frame_rate::FrameRate;
+use crate::ogg;
+use crate::pack::OmvMetadata;
+use crate::progress::Progress;
+
+/// Theora pixel format code of 4:4:4, the only one OMV files use.
+const PIXEL_FORMAT_444: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Suspicious but playable, does not fail the file.
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        f.pad(name)
+    }
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Every check run on one file, in order. Checks that depend on a failed one
+/// are left out.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != Status::Fail)
+    }
+
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|c| c.name == name)
+    }
+
+    fn push(&mut self, name: &'static str, status: Status, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            status,
+            detail: detail.into(),
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Decode every frame, not just the headers and pages.
+    pub decode: bool,
+    /// Check the logical stream with this serial number instead of the first
+    /// Theora stream of every chain link, as `--serial` decodes it.
+    pub serial: Option<u32>,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            decode: true,
+            serial: None,
+        }
+    }
+}
+
+/// Checks the OMV file held in `mem`. Decoded frames are reported to `progress`.
+pub fn verify(mem: &[u8], options: &VerifyOptions, progress: &mut Progress) -> Report {
+    let mut report = Report::default();
+
+    let header = match decoder::read_omv_header(mem) {
+        Ok(header) => header,
+        Err(e) => {
+            report.push("header", Status::Fail, format!("{:#}", e));
+            return report;
+        }
+    };
+    let metadata = header.metadata();
+    report.push(
+        "header",
+        Status::Pass,
+        format!(
+            "{}x{}, {} frame(s)",
+            metadata.width, metadata.height, metadata.frame_count
+        ),
+    );
+
+    let payload = match OggPayload::locate(mem) {
+        Ok(payload) => payload,
+        Err(e) => {
+            report.push("pages", Status::Fail, format!("{:#}", e));
+            return report;
+        }
+    };
+    if let Some(first) = payload.damage.first() {
+        report.push(
+            "pages",
+            Status::Fail,
+            format!(
+                "{} damaged range(s), the first at file offset {:#x}: {}",
+                payload.damage.len(),
+                payload.offset + first.offset,
+                first
+            ),
+        );
+    } else if payload.trailing() > 0 {
+        report.push(
+            "pages",
+            Status::Warn,
+            format!(
+                "{} page(s), {} byte(s) of non-Ogg data after the last one",
+                payload.pages,
+                payload.trailing()
+            ),
+        );
+    } else {
+        report.push(
+            "pages",
+            Status::Pass,
+            format!("{} page(s), all checksums valid", payload.pages),
+        );
+    }
+
+    // the streams the decoder picks, one per link of a chained payload
+    let streams = ogg::logical_streams(ogg::valid_pages(payload.data));
+    let links = match decoder::select_streams(&streams, options.serial) {
+        Ok(selected) => selected
+            .iter()
+            .filter_map(|s| {
+                let pages = ogg::extract_stream(payload.data, s.serial, s.link);
+                ogg::TheoraStream::collect(ogg::valid_pages(&pages.data))
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            report.push("stream", Status::Fail, format!("{:#}", e));
+            return report;
+        }
+    };
+    let Some(stream) = links.first() else {
+        report.push("stream", Status::Fail, "no Theora video stream found");
+        return report;
+    };
+    let info = &stream.info;
+    let odd_format = links
+        .iter()
+        .find(|link| link.info.pixel_format != PIXEL_FORMAT_444);
+    let odd_size = links.iter().enumerate().find(|(_, link)| {
+        (link.info.pic_width, link.info.pic_height) != (info.pic_width, info.pic_height)
+    });
+    if let Some(link) = odd_format {
+        report.push(
+            "stream",
+            Status::Fail,
+            format!(
+                "pixel format {}, OMV videos are 4:4:4",
+                decoder::pixel_format_name(link.info.pixel_format.into())
+            ),
+        );
+    } else if let Some((i, link)) = odd_size {
+        report.push(
+            "stream",
+            Status::Fail,
+            format!(
+                "link {} is {}x{}, the first one {}x{}",
+                i, link.info.pic_width, link.info.pic_height, info.pic_width, info.pic_height
+            ),
+        );
+    } else if links.iter().any(|link| !link.has_eos) {
+        report.push(
+            "stream",
+            Status::Fail,
+            "no end-of-stream page, the file is truncated",
+        );
+    } else {
+        let packets: usize = links.iter().map(|link| link.packets.len()).sum();
+        let mut detail = format!(
+            "Theora {}x{} at {}/{} fps, {} packet(s)",
+            info.pic_width, info.pic_height, info.fps_num, info.fps_den, packets
+        );
+        if links.len() > 1 {
+            detail += &format!(" in {} chained links", links.len());
+        }
+        report.push("stream", Status::Pass, detail);
+    }
+
+    let alpha = decoder::detect_alpha(AlphaMode::Auto, metadata.height, info.pic_height);
+    if metadata.width != info.pic_width {
+        report.push(
+            "geometry",
+            Status::Fail,
+            format!(
+                "header width {}, video width {}",
+                metadata.width, info.pic_width
+            ),
+        );
+    } else if let Err(e) = &alpha {
+        report.push("geometry", Status::Fail, format!("{:#}", e));
+    } else if matches!(alpha, Ok((_, true))) {
+        report.push(
+            "geometry",
+            Status::Pass,
+            format!(
+                "{} rows with alpha bands in {} video rows",
+                metadata.height, info.pic_height
+            ),
+        );
+    } else {
+        report.push("geometry", Status::Pass, "no alpha bands");
+    }
+
+    let frames: u64 = links.iter().map(ogg::TheoraStream::frame_span).sum();
+    let status = if frames == metadata.frame_count as u64 {
+        Status::Pass
+    } else {
+        Status::Fail
+    };
+    report.push(
+        "frame count",
+        status,
+        format!("header {}, stream {}", metadata.frame_count, frames),
+    );
+
+    if let Ok(rate) = FrameRate::new(info.fps_num, info.fps_den) {
+        let expected = OmvMetadata::frame_time_for(rate);
+        // allow for encoders that round differently
+        let difference = metadata.frame_time.abs_diff(expected);
+        let status = if difference <= 1 + expected / 1000 {
+            Status::Pass
+        } else {
+            Status::Fail
+        };
+        report.push(
+            "frame time",
+            status,
+            format!(
+                "header {}, stream {} microseconds",
+                metadata.frame_time, expected
+            ),
+        );
+    } else {
+        report.push("frame time", Status::Fail, "the stream has no frame rate");
+    }
+
+    let packs = metadata.data_pack_count as u64;
+    if packs != payload.pages as u64 && packs != frames {
+        report.push(
+            "data packs",
+            Status::Warn,
+            format!(
+                "data_pack_count {} matches neither the page count {} nor the frame count {}",
+                packs, payload.pages, frames
+            ),
+        );
+    }
+
+    if options.decode {
+        let decoded = decode_all(mem, options.serial, progress);
+        let (status, detail) = match decoded {
+            Ok(count) if count == metadata.frame_count => {
+                (Status::Pass, format!("{} frame(s)", count))
+            }
+            Ok(count) => (
+                Status::Fail,
+                format!("{} of {} frame(s) decoded", count, metadata.frame_count),
+            ),
+            Err((count, e)) => (Status::Fail, format!("{:#} after {} frame(s)", e, count)),
+        };
+        report.push("decode", status, detail);
+    }
+    report
+}
+
+/// Decodes every frame, returning the frame count or where decoding failed.
+fn decode_all(
+    mem: &[u8],
+    serial: Option<u32>,
+    progress: &mut Progress,
+) -> Result<u32, (u32, anyhow::Error)> {
+    let options = DecodeOptions {
+        on_error: OnError::Stop,
+        serial,
+        ..Default::default()
+    };
+    let mut reader = OmvReader::open(mem, options).map_err(|e| (0, e))?;
+    loop {
+        match reader.read_frame() {
+            Ok(Some(_)) => progress.advance(),
+            Ok(None) => return Ok(reader.frames_read()),
+            Err(e) => return Err((reader.frames_read(), e)),
+        }
+    }
+}
@@ -1,76 +1,36 @@
-import subprocess
-import sys
 import argparse
-from PIL import Image
-import io
-
-
-def read_exact(stream, n):
-    buf = bytearray()
-    while len(buf) < n:
-        chunk = stream.read(n - len(buf))
-        if not chunk:
-            # EOF
-            return None
-        buf.extend(chunk)
-    return bytes(buf)
-
+import os
+import sys
 
-def run_decoder(input_file):
-    cmd = [
-        "/Users/xmoe/Downloads/omvdecoder/target/release/omvdecoder", 
-        "--input",
-        input_file,
-        "--output",
-        "dummy",
-        "--format",
-        "piped-png"
-        ]
+sys.path.insert(0, os.path.join(os.path.dirname(os.path.abspath(__file__)), "python"))
 
-    proc = subprocess.Popen(
-        cmd,
-        stdout=subprocess.PIPE,
-        stderr=subprocess.PIPE,
-        bufsize=0
-    )
+from omvpipe import PipedStreamReader, ProtocolError, spawn_decoder  # noqa: E402
 
-    stdout = proc.stdout
 
-    def read_line():
-        line = stdout.readline()
-        if not line:
-            return None
-        return line.decode("utf-8", errors="replace").rstrip("\n")
+def run_decoder(input_file, payload, decoder):
+    proc = spawn_decoder(input_file, payload=payload, decoder=decoder)
 
-    header = read_line()
-    if header is None:
-        print("No header received, decoder failed?")
-        return
-    print(f"[HEADER] {header}")
+    try:
+        reader = PipedStreamReader(proc.stdout)
+    except ProtocolError as e:
+        print(f"No header received, decoder failed? ({e})")
+        reader = None
 
-    while True:
-        line = read_line()
-        if line is None:
-            break
-        if line.startswith("FRAME"):
-            pts_line = read_line()
-            bytes_line = read_line()
-            if pts_line is None or bytes_line is None:
-                break
-            pts = int(pts_line.split(" ", 1)[1])
-            n = int(bytes_line.split(" ", 1)[1])
-            png_bytes = png_bytes = read_exact(stdout, n)
-            if png_bytes is None:
-                print("Unexpected EOF while reading PNG payload")
-                break
-            if len(png_bytes) < n:
-                print("Unexpected EOF while reading PNG payload")
-                break
-            try:
-                img = Image.open(io.BytesIO(png_bytes))
-                print(f"[FRAME] PTS={pts}, PNG: {img.width}x{img.height}, mode={img.mode}, info={img.info}")
-            except Exception as e:
-                print(f"[FRAME] PTS={pts}, Failed to parse PNG: {e}")
+    if reader is not None:
+        h = reader.header
+        print(f"[HEADER] V{h.version} {h.width}x{h.height} @ {h.fps_num}/{h.fps_den} C{h.channels} Enc:{h.encoding}")
+        for key, value in h.meta.items():
+            print(f"[META] {key} {value}")
+        try:
+            for frame in reader:
+                try:
+                    img = frame.to_image()
+                    print(f"[FRAME] PTS={frame.pts}, {h.encoding}: {img.width}x{img.height}, mode={img.mode}")
+                except Exception as e:
+                    print(f"[FRAME] PTS={frame.pts}, Failed to parse {h.encoding} payload: {e}")
+            print(f"[END] {reader.end_frames} frame(s)")
+        except ProtocolError as e:
+            print(f"Stream error: {e}")
 
     proc.wait()
     if proc.returncode != 0:
@@ -79,8 +39,10 @@ def run_decoder(input_file):
 
 
 if __name__ == "__main__":
-    parser = argparse.ArgumentParser(description="Test omvdecoder piped PNG output")
-    parser.add_argument("--input", help="Input media file to pass to omvdecoder")
+    parser = argparse.ArgumentParser(description="Test omvdecoder piped output")
+    parser.add_argument("--input", required=True, help="Input media file to pass to omvdecoder")
+    parser.add_argument("--payload", default="png", choices=["raw-rgba", "raw-rgb", "qoi", "png"])
+    parser.add_argument("--decoder", help="Path to omvdecoder, defaults to $OMVDECODER, PATH or target/")
 
     args = parser.parse_args()
-    run_decoder(args.input)
+    run_decoder(args.input, args.payload, args.decoder)
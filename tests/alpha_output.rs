@@ -0,0 +1,147 @@
+mod common;
+
+use common::{fps, Collect};
+use image::{Rgba, RgbaImage};
+use omvdecoder::converter::{
+    AlphaLayout, Background, BackgroundConverter, Converter, MatteColour, MatteConverter,
+    MatteKind, PngConverter,
+};
+use omvdecoder::decoder;
+
+fn source() -> RgbaImage {
+    RgbaImage::from_fn(2, 1, |x, _| {
+        if x == 0 {
+            Rgba([200, 100, 50, 255])
+        } else {
+            Rgba([200, 100, 50, 128])
+        }
+    })
+}
+
+fn run(kind: MatteKind, colour: MatteColour) -> (RgbaImage, RgbaImage) {
+    let (c, m) = (Collect::default(), Collect::default());
+    let mut converter = MatteConverter::new(Box::new(c.clone()), Box::new(m.clone()))
+        .with_kind(kind)
+        .with_colour(colour);
+    converter.prepare(2, 1, fps(30)).unwrap();
+    converter.convert_frame(source(), 0).unwrap();
+    converter.finish().unwrap();
+    let colour = c.frames.borrow()[0].clone();
+    let matte = m.frames.borrow()[0].clone();
+    (colour, matte)
+}
+
+#[test]
+fn straight_colour_with_luma_matte() {
+    let (colour, matte) = run(MatteKind::Luma, MatteColour::Straight);
+    assert_eq!(colour.get_pixel(1, 0).0, [200, 100, 50, 255]);
+    assert_eq!(matte.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    assert_eq!(matte.get_pixel(1, 0).0, [128, 128, 128, 255]);
+}
+
+#[test]
+fn premultiplied_colour_with_alpha_matte() {
+    let (colour, matte) = run(MatteKind::Alpha, MatteColour::Premultiplied);
+    assert_eq!(colour.get_pixel(0, 0).0, [200, 100, 50, 255]);
+    assert_eq!(colour.get_pixel(1, 0).0, [100, 50, 25, 255]);
+    assert_eq!(matte.get_pixel(1, 0).0, [255, 255, 255, 128]);
+}
+
+#[test]
+fn grayscale_png_matte() {
+    let dir = std::env::temp_dir().join(format!("omvdecoder-matte-{}", std::process::id()));
+    let mut png = PngConverter::new(&dir).with_grayscale(false);
+    png.prepare(2, 1, fps(30)).unwrap();
+    let (_, luma) = run(MatteKind::Luma, MatteColour::Straight);
+    png.convert_frame(luma, 0).unwrap();
+    let mut png = PngConverter::new(&dir).with_grayscale(true);
+    let (_, alpha) = run(MatteKind::Alpha, MatteColour::Straight);
+    png.convert_frame(alpha, 1).unwrap();
+
+    let luma = image::open(dir.join("frame_0000.png")).unwrap();
+    assert_eq!(luma.color(), image::ColorType::L8);
+    assert_eq!(luma.to_luma8().get_pixel(1, 0).0, [128]);
+    let alpha = image::open(dir.join("frame_0001.png")).unwrap();
+    assert_eq!(alpha.color(), image::ColorType::La8);
+    assert_eq!(alpha.to_luma_alpha8().get_pixel(1, 0).0, [255, 128]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn packed_layouts() {
+    let image = source();
+    let packed = AlphaLayout::SideBySide.pack(&image);
+    assert_eq!(packed.dimensions(), (4, 1));
+    assert_eq!(packed.get_pixel(1, 0).0, [200, 100, 50, 255]);
+    assert_eq!(packed.get_pixel(3, 0).0, [128, 128, 128, 255]);
+
+    let packed = AlphaLayout::TopBottom.pack(&image);
+    assert_eq!(packed.dimensions(), (2, 2));
+    assert_eq!(packed.get_pixel(1, 1).0, [128, 128, 128, 255]);
+}
+
+#[test]
+fn siglus_bands_round_trip() {
+    for height in [1, 2, 3, 7, 9] {
+        let image = RgbaImage::from_fn(3, height, |x, y| {
+            Rgba([x as u8, y as u8, 7, (x * 40 + y * 20) as u8])
+        });
+        let packed = AlphaLayout::SiglusBands.pack(&image);
+        let (width, video_height) = packed.dimensions();
+        assert_eq!(
+            (width, video_height),
+            AlphaLayout::SiglusBands.packed_size(3, height)
+        );
+
+        // lay the packed frame out as the B, G, R planes theorafile hands out
+        let mut planes = Vec::new();
+        for channel in [2, 1, 0] {
+            planes.extend(packed.pixels().map(|p| p.0[channel]));
+        }
+        let decoded = decoder::yuv_to_image(&planes, width, height, video_height, false).unwrap();
+        assert_eq!(decoded, image, "height {}", height);
+    }
+}
+
+#[test]
+fn background_parsing() {
+    assert_eq!(
+        "checkerboard".parse::<Background>().unwrap(),
+        Background::Checkerboard
+    );
+    assert_eq!(
+        "White".parse::<Background>().unwrap(),
+        Background::Color([255, 255, 255])
+    );
+    assert_eq!(
+        "#102030".parse::<Background>().unwrap(),
+        Background::Color([0x10, 0x20, 0x30])
+    );
+    assert!("#12345".parse::<Background>().is_err());
+    assert_eq!(
+        "bg.png".parse::<Background>().unwrap(),
+        Background::Image("bg.png".into())
+    );
+    assert!(Background::Image("missing.png".into())
+        .render(2, 2)
+        .is_err());
+}
+
+#[test]
+fn frames_are_flattened() {
+    let out = Collect::default();
+    let mut converter =
+        BackgroundConverter::new(Box::new(out.clone()), Background::Color([0, 0, 255]));
+    converter.prepare(2, 1, fps(30)).unwrap();
+    converter.convert_frame(source(), 0).unwrap();
+    let frame = out.frames.borrow()[0].clone();
+    assert_eq!(frame.get_pixel(0, 0).0, [200, 100, 50, 255]);
+    assert_eq!(frame.get_pixel(1, 0).0, [100, 50, 152, 255]);
+    // a frame of another size is an error, not a panic
+    assert!(converter.convert_frame(RgbaImage::new(3, 1), 1).is_err());
+
+    let board = Background::Checkerboard.render(16, 16).unwrap();
+    assert_eq!(board.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    assert_eq!(board.get_pixel(8, 0).0, [204, 204, 204, 255]);
+    assert_eq!(board.get_pixel(8, 8).0, [255, 255, 255, 255]);
+}
@@ -0,0 +1,344 @@
+//! Builders for the Ogg and OMV data the integration tests feed the library.
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use image::RgbaImage;
+use omvdecoder::converter::Converter;
+use omvdecoder::frame_rate::FrameRate;
+use omvdecoder::ogg;
+
+/// A converter that keeps the frames it is given and the size it was prepared for.
+#[derive(Clone, Default)]
+pub struct Collect {
+    pub frames: Rc<RefCell<Vec<RgbaImage>>>,
+    pub size: Rc<Cell<(u32, u32)>>,
+}
+
+impl Converter for Collect {
+    fn prepare(&mut self, width: u32, height: u32, _rate: FrameRate) -> anyhow::Result<()> {
+        self.size.set((width, height));
+        Ok(())
+    }
+
+    fn convert_frame(&mut self, image: RgbaImage, _frame_id: u32) -> anyhow::Result<()> {
+        self.frames.borrow_mut().push(image);
+        Ok(())
+    }
+
+    fn finish(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Builds one page holding `packets`, the last one continuing if `open_end` is set.
+pub fn make_page(
+    serial: u32,
+    sequence: u32,
+    header_type: u8,
+    granule: i64,
+    packets: &[&[u8]],
+    open_end: bool,
+) -> Vec<u8> {
+    let mut lacing = Vec::new();
+    let mut body = Vec::new();
+    for (i, packet) in packets.iter().enumerate() {
+        let mut len = packet.len();
+        while len >= 255 {
+            lacing.push(255);
+            len -= 255;
+        }
+        if !(open_end && i == packets.len() - 1) {
+            lacing.push(len as u8);
+        }
+        body.extend_from_slice(packet);
+    }
+
+    let mut page = b"OggS".to_vec();
+    page.push(0);
+    page.push(header_type);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]);
+    page.push(lacing.len() as u8);
+    page.extend_from_slice(&lacing);
+    page.extend_from_slice(&body);
+    let crc = ogg::page_checksum(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// A Theora identification header for a 4:4:4 picture of `size` at `fps`.
+pub fn theora_ident(
+    version: (u8, u8, u8),
+    shift: u8,
+    size: (u32, u32),
+    fps: (u32, u32),
+) -> Vec<u8> {
+    let mut p = b"\x80theora".to_vec();
+    p.extend_from_slice(&[version.0, version.1, version.2]);
+    // frame size in macroblocks
+    p.extend_from_slice(&(size.0.div_ceil(16) as u16).to_be_bytes());
+    p.extend_from_slice(&(size.1.div_ceil(16) as u16).to_be_bytes());
+    p.extend_from_slice(&size.0.to_be_bytes()[1..]);
+    p.extend_from_slice(&size.1.to_be_bytes()[1..]);
+    p.extend_from_slice(&[0, 0]);
+    p.extend_from_slice(&fps.0.to_be_bytes());
+    p.extend_from_slice(&fps.1.to_be_bytes());
+    p.extend_from_slice(&[0, 0, 1, 0, 0, 1, 0, 0, 0, 0]);
+    let bits: u16 = ((shift as u16) << 5) | (3 << 3);
+    p.extend_from_slice(&bits.to_be_bytes());
+    p
+}
+
+/// A Theora stream of `frames` one-byte frame packets, one page per frame.
+pub fn theora_stream(serial: u32, size: (u32, u32), fps: (u32, u32), frames: u32) -> Vec<u8> {
+    let ident = theora_ident((3, 2, 1), 6, size, fps);
+    let mut data = make_page(serial, 0, 0x02, 0, &[&ident], false);
+    data.extend(make_page(
+        serial,
+        1,
+        0,
+        0,
+        &[b"\x81comment", b"\x82setup"],
+        false,
+    ));
+    for frame in 0..frames {
+        let eos = if frame + 1 == frames { 0x04 } else { 0 };
+        // granule of a keyframe-only stream, counted from 1
+        let granule = (frame as i64 + 1) << 6;
+        data.extend(make_page(serial, frame + 2, eos, granule, &[b"f"], false));
+    }
+    data
+}
+
+/// An OMV header: offset, version, padding, `padding2`, then the metadata block.
+pub fn omv_header(padding2: [u8; 0x24], metadata: [u32; 8]) -> Vec<u8> {
+    let mut out = 0x4cu32.to_le_bytes().to_vec();
+    out.extend_from_slice(&[1, 0, 0, 0]);
+    out.extend_from_slice(&padding2);
+    for value in metadata {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+pub fn fps(num: u32) -> FrameRate {
+    FrameRate::new(num, 1).unwrap()
+}
+
+/// MSB-first bit packing, as Theora headers and frames use it.
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    used: u32,
+}
+
+impl Bits {
+    fn put(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            let bit = self.used % 8;
+            if bit == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> bit;
+            }
+            self.used += 1;
+        }
+    }
+
+    /// A DCT token of the flat Huffman tables of [`theora_setup`]: five bits.
+    fn token(&mut self, token: u32) {
+        self.put(token, 5);
+    }
+
+    /// An end-of-block run over `blocks` blocks, 0 for the rest of the frame.
+    fn eob_run(&mut self, blocks: u32) {
+        assert!(blocks < 4096);
+        self.token(6);
+        self.put(blocks, 12);
+    }
+
+    /// A super block run length.
+    fn sb_run(&mut self, run: u32) {
+        let (prefix, bits, start) = match run {
+            1 => (0b0, 1, 1),
+            2..=3 => (0b10, 2, 2),
+            4..=5 => (0b110, 3, 4),
+            6..=9 => (0b1110, 4, 6),
+            10..=17 => (0b11110, 5, 10),
+            18..=33 => (0b111110, 6, 18),
+            _ => (0b111111, 6, 34),
+        };
+        let extra = match start {
+            1 => 0,
+            2 | 4 => 1,
+            6 => 2,
+            10 => 3,
+            18 => 4,
+            _ => 12,
+        };
+        self.put(prefix, bits);
+        self.put(run - start, extra);
+    }
+}
+
+/// A Theora comment header without user comments.
+pub fn theora_comment() -> Vec<u8> {
+    let vendor = b"omvdecoder tests";
+    let mut p = b"\x81theora".to_vec();
+    p.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    p.extend_from_slice(vendor);
+    p.extend_from_slice(&0u32.to_le_bytes());
+    p
+}
+
+/// A Theora setup header: no loop filter, one flat quantizer (a DC coefficient
+/// of 1 moves a pixel by 2) and 80 identical Huffman tables that code every
+/// token in five bits.
+pub fn theora_setup() -> Vec<u8> {
+    let mut bits = Bits::default();
+    // loop filter limits, zero bits each
+    bits.put(0, 3);
+    // AC and DC scales
+    for scale in [10, 100] {
+        bits.put(7, 4);
+        for _ in 0..64 {
+            bits.put(scale, 8);
+        }
+    }
+    // one base matrix
+    bits.put(0, 9);
+    for _ in 0..64 {
+        bits.put(16, 8);
+    }
+    // one quant range covering every qi, the other five copy the previous one
+    bits.put(62, 6);
+    for i in 1..6 {
+        bits.put(0, if i >= 3 { 2 } else { 1 });
+    }
+    fn tree(bits: &mut Bits, depth: u32, token: u32) {
+        if depth == 5 {
+            bits.put(1, 1);
+            bits.put(token, 5);
+        } else {
+            bits.put(0, 1);
+            tree(bits, depth + 1, token << 1);
+            tree(bits, depth + 1, token << 1 | 1);
+        }
+    }
+    for _ in 0..80 {
+        tree(&mut bits, 0, 0);
+    }
+    let mut p = b"\x82theora".to_vec();
+    p.extend(bits.bytes);
+    p
+}
+
+/// A frame of a flat picture for [`theora_setup`]. A keyframe sets every plane
+/// to `128 + 2 * dc`, an inter frame adds `2 * dc` to the previous picture.
+/// `dc` is per plane and within ±6.
+pub fn theora_frame(size: (u32, u32), keyframe: bool, dc: [i32; 3]) -> Vec<u8> {
+    let (bw, bh) = (size.0.div_ceil(16) * 2, size.1.div_ceil(16) * 2);
+    let blocks = bw * bh;
+    let super_blocks = 3 * bw.div_ceil(4) * bh.div_ceil(4);
+    let mut bits = Bits::default();
+    // data packet, frame type, qi 0 and no second qi
+    bits.put(0, 1);
+    bits.put(!keyframe as u32, 1);
+    bits.put(0, 6);
+    bits.put(0, 1);
+    if keyframe {
+        bits.put(0, 3);
+    } else {
+        // no partially coded super blocks, all of them fully coded
+        bits.put(0, 1);
+        bits.sb_run(super_blocks);
+        bits.put(1, 1);
+        bits.sb_run(super_blocks);
+        // macro block modes with fixed length codes, all INTER_NOMV
+        bits.put(7, 3);
+        for _ in 0..(bw / 2) * (bh / 2) {
+            bits.put(0, 3);
+        }
+        // motion vector coding, unused
+        bits.put(0, 1);
+    }
+    // DC tokens: the first block of each plane carries the value, the others
+    // predict it from their neighbours
+    bits.put(0, 8);
+    let mut open = false;
+    for dc in dc {
+        assert!(dc.abs() <= 6, "dc {} out of range", dc);
+        if dc == 0 {
+            bits.eob_run(blocks);
+            continue;
+        }
+        match dc {
+            1 => bits.token(9),
+            -1 => bits.token(10),
+            2 => bits.token(11),
+            -2 => bits.token(12),
+            _ => {
+                bits.token(10 + dc.unsigned_abs());
+                bits.put((dc < 0) as u32, 1);
+            }
+        }
+        bits.eob_run(blocks - 1);
+        open = true;
+    }
+    // AC tokens: one run ends every block that got a DC value
+    bits.put(0, 8);
+    if open {
+        bits.eob_run(0);
+    }
+    bits.bytes
+}
+
+/// A decodable 4:4:4 Theora stream of flat frames for [`theora_frame`], one page
+/// per frame with the granule positions of Theora 3.2.1.
+pub fn decodable_stream(
+    serial: u32,
+    size: (u32, u32),
+    fps: (u32, u32),
+    frames: &[(bool, [i32; 3])],
+) -> Vec<u8> {
+    let ident = theora_ident((3, 2, 1), 6, size, fps);
+    let mut data = make_page(serial, 0, 0x02, 0, &[&ident], false);
+    data.extend(make_page(
+        serial,
+        1,
+        0,
+        0,
+        &[&theora_comment(), &theora_setup()],
+        false,
+    ));
+    let mut keyframe = 0;
+    for (i, &(key, dc)) in frames.iter().enumerate() {
+        if key {
+            keyframe = i;
+        }
+        let granule = (((keyframe + 1) << 6) + i - keyframe) as i64;
+        let eos = if i + 1 == frames.len() { 0x04 } else { 0 };
+        let packet = theora_frame(size, key, dc);
+        data.extend(make_page(
+            serial,
+            i as u32 + 2,
+            eos,
+            granule,
+            &[&packet],
+            false,
+        ));
+    }
+    data
+}
+
+/// An OMV file around `payload`, an opaque video of `size` at 30 fps.
+pub fn omv_file(size: (u32, u32), frames: u32, payload: &[u8]) -> Vec<u8> {
+    let mut omv = omv_header([0; 0x24], [size.0, size.1, 33333, 0, 0, 0, frames, frames]);
+    omv.extend_from_slice(payload);
+    omv
+}
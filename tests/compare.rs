@@ -0,0 +1,108 @@
+use image::{Rgba, RgbaImage};
+use omvdecoder::compare::{self, Frames, Reference, ReportFormat};
+use omvdecoder::progress::Progress;
+
+fn frames(images: Vec<RgbaImage>) -> Frames {
+    Box::new(images.into_iter().map(Ok))
+}
+
+#[test]
+fn scores_per_channel() {
+    let image = RgbaImage::from_pixel(16, 16, Rgba([100, 50, 25, 255]));
+    let same = compare::compare_frames(0, &image, &image).unwrap();
+    assert_eq!(same.mse.r, 0.0);
+    assert!(same.psnr.r.is_infinite() && same.psnr.a.is_infinite());
+    assert!((same.ssim.g - 1.0).abs() < 1e-12);
+
+    // red off by 10, alpha off by 1 on one pixel
+    let mut other = RgbaImage::from_pixel(16, 16, Rgba([110, 50, 25, 255]));
+    other.put_pixel(3, 3, Rgba([110, 50, 25, 254]));
+    let scores = compare::compare_frames(7, &image, &other).unwrap();
+    assert_eq!(scores.frame, 7);
+    assert_eq!(scores.mse.r, 100.0);
+    assert!((scores.psnr.r - 28.1308).abs() < 1e-3, "{}", scores.psnr.r);
+    assert!(scores.psnr.g.is_infinite() && scores.psnr.b.is_infinite());
+    assert_eq!(scores.mse.a, 1.0 / 256.0);
+    // flat windows only differ in their mean
+    let c1 = (0.01f64 * 255.0).powi(2);
+    let expected = (2.0 * 100.0 * 110.0 + c1) / (100.0f64.powi(2) + 110.0f64.powi(2) + c1);
+    assert!((scores.ssim.r - expected).abs() < 1e-9, "{}", scores.ssim.r);
+    assert!(scores.ssim.a < 1.0 && scores.ssim.a > 0.9);
+    assert_eq!(scores.ssim.b, 1.0);
+
+    let small = RgbaImage::new(8, 16);
+    assert!(compare::compare_frames(0, &image, &small).is_err());
+}
+
+#[test]
+fn summary_averages_the_error() {
+    let black = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+    let grey = RgbaImage::from_pixel(4, 4, Rgba([10, 0, 0, 255]));
+    let comparison = compare::compare(
+        frames(vec![black.clone(), black.clone(), black.clone()]),
+        // the reference has one frame less
+        frames(vec![black.clone(), grey]),
+        &mut Progress::hidden(),
+    )
+    .unwrap();
+    assert_eq!(comparison.frames.len(), 2);
+    assert_eq!(comparison.summary.frames, 2);
+    // the mean MSE is 50
+    let expected = 10.0 * (255.0f64 * 255.0 / 50.0).log10();
+    assert!((comparison.summary.psnr.r - expected).abs() < 1e-9);
+    assert!(comparison.summary.psnr.a.is_infinite());
+
+    let mut csv = Vec::new();
+    comparison.write(ReportFormat::Csv, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[0],
+        "frame,psnr_r,psnr_g,psnr_b,psnr_a,ssim_r,ssim_g,ssim_b,ssim_a"
+    );
+    assert!(lines[1].starts_with("0,inf,inf,inf,inf,1.000000,"));
+    assert!(lines[3].starts_with("all,31.1411,inf,"), "{}", lines[3]);
+
+    let mut json = Vec::new();
+    comparison.write(ReportFormat::Json, &mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["summary"]["frames"], 2);
+    assert_eq!(json["frames"][1]["mse"]["r"], 100.0);
+    assert!(json["frames"][0]["psnr"]["r"].is_null());
+
+    let empty = compare::compare(frames(vec![]), frames(vec![]), &mut Progress::hidden());
+    assert!(empty.is_err());
+}
+
+#[test]
+fn image_sequence_reference() {
+    let dir = std::env::temp_dir().join(format!("omvdecoder-compare-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // written out of order, read back in frame order
+    for i in [10u8, 9, 1] {
+        RgbaImage::from_pixel(2, 2, Rgba([i, 0, 0, 255]))
+            .save(dir.join(format!("frame_{:01}.png", i)))
+            .unwrap();
+    }
+    std::fs::write(dir.join("notes.txt"), "not a picture").unwrap();
+
+    let reference = Reference::from_path(&dir);
+    assert_eq!(reference, Reference::Images(dir.clone()));
+    let red: Vec<u8> = reference
+        .frames(2, 2)
+        .unwrap()
+        .map(|frame| frame.unwrap().get_pixel(0, 0).0[0])
+        .collect();
+    assert_eq!(red, [1, 9, 10]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(
+        Reference::from_path("other/ED.OMV"),
+        Reference::Omv(_)
+    ));
+    assert!(matches!(
+        Reference::from_path("encoded/ed.mp4"),
+        Reference::Video(_)
+    ));
+}
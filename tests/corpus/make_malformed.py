@@ -0,0 +1,157 @@
+"""Regenerates the malformed OMV files in tests/corpus/malformed.
+
+Every file must make the decoder fail with an error, never crash. Run from the
+repository root: python tests/corpus/make_malformed.py
+"""
+
+import os
+import struct
+
+OUT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "malformed")
+
+
+def crc_table():
+    table = []
+    for i in range(256):
+        r = i << 24
+        for _ in range(8):
+            r = ((r << 1) ^ 0x04C11DB7) if r & 0x80000000 else (r << 1)
+        table.append(r & 0xFFFFFFFF)
+    return table
+
+
+TABLE = crc_table()
+
+
+def ogg_crc(data):
+    crc = 0
+    for b in data:
+        crc = ((crc << 8) & 0xFFFFFFFF) ^ TABLE[((crc >> 24) & 0xFF) ^ b]
+    return crc
+
+
+def page(serial, sequence, header_type, granule, *packets):
+    lacing = []
+    for packet in packets:
+        lacing += [255] * (len(packet) // 255) + [len(packet) % 255]
+    raw = bytearray(b"OggS")
+    raw += struct.pack("<BBqIII", 0, header_type, granule, serial, sequence, 0)
+    raw += bytes([len(lacing)]) + bytes(lacing) + b"".join(packets)
+    raw[22:26] = struct.pack("<I", ogg_crc(raw))
+    return bytes(raw)
+
+
+def header(width=64, height=48, frame_count=1):
+    # offset, version, padding, padding2, then the metadata block
+    out = struct.pack("<IBB2s", 0x4C, 1, 0, b"\0\0")
+    out += b"\0" * 0x24
+    out += struct.pack("<8I", width, height, 33333, 0, 0, 0, 1, frame_count)
+    return out
+
+
+def theora_ident(pic_width, pic_height, pixel_format=3):
+    p = bytearray(b"\x80theora")
+    p += bytes([3, 2, 1])
+    p += struct.pack(">HH", (pic_width + 15) // 16, (pic_height + 15) // 16)
+    p += struct.pack(">I", pic_width)[1:] + struct.pack(">I", pic_height)[1:]
+    p += bytes([0, 0])
+    p += struct.pack(">II", 30, 1)
+    p += bytes([0, 0, 1, 0, 0, 1, 0, 0, 0, 0])
+    p += struct.pack(">H", (6 << 5) | (pixel_format << 3))
+    return bytes(p)
+
+
+def theora_comment():
+    vendor = b"omvdecoder tests"
+    return b"\x81theora" + struct.pack("<I", len(vendor)) + vendor + struct.pack("<I", 0)
+
+
+class Bits:
+    """MSB-first bit packing, as Theora headers use it."""
+
+    def __init__(self):
+        self.bits = []
+
+    def put(self, value, n):
+        self.bits += [(value >> i) & 1 for i in reversed(range(n))]
+
+    def bytes(self):
+        bits = self.bits + [0] * (-len(self.bits) % 8)
+        return bytes(
+            int("".join(map(str, bits[i : i + 8])), 2) for i in range(0, len(bits), 8)
+        )
+
+
+def theora_setup():
+    # the same tables as theora_setup() in tests/common/mod.rs
+    bits = Bits()
+    bits.put(0, 3)
+    for scale in (10, 100):
+        bits.put(7, 4)
+        for _ in range(64):
+            bits.put(scale, 8)
+    bits.put(0, 9)
+    for _ in range(64):
+        bits.put(16, 8)
+    bits.put(62, 6)
+    for i in range(1, 6):
+        bits.put(0, 2 if i >= 3 else 1)
+
+    def tree(depth, token):
+        if depth == 5:
+            bits.put(1, 1)
+            bits.put(token, 5)
+        else:
+            bits.put(0, 1)
+            tree(depth + 1, token << 1)
+            tree(depth + 1, token << 1 | 1)
+
+    for _ in range(80):
+        tree(0, 0)
+    return b"\x82theora" + bits.bytes()
+
+
+def theora_headers(pic_width, pic_height, pixel_format=3):
+    """The three header pages of a stream theorafile can open, no frames."""
+    return page(1, 0, 0x02, 0, theora_ident(pic_width, pic_height, pixel_format)) + page(
+        1, 1, 0x04, 0, theora_comment(), theora_setup()
+    )
+
+
+FILES = {
+    # nothing at all
+    "empty.omv": b"",
+    # the header stops in the middle of padding2
+    "truncated_header.omv": header()[:40],
+    # a complete header without any Ogg payload
+    "header_only.omv": header(),
+    # a capture pattern followed by junk
+    "garbage_after_capture.omv": header() + b"OggS" + bytes(range(200)),
+    # a valid page whose checksum was damaged
+    "bad_checksum.omv": header()
+    + page(1, 0, 0x02, 0, theora_ident(64, 64))[:-1]
+    + b"\xff",
+    # an Ogg stream that carries Vorbis, not Theora
+    "not_theora.omv": header()
+    + page(1, 0, 0x02, 0, b"\x01vorbis" + b"\0" * 23)
+    + page(1, 1, 0x04, 0, b"\x03vorbis"),
+    # the stream ends after its comment header, without a setup header
+    "missing_setup.omv": header(height=64)
+    + page(1, 0, 0x02, 0, theora_ident(64, 64))
+    + page(1, 1, 0x04, 0, theora_comment()),
+    # the header announces more rows than the video has
+    "mismatched_height.omv": header(height=4096) + theora_headers(64, 64),
+    # alpha bands cannot fit: 4 * 49 > 3 * 64
+    "odd_alpha_height.omv": header(height=49) + theora_headers(64, 64),
+    # 4:2:0 instead of the 4:4:4 planes OMV uses
+    "wrong_pixel_format.omv": header(height=64)
+    + theora_headers(64, 64, pixel_format=0),
+}
+
+
+if __name__ == "__main__":
+    os.makedirs(OUT, exist_ok=True)
+    for name, data in FILES.items():
+        with open(os.path.join(OUT, name), "wb") as f:
+            f.write(data)
+        print(f"{name}: {len(data)} bytes")
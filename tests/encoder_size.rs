@@ -0,0 +1,56 @@
+use image::{Rgba, RgbaImage};
+use omvdecoder::converter::{FittedSize, SizePolicy};
+
+fn gradient(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 10, 255]))
+}
+
+#[test]
+fn even_sizes_are_left_alone() {
+    for policy in [SizePolicy::Pad, SizePolicy::Crop, SizePolicy::Error] {
+        let size = FittedSize::new(640, 360, 2, policy, "h264").unwrap();
+        assert!(!size.is_adjusted());
+        assert_eq!((size.width, size.height), (640, 360));
+        let frame = gradient(640, 360);
+        assert_eq!(size.fit(frame.clone(), 0).unwrap(), frame);
+    }
+}
+
+#[test]
+fn odd_sizes_follow_the_policy() {
+    let pad = FittedSize::new(641, 359, 2, SizePolicy::Pad, "h264").unwrap();
+    assert_eq!((pad.width, pad.height), (642, 360));
+    assert_eq!(pad.original_size_tag(), "original_size=641x359");
+
+    let crop = FittedSize::new(641, 359, 2, SizePolicy::Crop, "h264").unwrap();
+    assert_eq!((crop.width, crop.height), (640, 358));
+    assert!(crop.is_adjusted());
+
+    let err = FittedSize::new(641, 360, 2, SizePolicy::Error, "h264").unwrap_err();
+    assert!(err.to_string().contains("--odd-size"), "{}", err);
+
+    // nothing left to encode once a 1 pixel side is cropped
+    assert!(FittedSize::new(1, 360, 2, SizePolicy::Crop, "h264").is_err());
+    assert!(FittedSize::new(1, 360, 2, SizePolicy::Pad, "h264").is_ok());
+}
+
+#[test]
+fn padding_repeats_the_edge() {
+    let size = FittedSize::new(5, 3, 2, SizePolicy::Pad, "ffmpeg").unwrap();
+    let frame = gradient(5, 3);
+    let fitted = size.fit(frame.clone(), 0).unwrap();
+    assert_eq!(fitted.dimensions(), (6, 4));
+    for y in 0..4 {
+        for x in 0..6 {
+            assert_eq!(fitted.get_pixel(x, y), frame.get_pixel(x.min(4), y.min(2)));
+        }
+    }
+
+    let size = FittedSize::new(5, 3, 2, SizePolicy::Crop, "ffmpeg").unwrap();
+    let fitted = size.fit(frame.clone(), 0).unwrap();
+    assert_eq!(fitted.dimensions(), (4, 2));
+    assert_eq!(fitted.get_pixel(3, 1), frame.get_pixel(3, 1));
+
+    // frames must arrive at the size the encoder was prepared for
+    assert!(size.fit(gradient(4, 2), 7).is_err());
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use anyhow::Context;
+use omvdecoder::decoder::{self, DecodeOptions, OmvReader, OnError};
+use omvdecoder::error::{self, Error};
+
+fn open(name: &str) -> anyhow::Error {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/corpus/malformed")
+        .join(name);
+    let data = std::fs::read(path).unwrap();
+    match OmvReader::open(&data, DecodeOptions::default()) {
+        Ok(_) => panic!("{} opened", name),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn failures_carry_their_kind() {
+    let e = open("truncated_header.omv");
+    assert!(
+        matches!(Error::find(&e), Some(Error::InvalidHeader(_))),
+        "{:#}",
+        e
+    );
+    assert_eq!(error::exit_code(&e), 3);
+
+    let e = open("header_only.omv");
+    assert!(
+        matches!(Error::find(&e), Some(Error::NoOggPayload)),
+        "{:#}",
+        e
+    );
+    assert_eq!(error::exit_code(&e), 4);
+
+    let e = open("bad_checksum.omv");
+    assert!(
+        matches!(Error::find(&e), Some(Error::CorruptStream(_))),
+        "{:#}",
+        e
+    );
+    assert_eq!(error::exit_code(&e), 8);
+
+    let e = decoder::check_geometry(480, 600, true).unwrap_err();
+    assert!(
+        matches!(Error::find(&e), Some(Error::InvalidGeometry(_))),
+        "{:#}",
+        e
+    );
+    assert_eq!(error::exit_code(&e), 7);
+
+    let options = DecodeOptions {
+        on_error: OnError::Skip,
+        ..Default::default()
+    };
+    let e = OmvReader::open(&[0u8; 16], options).err().unwrap();
+    assert!(!format!("{:#}", e).contains('\n'), "{:#}", e);
+}
+
+#[test]
+fn exit_code_looks_through_context() {
+    let e = anyhow::Error::from(Error::NoVideoStream).context("op.omv");
+    assert_eq!(error::exit_code(&e), 5);
+    assert_eq!(format!("{:#}", e), "op.omv: no Theora video stream found");
+
+    let io = std::io::Error::new(std::io::ErrorKind::StorageFull, "disk full");
+    let e = anyhow::Error::from(Error::io("out/op.mp4", io));
+    assert_eq!(error::exit_code(&e), 11);
+    assert_eq!(format!("{:#}", e), "I/O error on out/op.mp4: disk full");
+
+    let e = std::fs::read("/nonexistent/op.omv")
+        .context("reading op.omv")
+        .unwrap_err();
+    assert_eq!(error::exit_code(&e), 11);
+
+    assert_eq!(error::exit_code(&anyhow::anyhow!("invalid filter")), 1);
+}
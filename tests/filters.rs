@@ -0,0 +1,117 @@
+mod common;
+
+use common::{fps, Collect};
+use image::{Rgba, RgbaImage};
+use omvdecoder::converter::{Converter, FilteredConverter};
+use omvdecoder::filter::{self, Crop, FilterChain, Flip};
+
+fn gradient(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 10, 128]))
+}
+
+#[test]
+fn chain_declares_output_size() {
+    let chain: FilterChain = "scale=1280:-1:lanczos, crop=1280:540, pad=1920:1080, rotate=90"
+        .parse()
+        .unwrap();
+    assert_eq!(chain.len(), 4);
+    assert_eq!(chain.output_size(640, 360).unwrap(), (1080, 1920));
+    // the crop no longer fits a smaller frame
+    assert!(chain.output_size(640, 200).is_err());
+    assert!(FilterChain::new().output_size(7, 5).unwrap() == (7, 5));
+}
+
+#[test]
+fn invalid_filters_are_rejected() {
+    for spec in [
+        "blur=3",
+        "crop=10",
+        "crop=a:b",
+        "scale=10:10:cubic",
+        "rotate=45",
+        "hflip=1",
+        "pad=4:4:0:0:#12",
+        "adjust=0:1:1:0",
+    ] {
+        assert!(filter::parse_filter(spec).is_err(), "{}", spec);
+    }
+}
+
+#[test]
+fn geometry_filters() {
+    let image = gradient(4, 3);
+
+    let cropped = "crop=2:2:1:1"
+        .parse::<FilterChain>()
+        .unwrap()
+        .apply(image.clone())
+        .unwrap();
+    assert_eq!(cropped.dimensions(), (2, 2));
+    assert_eq!(cropped.get_pixel(0, 0).0, [1, 1, 10, 128]);
+
+    let padded = "pad=6:5:#ff0000".parse::<FilterChain>();
+    assert!(padded.is_err(), "the colour is the fifth argument");
+    let padded = "pad=6:5:::#ff000080"
+        .parse::<FilterChain>()
+        .unwrap()
+        .apply(image.clone())
+        .unwrap();
+    assert_eq!(padded.dimensions(), (6, 5));
+    assert_eq!(padded.get_pixel(0, 0).0, [255, 0, 0, 128]);
+    assert_eq!(padded.get_pixel(1, 1).0, [0, 0, 10, 128]);
+
+    let rotated = "rotate=90"
+        .parse::<FilterChain>()
+        .unwrap()
+        .apply(image.clone())
+        .unwrap();
+    assert_eq!(rotated.dimensions(), (3, 4));
+    // the bottom left corner ends up top left
+    assert_eq!(rotated.get_pixel(0, 0).0, [0, 2, 10, 128]);
+
+    let chain = FilterChain::new().with(Flip::Horizontal).with(Crop {
+        width: 1,
+        height: 1,
+        x: Some(0),
+        y: Some(0),
+    });
+    assert_eq!(
+        chain.apply(image.clone()).unwrap().get_pixel(0, 0).0,
+        [3, 0, 10, 128]
+    );
+
+    let scaled = "scale=8:6:nearest"
+        .parse::<FilterChain>()
+        .unwrap()
+        .apply(image)
+        .unwrap();
+    assert_eq!(scaled.get_pixel(7, 5).0, [3, 2, 10, 128]);
+}
+
+#[test]
+fn colour_filters() {
+    let image = RgbaImage::from_pixel(1, 1, Rgba([200, 100, 50, 128]));
+    let chain: FilterChain = "premultiply".parse().unwrap();
+    let premultiplied = chain.apply(image.clone()).unwrap();
+    assert_eq!(premultiplied.get_pixel(0, 0).0, [100, 50, 25, 128]);
+    let chain: FilterChain = "unpremultiply".parse().unwrap();
+    let straight = chain.apply(premultiplied).unwrap();
+    assert_eq!(straight.get_pixel(0, 0).0, [199, 100, 50, 128]);
+
+    let identity: FilterChain = "adjust".parse().unwrap();
+    assert_eq!(identity.apply(image.clone()).unwrap(), image);
+    let gray: FilterChain = "adjust=0:1:0".parse().unwrap();
+    let [r, g, b, a] = gray.apply(image).unwrap().get_pixel(0, 0).0;
+    assert!(r == g && g == b && a == 128);
+}
+
+#[test]
+fn converter_is_prepared_with_the_filtered_size() {
+    let out = Collect::default();
+    let chain: FilterChain = "scale=8:-1,vflip".parse().unwrap();
+    let mut converter = FilteredConverter::new(Box::new(out.clone()), chain);
+    converter.prepare(4, 3, fps(30)).unwrap();
+    assert_eq!(out.size.get(), (8, 6));
+    converter.convert_frame(gradient(4, 3), 0).unwrap();
+    assert_eq!(out.frames.borrow()[0].dimensions(), (8, 6));
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use omvdecoder::converter::set_mp4_frame_rate;
+use omvdecoder::frame_rate::FrameRate;
+
+#[test]
+fn rates_are_exact() {
+    let ntsc = FrameRate::new(60000, 2002).unwrap();
+    assert_eq!((ntsc.num(), ntsc.den()), (30000, 1001));
+    assert_eq!(ntsc.to_string(), "30000/1001");
+    assert_eq!(ntsc.rounded(), 30);
+    assert_eq!(ntsc.timestamp(30000), Duration::from_secs(1001));
+    assert_eq!(ntsc.frame_duration(), Duration::from_nanos(33_366_666));
+    assert_eq!(FrameRate::new(24, 1).unwrap().rounded(), 24);
+    assert_eq!(FrameRate::new(1, 2).unwrap().rounded(), 1);
+    assert!(FrameRate::new(0, 1).is_err());
+    assert!(FrameRate::new(30, 0).is_err());
+}
+
+#[test]
+fn floats_map_back_to_their_rational() {
+    for (fps, expected) in [
+        (30000.0 / 1001.0, (30000, 1001)),
+        (24000.0 / 1001.0, (24000, 1001)),
+        (29.97, (2997, 100)),
+        (25.0, (25, 1)),
+        (12.5, (25, 2)),
+    ] {
+        let rate = FrameRate::from_f64(fps).unwrap();
+        assert_eq!((rate.num(), rate.den()), expected, "{}", fps);
+    }
+    for fps in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        assert!(FrameRate::from_f64(fps).is_err(), "{}", fps);
+    }
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut b = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    b.extend_from_slice(kind);
+    b.extend_from_slice(payload);
+    b
+}
+
+/// A version 0 `mvhd`/`mdhd` payload: times, timescale, duration, padding.
+fn header_box(kind: &[u8; 4], timescale: u32, duration: u32) -> Vec<u8> {
+    let mut p = vec![0u8; 12];
+    p.extend_from_slice(&timescale.to_be_bytes());
+    p.extend_from_slice(&duration.to_be_bytes());
+    p.extend_from_slice(&[0u8; 8]);
+    mp4_box(kind, &p)
+}
+
+fn be32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn find(data: &[u8], kind: &[u8; 4]) -> usize {
+    data.windows(4).position(|w| w == kind).unwrap() + 4
+}
+
+/// An mp4 with one video track of the given `stts` entries at a media timescale
+/// of 90000, and `edits` as a version 0 edit list if there are any.
+fn muxed_mp4(samples: &[(u32, u32)], edits: &[(u32, i32)]) -> Vec<u8> {
+    let mut tkhd = vec![0u8; 20];
+    tkhd.extend_from_slice(&333u32.to_be_bytes());
+    tkhd.extend_from_slice(&[0u8; 60]);
+    let mut stts = vec![0u8; 4];
+    stts.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    for (count, delta) in samples {
+        stts.extend_from_slice(&count.to_be_bytes());
+        stts.extend_from_slice(&delta.to_be_bytes());
+    }
+    let stbl = mp4_box(b"stbl", &mp4_box(b"stts", &stts));
+    let mdia = [header_box(b"mdhd", 90000, 30000), mp4_box(b"minf", &stbl)].concat();
+    let mut trak = mp4_box(b"tkhd", &tkhd);
+    if !edits.is_empty() {
+        let mut elst = vec![0u8; 4];
+        elst.extend_from_slice(&(edits.len() as u32).to_be_bytes());
+        for (duration, media_time) in edits {
+            elst.extend_from_slice(&duration.to_be_bytes());
+            elst.extend_from_slice(&media_time.to_be_bytes());
+            elst.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+        }
+        trak.extend(mp4_box(b"edts", &mp4_box(b"elst", &elst)));
+    }
+    trak.extend(mp4_box(b"mdia", &mdia));
+    let moov = [header_box(b"mvhd", 1000, 333), mp4_box(b"trak", &trak)].concat();
+    [
+        mp4_box(b"ftyp", b"isom\0\0\0\0"),
+        mp4_box(b"mdat", &[0xab; 32]),
+        mp4_box(b"moov", &moov),
+    ]
+    .concat()
+}
+
+#[test]
+fn mp4_is_retimed_in_place() {
+    // what a muxer taking whole frame rates writes for 10 frames at "30" fps
+    let mut mp4 = muxed_mp4(&[(9, 3000), (1, 3000)], &[]);
+    let original = mp4.clone();
+
+    let rate = FrameRate::new(30000, 1001).unwrap();
+    assert_eq!(set_mp4_frame_rate(&mut mp4, rate).unwrap(), 10);
+    assert_eq!(mp4.len(), original.len());
+
+    let mdhd = find(&mp4, b"mdhd");
+    assert_eq!(be32(&mp4, mdhd + 12), 30000);
+    assert_eq!(be32(&mp4, mdhd + 16), 10010);
+    let stts = find(&mp4, b"stts");
+    assert_eq!(be32(&mp4, stts + 8), 9);
+    assert_eq!(be32(&mp4, stts + 12), 1001);
+    assert_eq!(be32(&mp4, stts + 20), 1001);
+    let mvhd = find(&mp4, b"mvhd");
+    assert_eq!(be32(&mp4, mvhd + 12), 1000);
+    assert_eq!(be32(&mp4, mvhd + 16), 333);
+    let tkhd = find(&mp4, b"tkhd");
+    assert_eq!(be32(&mp4, tkhd + 20), 333);
+    // the sample data is untouched
+    let mdat = find(&original, b"mdat");
+    assert_eq!(mp4[mdat..mdat + 32], original[mdat..mdat + 32]);
+
+    assert!(set_mp4_frame_rate(&mut mp4[..40], rate).is_err());
+}
+
+#[test]
+fn edit_lists_are_retimed() {
+    // 100 ms of nothing, then the video from its second frame on
+    let mut mp4 = muxed_mp4(&[(300, 3000)], &[(100, -1), (9966, 3000)]);
+    let rate = FrameRate::new(30000, 1001).unwrap();
+    assert_eq!(set_mp4_frame_rate(&mut mp4, rate).unwrap(), 300);
+
+    let elst = find(&mp4, b"elst");
+    assert_eq!(be32(&mp4, elst + 4), 2);
+    // segments stretch with the frames: 100.1 ms, 9975.966 ms
+    assert_eq!(be32(&mp4, elst + 8), 100);
+    assert_eq!(be32(&mp4, elst + 12), u32::MAX);
+    assert_eq!(be32(&mp4, elst + 20), 9975);
+    // the second edit still starts at the second frame
+    assert_eq!(be32(&mp4, elst + 24), 1001);
+    assert_eq!(be32(&mp4, elst + 28), 0x0001_0000);
+
+    // an edit list cannot follow frames of varying duration
+    let mut mp4 = muxed_mp4(&[(9, 3000), (1, 1500)], &[(333, 0)]);
+    let err = set_mp4_frame_rate(&mut mp4, rate).unwrap_err();
+    assert!(err.to_string().contains("edit list"), "{}", err);
+}
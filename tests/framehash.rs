@@ -0,0 +1,178 @@
+mod common;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use common::{decodable_stream, omv_header};
+use image::{Rgba, RgbaImage};
+use omvdecoder::converter::{Converter, FrameHashConverter};
+use omvdecoder::decoder::{AlphaMode, DecodeOptions, OmvReader, RawPlanes};
+use omvdecoder::frame_rate::FrameRate;
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn hash(channels: bool, frames: &[RgbaImage]) -> Vec<String> {
+    let out = Shared::default();
+    let mut cvt = FrameHashConverter::from_writer(out.clone()).with_channels(channels);
+    let (width, height) = frames[0].dimensions();
+    cvt.prepare(width, height, FrameRate::new(30000, 1001).unwrap())
+        .unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        cvt.convert_frame(frame.clone(), i as u32).unwrap();
+    }
+    cvt.finish().unwrap();
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    text.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn lines_follow_framemd5() {
+    let frames = [
+        RgbaImage::from_pixel(3, 2, Rgba([1, 2, 3, 4])),
+        RgbaImage::from_pixel(3, 2, Rgba([0, 0, 0, 0])),
+    ];
+    let lines = hash(false, &frames);
+    assert_eq!(
+        lines[..9],
+        [
+            "#format: frame checksums",
+            "#version: 2",
+            "#hash: MD5",
+            "#tb 0: 1001/30000",
+            "#media_type 0: video",
+            "#codec_id 0: rawvideo",
+            "#dimensions 0: 3x2",
+            "#sar 0: 1/1",
+            "#stream#, dts,        pts, duration,     size, hash",
+        ]
+    );
+    assert_eq!(lines.len(), 11);
+    assert_eq!(
+        lines[10],
+        format!(
+            "0,          1,          1,        1,       24, {:x}",
+            md5::compute([0u8; 24])
+        )
+    );
+    // the same pixels always give the same line
+    assert_eq!(hash(false, &frames), lines);
+}
+
+#[test]
+fn channel_hashes_are_separate() {
+    let mut frame = RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255]));
+    let lines = hash(true, std::slice::from_ref(&frame));
+    assert!(lines[8].ends_with("hash, r, g, b, a"));
+    let columns: Vec<&str> = lines[9].split(", ").collect();
+    assert_eq!(columns.len(), 10);
+    for (column, value) in columns[6..].iter().zip([10u8, 20, 30, 255]) {
+        assert_eq!(*column, format!("{:x}", md5::compute([value; 4])));
+    }
+
+    // a changed alpha leaves the colour hashes alone
+    frame.put_pixel(1, 1, Rgba([10, 20, 30, 0]));
+    let changed = hash(true, &[frame]);
+    let changed: Vec<&str> = changed[9].split(", ").collect();
+    assert_ne!(changed[5], columns[5]);
+    assert_eq!(changed[6..9], columns[6..9]);
+    assert_ne!(changed[9], columns[9]);
+}
+
+/// Hashes the frames of `omv` with their planes, the way the framehash output does.
+fn hash_decoded(omv: &[u8], alpha_mode: AlphaMode) -> Vec<String> {
+    let options = DecodeOptions {
+        alpha_mode,
+        raw_planes: true,
+        ..Default::default()
+    };
+    let mut reader = OmvReader::open(omv, options).unwrap();
+    let out = Shared::default();
+    let mut cvt = FrameHashConverter::from_writer(out.clone()).with_planes(true);
+    let info = reader.info().clone();
+    cvt.prepare(info.width, info.height, info.frame_rate)
+        .unwrap();
+    while let Some(frame) = reader.read_frame().unwrap() {
+        cvt.convert_planes(frame.planes.as_ref().unwrap(), frame.index)
+            .unwrap();
+        cvt.convert_frame(frame.image, frame.index).unwrap();
+    }
+    cvt.finish().unwrap();
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    text.lines().skip(9).map(str::to_string).collect()
+}
+
+#[test]
+fn plane_hashes_cover_the_decoded_planes() {
+    let planes = RawPlanes {
+        width: 2,
+        height: 1,
+        data: vec![1, 2, 3, 4, 5, 6],
+    };
+    let out = Shared::default();
+    let mut cvt = FrameHashConverter::from_writer(out.clone()).with_planes(true);
+    cvt.prepare(2, 1, FrameRate::new(30, 1).unwrap()).unwrap();
+    let frame = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+    cvt.convert_planes(&planes, 0).unwrap();
+    cvt.convert_frame(frame.clone(), 0).unwrap();
+    // every frame needs its own planes
+    assert!(cvt.convert_frame(frame, 1).is_err());
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines[8].ends_with("hash, y, cb, cr"));
+    let columns: Vec<&str> = lines[9].split(", ").collect();
+    assert_eq!(columns.len(), 9);
+    for (column, plane) in columns[6..].iter().zip([[1, 2], [3, 4], [5, 6]]) {
+        assert_eq!(*column, format!("{:x}", md5::compute(plane)));
+    }
+}
+
+#[test]
+fn plane_hashes_ignore_the_reconstruction() {
+    // a shader video: 36 colour rows and 12 alpha rows
+    let frames = [(true, [2, -1, 0]), (false, [1, 0, 3])];
+    let payload = decodable_stream(1, (64, 48), (30, 1), &frames);
+    let mut omv = omv_header([0; 0x24], [64, 36, 33333, 0, 0, 0, 2, 2]);
+    omv.extend_from_slice(&payload);
+
+    let options = DecodeOptions {
+        raw_planes: true,
+        ..Default::default()
+    };
+    let frame = OmvReader::open(&omv, options)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let planes = frame.planes.unwrap();
+    assert_eq!((planes.width, planes.height), (64, 48));
+    assert!(planes.plane(0).iter().all(|&y| y == 132));
+    assert!(planes.plane(1).iter().all(|&cb| cb == 126));
+    let frame = OmvReader::open(&omv, DecodeOptions::default())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(frame.planes.is_none());
+
+    // with and without the alpha bands the frames differ, the planes do not
+    let bands = hash_decoded(&omv, AlphaMode::Auto);
+    let opaque = hash_decoded(&omv, AlphaMode::None);
+    assert_eq!(bands.len(), 2);
+    for (bands, opaque) in bands.iter().zip(&opaque) {
+        let bands: Vec<&str> = bands.split(", ").collect();
+        let opaque: Vec<&str> = opaque.split(", ").collect();
+        assert_ne!(bands[5], opaque[5]);
+        assert_eq!(bands[6..], opaque[6..]);
+    }
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{omv_header, theora_stream};
+use omvdecoder::decoder;
+use omvdecoder::error::Error;
+use omvdecoder::pack::{AlphaBands, OmvHeader};
+use omvdecoder::repack;
+
+fn omv(major_version: u8) -> Vec<u8> {
+    let mut omv = omv_header([0; 0x24], [640, 360, 33333, 0, 0, 0, 12, 10]);
+    omv[4] = major_version;
+    omv.extend(theora_stream(1, (640, 480), (30, 1), 10));
+    omv
+}
+
+#[test]
+fn versions_are_dispatched() {
+    let header = decoder::read_omv_header(&omv(1)).unwrap();
+    assert!(matches!(header, OmvHeader::V1(_)));
+    assert_eq!(header.version(), (1, 0));
+    assert_eq!(header.metadata().height, 360);
+    assert_eq!(header.alpha_bands(), AlphaBands::PerPlane);
+
+    // best effort: the version 1 layout fits in front of the payload
+    let header = decoder::read_omv_header(&omv(2)).unwrap();
+    assert_eq!(header.version(), (2, 0));
+    assert_eq!(header.metadata().frame_count, 10);
+
+    // the payload starts where the metadata would be
+    let mut short = omv(2);
+    short.drain(0x30..0x4c);
+    let e = decoder::read_omv_header(&short).unwrap_err();
+    assert!(
+        matches!(Error::find(&e), Some(Error::InvalidHeader(_))),
+        "{:#}",
+        e
+    );
+    assert!(e.to_string().contains("version 2.0"), "{}", e);
+}
+
+#[test]
+fn alpha_reconstruction_follows_the_layout() {
+    // a 2x3 frame: every 4 row plane holds 3 colour rows and one alpha row
+    let (w, h, vh) = (2u32, 3u32, 4u32);
+    let plane = (w * vh) as usize;
+    let mut data = vec![0u8; plane * 3];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let opaque = decoder::reconstruct_frame(&data, w, h, vh, None).unwrap();
+    assert_eq!(opaque.get_pixel(1, 2).0, [21, 13, 5, 0xff]);
+    let bands = decoder::reconstruct_frame(&data, w, h, vh, Some(AlphaBands::PerPlane)).unwrap();
+    // row y takes its alpha from the band of plane y
+    let alpha: Vec<u8> = (0..h).map(|y| bands.get_pixel(0, y).0[3]).collect();
+    assert_eq!(alpha, [6, 14, 22]);
+    let legacy = decoder::yuv_to_image(&data, w, h, vh, false).unwrap();
+    assert_eq!(legacy, bands);
+}
+
+#[test]
+fn json_round_trip() {
+    let mut padding2 = [0u8; 0x24];
+    padding2[0] = 0xab;
+    padding2[0x23] = 0x01;
+    let mut original = omv_header(padding2, [640, 360, 33333, 7, 8, 9, 12, 10]);
+    original.extend(theora_stream(1, (640, 480), (30, 1), 10));
+    let header = decoder::read_omv_header(&original).unwrap();
+
+    let json = serde_json::to_value(&header).unwrap();
+    assert_eq!(json["layout"], "v1");
+    assert_eq!(json["major_version"], 1);
+    assert_eq!(json["padding"], "0000");
+    let hex = json["padding2"].as_str().unwrap();
+    assert_eq!(hex.len(), 0x48);
+    assert!(hex.starts_with("ab00") && hex.ends_with("0001"), "{}", hex);
+    assert_eq!(json["metadata"]["frame_time"], 33333);
+
+    let parsed: OmvHeader = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(parsed, header);
+    assert_eq!(parsed.to_bytes().unwrap(), original[..0x4c]);
+
+    // edit the timing and write it back
+    let mut edited = json.clone();
+    edited["metadata"]["frame_time"] = 41708.into();
+    edited["padding2"] = format!("ab00 {}", "0".repeat(0x40) + "0001").into();
+    let edited: OmvHeader = serde_json::from_value(edited).unwrap();
+    let omv = repack::replace_header(&original, &edited).unwrap();
+    assert_eq!(omv.len(), original.len());
+    assert_eq!(omv[0x4c..], original[0x4c..]);
+    let header = decoder::read_omv_header(&omv).unwrap();
+    assert_eq!(header.metadata().frame_time, 41708);
+    assert_eq!(omv[8..0x2c], padding2);
+
+    // typos and short hex are refused
+    let mut typo = json.clone();
+    typo["metadata"]["frametime"] = 1.into();
+    assert!(serde_json::from_value::<OmvHeader>(typo).is_err());
+    let mut short = json;
+    short["padding2"] = "ab".into();
+    assert!(serde_json::from_value::<OmvHeader>(short).is_err());
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{make_page, omv_header, theora_stream};
+use omvdecoder::inspect;
+use omvdecoder::ogg::Codec;
+
+/// A Vorbis identification header: 2 channels at 44100 Hz.
+fn vorbis_ident() -> Vec<u8> {
+    let mut p = b"\x01vorbis".to_vec();
+    p.extend_from_slice(&0u32.to_le_bytes());
+    p.push(2);
+    p.extend_from_slice(&44100u32.to_le_bytes());
+    p.extend_from_slice(&[0; 13]);
+    p
+}
+
+fn omv(stream: &[u8]) -> Vec<u8> {
+    let mut omv = omv_header([0; 0x24], [640, 480, 33333, 0, 0, 0, 12, 10]);
+    omv.extend_from_slice(stream);
+    omv
+}
+
+#[test]
+fn lists_pages_and_streams() {
+    let mut stream = theora_stream(1, (640, 480), (30, 1), 10);
+    stream.extend(make_page(2, 0, 0x02, 0, &[&vorbis_ident()], false));
+    stream.extend(make_page(2, 1, 0x04, 88200, &[&[0; 300]], false));
+    let inspection = inspect::inspect(&omv(&stream)).unwrap();
+
+    assert_eq!(inspection.payload_offset, 0x4c);
+    assert_eq!(inspection.pages.len(), 14);
+    let first = &inspection.pages[0];
+    assert_eq!((first.offset, first.serial, first.sequence), (0x4c, 1, 0));
+    assert!(first.bos && !first.eos && first.checksum_ok);
+    assert_eq!(first.flags(), "-b-");
+    assert_eq!(first.packets, [42]);
+    assert_eq!(first.frame, None);
+    assert_eq!(inspection.pages[1].packets, [8, 6]);
+
+    // granule 4 << 6 of a 3.2.1 stream: keyframe 3, frame 3
+    let page = &inspection.pages[5];
+    assert_eq!(page.granule_position, 4 << 6);
+    assert_eq!((page.keyframe, page.frame), (Some(3), Some(3)));
+    assert!(inspection.pages[11].eos);
+    assert_eq!(inspection.pages[13].packets, [300]);
+
+    assert_eq!(inspection.streams.len(), 2);
+    let video = &inspection.streams[0];
+    assert_eq!((video.serial, video.codec, video.pages), (1, "theora", 12));
+    assert!(video.details.starts_with("640x480 4:4:4 30/1 fps"));
+    let duration = video.duration.unwrap();
+    assert!((duration - 10.0 / 30.0).abs() < 1e-9);
+    let bitrate = video.bitrate.unwrap();
+    assert!((bitrate - video.bytes as f64 * 8.0 / duration / 1000.0).abs() < 1e-9);
+    let audio = &inspection.streams[1];
+    assert_eq!(audio.codec, "vorbis");
+    // it begins after the video ended, a second link
+    assert_eq!((video.link, audio.link), (0, 1));
+    assert_eq!(audio.duration, Some(2.0));
+
+    let mut text = Vec::new();
+    inspection.write_text(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("(key 3 frame 3)"), "{}", text);
+    assert!(
+        text.contains("stream 00000002 vorbis (link 1): 2 page(s)"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn damaged_pages_are_listed() {
+    let stream = theora_stream(1, (640, 480), (30, 1), 3);
+    let mut data = omv(&stream);
+    // flip a body byte of the last page, then add garbage
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    data.extend_from_slice(b"garbage");
+    let inspection = inspect::inspect(&data).unwrap();
+
+    assert_eq!(inspection.pages.len(), 5);
+    let bad = inspection.pages.last().unwrap();
+    assert!(!bad.checksum_ok && bad.eos);
+    assert_eq!(inspection.damage.len(), 1);
+    assert_eq!(inspection.damage[0].len, 7);
+    assert_eq!(inspection.damage[0].offset, last + 1);
+
+    let mut text = Vec::new();
+    inspection.write_text(&mut text).unwrap();
+    assert!(String::from_utf8(text).unwrap().contains(" BAD "));
+
+    assert!(inspect::inspect(&omv_header([0; 0x24], [0; 8])).is_err());
+}
+
+#[test]
+fn identifies_codecs() {
+    assert!(matches!(
+        Codec::identify(3, &vorbis_ident()),
+        Codec::Vorbis {
+            channels: 2,
+            sample_rate: 44100
+        }
+    ));
+    assert_eq!(Codec::identify(3, b"fishead\0\x03\0"), Codec::Skeleton);
+    assert_eq!(Codec::identify(3, b"\x7fFLAC"), Codec::Unknown);
+}
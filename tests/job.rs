@@ -0,0 +1,156 @@
+use omvdecoder::converter::{
+    Bitrate, EncoderSettings, H264Profile, MatteKind, OutputFormat, Preset, SizePolicy,
+};
+use omvdecoder::decoder::OnError;
+use omvdecoder::job::Job;
+use std::path::{Path, PathBuf};
+
+const JOB: &str = r#"
+output-dir = "out"
+
+[defaults]
+format = "ffmpeg"
+on-error = "skip"
+filter = ["scale=1280:-1"]
+crf = 20
+preset = "slow"
+
+[[input]]
+path = "movie/op.omv"
+
+[[input]]
+path = "movie/ed.v2.omv"
+format = "png-picture"
+filter = []
+matte = "mattes/ed"
+matte-kind = "alpha"
+
+[[input]]
+path = "movie/logo.omv"
+output = "logo.mp4"
+format = "h264"
+odd-size = "crop"
+background = "bg/paper.png"
+crf = 23
+profile = "high"
+"#;
+
+#[test]
+fn entries_apply_defaults_and_overrides() {
+    let mut job = Job::from_toml(JOB).unwrap();
+    job.base = PathBuf::from("jobs");
+    let entries = job.entries().unwrap();
+    assert_eq!(entries.len(), 3);
+
+    let op = &entries[0];
+    assert_eq!(op.input, Path::new("jobs/movie/op.omv"));
+    assert_eq!(op.output, Path::new("jobs/out/op.mp4"));
+    assert_eq!(op.format, OutputFormat::Ffmpeg);
+    assert_eq!(op.settings.on_error, Some(OnError::Skip));
+    assert_eq!(op.settings.filter_chain().unwrap().len(), 1);
+    assert_eq!(
+        op.settings.encoder().unwrap(),
+        EncoderSettings {
+            crf: Some(20),
+            bitrate: None,
+            preset: Some(Preset::Slow),
+            profile: H264Profile::Main,
+        }
+    );
+
+    let ed = &entries[1];
+    // picture formats write a directory named after the input
+    assert_eq!(ed.output, Path::new("jobs/out/ed.v2"));
+    assert_eq!(ed.format, OutputFormat::PngPicture);
+    assert!(ed.settings.filter_chain().unwrap().is_empty());
+    assert_eq!(
+        ed.settings.matte.as_deref(),
+        Some(Path::new("jobs/mattes/ed"))
+    );
+    assert_eq!(ed.settings.matte_kind, Some(MatteKind::Alpha));
+
+    let logo = &entries[2];
+    assert_eq!(logo.output, Path::new("jobs/logo.mp4"));
+    assert_eq!(logo.settings.odd_size, Some(SizePolicy::Crop));
+    assert_eq!(
+        logo.settings.background.as_deref().map(Path::new),
+        Some(Path::new("jobs/bg/paper.png"))
+    );
+    let encoder = logo.settings.encoder().unwrap();
+    assert_eq!(encoder.crf, Some(23));
+    assert_eq!(encoder.preset, Some(Preset::Slow));
+    assert_eq!(encoder.profile, H264Profile::High);
+}
+
+#[test]
+fn bitrates_are_parsed() {
+    assert_eq!("2500000".parse::<Bitrate>().unwrap(), Bitrate(2_500_000));
+    assert_eq!("800k".parse::<Bitrate>().unwrap(), Bitrate(800_000));
+    assert_eq!("2.5M".parse::<Bitrate>().unwrap(), Bitrate(2_500_000));
+    for bad in ["", "k", "fast", "-1M", "0", "5G"] {
+        assert!(bad.parse::<Bitrate>().is_err(), "{:?}", bad);
+    }
+}
+
+#[test]
+fn json_jobs_match_toml() {
+    let json = r#"{
+        "output-dir": "out",
+        "defaults": { "format": "ffmpeg", "on-error": "skip", "filter": ["scale=1280:-1"],
+                      "crf": 20, "preset": "slow" },
+        "input": [
+            { "path": "movie/op.omv" },
+            { "path": "movie/ed.v2.omv", "format": "png-picture", "filter": [],
+              "matte": "mattes/ed", "matte-kind": "alpha" },
+            { "path": "movie/logo.omv", "output": "logo.mp4", "format": "h264",
+              "odd-size": "crop", "background": "bg/paper.png", "crf": 23, "profile": "high" }
+        ]
+    }"#;
+    assert_eq!(Job::from_json(json).unwrap(), Job::from_toml(JOB).unwrap());
+}
+
+#[test]
+fn mistakes_are_reported_before_converting() {
+    for (job, message) in [
+        ("[[input]]\npath = \"a.omv\"\nfromat = \"h264\"", "fromat"),
+        ("[[input]]\noutput = \"a.mp4\"", "path"),
+        ("[[input]]\npath = \"a.omv\"", "output-dir"),
+        (
+            "[defaults]\noutput = \"a.mp4\"\n[[input]]\npath = \"a.omv\"",
+            "output-dir",
+        ),
+        (
+            "[[input]]\npath = \"a.omv\"\noutput = \"a\"\nfilter = [\"blur=3\"]",
+            "blur",
+        ),
+        (
+            "[[input]]\npath = \"a.omv\"\noutput = \"a\"\nmatte = \"m\"\nalpha-layout = \"side-by-side\"",
+            "combined",
+        ),
+        (
+            "output-dir = \"out\"\n[[input]]\npath = \"a/op.omv\"\n[[input]]\npath = \"b/op.omv\"",
+            "earlier entry",
+        ),
+        (
+            "output-dir = \"out\"\n[[input]]\npath = \"a.omv\"\nformat = \"piped-png\"",
+            "piped-png",
+        ),
+        ("[[input]]\npath = \"a.omv\"\noutput = \"a\"\ncrf = 60", "crf 60"),
+        (
+            "[[input]]\npath = \"a.omv\"\noutput = \"a\"\ncrf = 20\nbitrate = \"4M\"",
+            "cannot be combined",
+        ),
+        (
+            "[[input]]\npath = \"a.omv\"\noutput = \"a\"\nbitrate = \"fast\"",
+            "invalid bitrate",
+        ),
+        (
+            "[[input]]\npath = \"a.omv\"\noutput = \"a\"\npreset = \"quick\"",
+            "quick",
+        ),
+    ] {
+        let err = Job::from_toml(job).and_then(|job| job.entries());
+        let err = format!("{:#}", err.unwrap_err());
+        assert!(err.contains(message), "{:?}: {}", job, err);
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use omvdecoder::decoder::{self, DecodeOptions, OmvReader, OnError};
+use omvdecoder::error::Error;
+
+/// Opens and fully decodes `data`, the way the fuzz target does.
+fn decode(data: &[u8], on_error: OnError) -> anyhow::Result<usize> {
+    let reader = OmvReader::open(
+        data,
+        DecodeOptions {
+            on_error,
+            ..Default::default()
+        },
+    )?;
+    let frames = reader.collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(frames.len())
+}
+
+/// The error each file of the corpus fails with under `on_error`.
+fn expected(name: &str, on_error: OnError) -> fn(&Error) -> bool {
+    match name {
+        "empty.omv" | "truncated_header.omv" => |e| matches!(e, Error::InvalidHeader(_)),
+        "header_only.omv" => |e| matches!(e, Error::NoOggPayload),
+        // fatal under Stop, the other policies drop the damaged pages and
+        // with them the only stream
+        "garbage_after_capture.omv" | "bad_checksum.omv" if on_error == OnError::Stop => {
+            |e| matches!(e, Error::CorruptStream(_))
+        }
+        "garbage_after_capture.omv" | "bad_checksum.omv" | "not_theora.omv" => {
+            |e| matches!(e, Error::NoVideoStream)
+        }
+        "missing_setup.omv" => |e| matches!(e, Error::CorruptStream(_)),
+        "mismatched_height.omv" | "odd_alpha_height.omv" => {
+            |e| matches!(e, Error::InvalidGeometry(_))
+        }
+        "wrong_pixel_format.omv" => |e| matches!(e, Error::UnsupportedPixelFormat(_)),
+        _ => panic!("{} has no expected error", name),
+    }
+}
+
+#[test]
+fn malformed_corpus_is_rejected() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/malformed");
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "omv") {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        for on_error in [OnError::Stop, OnError::Skip, OnError::RepeatLast] {
+            match decode(&data, on_error) {
+                Ok(frames) => panic!("{} decoded {} frame(s) under {:?}", name, frames, on_error),
+                Err(e) => assert!(
+                    Error::find(&e).is_some_and(expected(name, on_error)),
+                    "{} under {:?}: {:#}",
+                    name,
+                    on_error,
+                    e
+                ),
+            }
+        }
+        count += 1;
+    }
+    assert!(count >= 10, "corpus has only {} file(s)", count);
+}
+
+#[test]
+fn geometry_is_checked() {
+    // 24bit video, the header height matches
+    assert!(decoder::check_geometry(480, 480, false).is_ok());
+    // shader video with the alpha bands below
+    assert!(decoder::check_geometry(480, 640, true).is_ok());
+    assert!(decoder::check_geometry(359, 479, true).is_ok());
+    // mismatched metadata height
+    assert!(decoder::check_geometry(600, 480, false).is_err());
+    assert!(decoder::check_geometry(0, 480, false).is_err());
+    // odd heights whose bands overrun the planes
+    assert!(decoder::check_geometry(361, 480, true).is_err());
+    assert!(decoder::check_geometry(480, 480, true).is_err());
+}
+
+#[test]
+fn alpha_is_detected() {
+    use decoder::AlphaMode::{Auto, None, SiglusBands};
+
+    assert_eq!(decoder::detect_alpha(Auto, 480, 480).unwrap(), (480, false));
+    assert_eq!(decoder::detect_alpha(Auto, 360, 480).unwrap(), (360, true));
+    assert_eq!(decoder::detect_alpha(Auto, 359, 479).unwrap(), (359, true));
+    // neither 24bit nor 4/3 of the header height
+    let e = decoder::detect_alpha(Auto, 300, 480).unwrap_err();
+    assert!(
+        matches!(Error::find(&e), Some(Error::InvalidGeometry(_))),
+        "{:#}",
+        e
+    );
+    assert!(decoder::detect_alpha(Auto, 600, 480).is_err());
+    assert!(decoder::detect_alpha(Auto, 0, 480).is_err());
+
+    // forced layouts
+    assert_eq!(decoder::detect_alpha(None, 360, 480).unwrap(), (480, false));
+    assert_eq!(
+        decoder::detect_alpha(SiglusBands, 300, 480).unwrap(),
+        (300, true)
+    );
+    assert!(decoder::detect_alpha(SiglusBands, 480, 480).is_err());
+}
+
+#[test]
+fn yuv_to_image_checks_buffer() {
+    let (w, h, vh) = (4u32, 3u32, 4u32);
+    let plane = (w * vh) as usize;
+    let mut data = vec![0u8; plane * 3];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i / plane * 100 + i % plane) as u8;
+    }
+
+    let image = decoder::yuv_to_image(&data, w, h, vh, false).unwrap();
+    // planes are B, G, R; the alpha of row 0 comes from row `h` of the blue plane
+    assert_eq!(image.get_pixel(1, 0).0, [201, 101, 1, 13]);
+    // row 1 takes its alpha from the second band, row 2 from the third
+    assert_eq!(image.get_pixel(0, 1).0[3], 112);
+    assert_eq!(image.get_pixel(0, 2).0[3], 212);
+    let opaque = decoder::yuv_to_image(&data, w, vh, vh, true).unwrap();
+    assert_eq!(opaque.get_pixel(0, 3).0, [212, 112, 12, 0xff]);
+
+    assert!(decoder::yuv_to_image(&data[1..], w, h, vh, false).is_err());
+    assert!(decoder::yuv_to_image(&data, w, vh + 1, vh, true).is_err());
+    assert!(decoder::yuv_to_image(&data, w, vh, vh, false).is_err());
+}
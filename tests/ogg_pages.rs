@@ -0,0 +1,173 @@
+mod common;
+
+use common::{make_page, theora_ident};
+use omvdecoder::decoder::OggPayload;
+use omvdecoder::ogg::{self, PageError, PageScanner, ScanItem, TheoraStream};
+
+#[test]
+fn checksum_matches_ogg_polynomial() {
+    // CRC-32/CKSUM("123456789") is 0x765e7680, Ogg skips the final inversion
+    assert_eq!(ogg::page_checksum(b"123456789"), 0x765e_7680 ^ 0xffff_ffff);
+}
+
+#[test]
+fn parses_page_fields() {
+    let page = make_page(7, 3, 0x02, 42, &[b"abc", &[1; 300]], false);
+    let parsed = ogg::parse_page(&page, 0).unwrap();
+    assert_eq!(parsed.serial, 7);
+    assert_eq!(parsed.sequence, 3);
+    assert_eq!(parsed.granule_position, 42);
+    assert!(parsed.is_bos() && !parsed.is_eos() && !parsed.is_continued());
+    assert_eq!(parsed.fragments(), vec![(3, true), (300, true)]);
+    assert_eq!(parsed.len(), page.len());
+}
+
+#[test]
+fn rejects_bad_checksum_and_truncation() {
+    let mut page = make_page(1, 0, 0, 0, &[b"hello"], false);
+    assert!(matches!(
+        ogg::parse_page(&page[..page.len() - 1], 0),
+        Err(PageError::Truncated)
+    ));
+    let last = page.len() - 1;
+    page[last] ^= 0xff;
+    assert!(matches!(
+        ogg::parse_page(&page, 0),
+        Err(PageError::BadChecksum { .. })
+    ));
+}
+
+#[test]
+fn rejects_offsets_off_a_page() {
+    let page = make_page(1, 0, 0, 0, &[b"hello"], false);
+    let mut data = b"junk".to_vec();
+    data.extend_from_slice(&page);
+    for parse in [ogg::parse_page, ogg::parse_page_unchecked] {
+        assert!(parse(&data, 4).is_ok());
+        assert!(matches!(parse(&data, 0), Err(PageError::NoCapturePattern)));
+        assert!(matches!(
+            parse(&data, data.len()),
+            Err(PageError::Truncated)
+        ));
+        assert!(matches!(
+            parse(&data, data.len() + 1),
+            Err(PageError::Truncated)
+        ));
+    }
+}
+
+#[test]
+fn resyncs_after_damage() {
+    let a = make_page(1, 0, 0x02, 0, &[b"first"], false);
+    let mut b = make_page(1, 1, 0, 0, &[b"second"], false);
+    let c = make_page(1, 2, 0x04, 0, &[b"third"], false);
+    let b_len = b.len();
+    b[30] ^= 0x55;
+
+    let mut data = a.clone();
+    data.extend_from_slice(b"junk");
+    data.extend_from_slice(&b);
+    data.extend_from_slice(&c);
+    data.extend_from_slice(b"tail padding");
+
+    let items: Vec<_> = PageScanner::new(&data).collect();
+    assert_eq!(items.len(), 5);
+    assert!(matches!(&items[0], ScanItem::Page(p) if p.offset == 0));
+    assert!(matches!(&items[1], ScanItem::Damaged(d) if d.error.is_none() && d.len == 4));
+    assert!(matches!(
+        &items[2],
+        ScanItem::Damaged(d) if d.len == b_len && matches!(d.error, Some(PageError::BadChecksum { .. }))
+    ));
+    assert!(matches!(&items[3], ScanItem::Page(p) if p.sequence == 2));
+    assert!(matches!(&items[4], ScanItem::Trailing { len: 12, .. }));
+
+    let repaired = ogg::repair(&data);
+    assert_eq!(repaired.damage.len(), 2);
+    assert_eq!(repaired.trailing, 12);
+    assert_eq!(repaired.data.len(), a.len() + c.len());
+    // the third page moved up in the repaired buffer
+    let c_source = a.len() + 4 + b_len;
+    assert_eq!(repaired.source_offset(a.len()), c_source);
+    assert_eq!(repaired.source_offset(a.len() + 5), c_source + 5);
+    assert_eq!(repaired.source_offset(3), 3);
+}
+
+fn theora_pages(version: (u8, u8, u8)) -> Vec<Vec<u8>> {
+    let ident = theora_ident(version, 6, (640, 480), (30000, 1001));
+    let base = (version >= (3, 2, 1)) as i64;
+    // open-ended packets must fill whole lacing values
+    let big = vec![9u8; 510];
+    vec![
+        make_page(5, 0, 0x02, 0, &[&ident], false),
+        make_page(5, 1, 0, 0, &[b"\x81comment", b"\x82setup"], false),
+        // frames 0 and 1, frame 0 is the keyframe
+        make_page(5, 2, 0, base + 1, &[b"key", b""], false),
+        // frame 2 spans two pages
+        make_page(5, 3, 0, -1, &[&big], true),
+        make_page(5, 4, 0x01, base + 3, &[&big[..10], b"f3"], false),
+        make_page(5, 5, 0x04, base + 4, &[b"f4"], false),
+    ]
+}
+
+#[test]
+fn collects_theora_packets() {
+    for version in [(3, 2, 0), (3, 2, 1)] {
+        let pages = theora_pages(version);
+        let data = pages.concat();
+        let stream = TheoraStream::collect(PageScanner::new(&data).filter_map(|i| match i {
+            ScanItem::Page(p) => Some(p),
+            _ => None,
+        }))
+        .unwrap();
+
+        assert_eq!(stream.info.version, version);
+        assert_eq!((stream.info.pic_width, stream.info.pic_height), (640, 480));
+        assert_eq!((stream.info.fps_num, stream.info.fps_den), (30000, 1001));
+        assert_eq!(stream.info.keyframe_granule_shift, 6);
+        assert_eq!(stream.info.pixel_format, 3);
+        assert!(stream.has_eos);
+
+        let sizes: Vec<_> = stream.packets.iter().map(|p| p.size).collect();
+        assert_eq!(sizes, vec![3, 0, 520, 2, 2]);
+        let frames: Vec<_> = stream.packets.iter().map(|p| p.frame).collect();
+        assert_eq!(frames, vec![0, 1, 2, 3, 4]);
+        // the spanning packet starts on the fourth page
+        let fourth = pages[..3].iter().map(Vec::len).sum::<usize>();
+        assert_eq!(stream.packets[2].page_offset, fourth);
+    }
+}
+
+#[test]
+fn missing_page_drops_broken_packet() {
+    let mut pages = theora_pages((3, 2, 1));
+    pages.remove(3);
+    let data = pages.concat();
+    let stream = TheoraStream::collect(PageScanner::new(&data).filter_map(|i| match i {
+        ScanItem::Page(p) => Some(p),
+        _ => None,
+    }))
+    .unwrap();
+
+    // the continued fragment is discarded, frame 2 is gone
+    let frames: Vec<_> = stream.packets.iter().map(|p| p.frame).collect();
+    assert_eq!(frames, vec![0, 1, 3, 4]);
+}
+
+#[test]
+fn locates_embedded_payload() {
+    let pages = theora_pages((3, 2, 1)).concat();
+    let mut omv = vec![0u8; 0x4c];
+    omv.extend_from_slice(&pages);
+    // a page cut short, then padding
+    omv.extend_from_slice(&make_page(5, 6, 0, 0, &[b"cut"], false)[..20]);
+    omv.extend_from_slice(&[0; 16]);
+
+    let payload = OggPayload::locate(&omv).unwrap();
+    assert_eq!(payload.offset, 0x4c);
+    assert_eq!(payload.pages, 6);
+    assert_eq!(payload.trimmed(), &pages[..]);
+    assert_eq!(payload.trailing(), 36);
+    assert_eq!(payload.data.len(), pages.len() + 36);
+
+    assert!(OggPayload::locate(&omv[..0x4c + 20]).is_err());
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{decodable_stream, omv_file};
+use omvdecoder::decoder::{DecodeOptions, OmvReader, OnError};
+use omvdecoder::error::Error;
+use omvdecoder::ogg;
+
+const SIZE: (u32, u32) = (64, 48);
+
+/// Eight keyframes, frame `i` a flat picture whose blue channel tells `i`.
+fn pages() -> Vec<Vec<u8>> {
+    let frames: Vec<(bool, [i32; 3])> = (0..8).map(|i| (true, [i - 3, 0, 0])).collect();
+    let data = decodable_stream(1, SIZE, (30, 1), &frames);
+    ogg::valid_pages(&data)
+        .map(|page| page.raw.to_vec())
+        .collect()
+}
+
+/// The frame each decoded picture shows, after the two header pages.
+fn decode(pages: &[Vec<u8>], on_error: OnError) -> anyhow::Result<Vec<i32>> {
+    let omv = omv_file(SIZE, 8, &pages.concat());
+    let options = DecodeOptions {
+        on_error,
+        ..Default::default()
+    };
+    let mut shown = Vec::new();
+    for (i, frame) in OmvReader::open(&omv, options)?.enumerate() {
+        let frame = frame?;
+        assert_eq!(frame.index as usize, i);
+        let blue = frame.image.get_pixel(0, 0).0[2] as i32;
+        shown.push((blue - 128) / 2 + 3);
+    }
+    Ok(shown)
+}
+
+#[test]
+fn intact_stream_decodes_every_frame() {
+    for on_error in [OnError::Stop, OnError::Skip, OnError::RepeatLast] {
+        assert_eq!(
+            decode(&pages(), on_error).unwrap(),
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        );
+    }
+}
+
+#[test]
+fn dropped_page_loses_its_frame() {
+    let mut pages = pages();
+    // the page of frame 3
+    pages.remove(2 + 3);
+    assert_eq!(
+        decode(&pages, OnError::Stop).unwrap(),
+        [0, 1, 2, 4, 5, 6, 7]
+    );
+    assert_eq!(
+        decode(&pages, OnError::Skip).unwrap(),
+        [0, 1, 2, 4, 5, 6, 7]
+    );
+    // the lost frame is filled in, the timing stays intact
+    assert_eq!(
+        decode(&pages, OnError::RepeatLast).unwrap(),
+        [0, 1, 2, 2, 4, 5, 6, 7]
+    );
+}
+
+#[test]
+fn damaged_page_is_dropped_or_fatal() {
+    let mut pages = pages();
+    // break the checksum of the page of frame 5
+    let page = &mut pages[2 + 5];
+    let last = page.len() - 1;
+    page[last] ^= 0xff;
+
+    let e = decode(&pages, OnError::Stop).unwrap_err();
+    assert!(
+        matches!(Error::find(&e), Some(Error::CorruptStream(_))),
+        "{:#}",
+        e
+    );
+    assert_eq!(
+        decode(&pages, OnError::Skip).unwrap(),
+        [0, 1, 2, 3, 4, 6, 7]
+    );
+    assert_eq!(
+        decode(&pages, OnError::RepeatLast).unwrap(),
+        [0, 1, 2, 3, 4, 4, 6, 7]
+    );
+}
+
+#[test]
+fn undecodable_packet_follows_the_policy() {
+    let mut pages = pages();
+    // replace frame 6 with a packet that is not a data packet, on a valid page
+    let page = ogg::parse_page(&pages[2 + 6], 0).unwrap();
+    pages[2 + 6] = common::make_page(
+        1,
+        page.sequence,
+        0,
+        page.granule_position,
+        &[b"\x80junk"],
+        false,
+    );
+
+    let e = decode(&pages, OnError::Stop).unwrap_err();
+    assert!(
+        matches!(Error::find(&e), Some(Error::CorruptStream(_))),
+        "{:#}",
+        e
+    );
+    assert!(e.to_string().contains("frame 6"), "{:#}", e);
+    assert_eq!(
+        decode(&pages, OnError::Skip).unwrap(),
+        [0, 1, 2, 3, 4, 5, 7]
+    );
+    assert_eq!(
+        decode(&pages, OnError::RepeatLast).unwrap(),
+        [0, 1, 2, 3, 4, 5, 5, 7]
+    );
+}
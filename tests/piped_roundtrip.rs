@@ -0,0 +1,245 @@
+use std::cell::RefCell;
+use std::io::{BufReader, Cursor, Write};
+use std::rc::Rc;
+
+use image::{Rgba, RgbaImage};
+use omvdecoder::converter::{Converter, PipedOutput, PipedPayload, PipedPngConverter};
+use omvdecoder::frame_rate::FrameRate;
+use omvdecoder::reader::PipedStreamReader;
+
+const WIDTH: u32 = 13;
+const HEIGHT: u32 = 7;
+
+/// Write end that stays readable after the converter is dropped.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn test_frame(index: u32, alpha: bool) -> RgbaImage {
+    RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let a = if alpha {
+            ((x * 19 + y * 7) % 256) as u8
+        } else {
+            0xff
+        };
+        Rgba([
+            (x * 17 + index) as u8,
+            (y * 31) as u8,
+            (index * 53) as u8,
+            a,
+        ])
+    })
+}
+
+fn encode(payload: PipedPayload, alpha: bool, frames: &[RgbaImage]) -> Vec<u8> {
+    let buf = SharedBuf::default();
+    let mut cvt = PipedPngConverter::from_writer(buf.clone())
+        .with_payload(payload)
+        .with_alpha(alpha)
+        .with_frame_count(frames.len() as u32)
+        .with_source_name("sample.omv");
+    cvt.prepare(WIDTH, HEIGHT, FrameRate::new(30000, 1001).unwrap())
+        .unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        cvt.convert_frame(frame.clone(), i as u32).unwrap();
+    }
+    cvt.finish().unwrap();
+    let bytes = buf.0.borrow().clone();
+    bytes
+}
+
+fn roundtrip(payload: PipedPayload, alpha: bool) {
+    let frames: Vec<_> = (0..5).map(|i| test_frame(i, alpha)).collect();
+    let stream = encode(payload, alpha, &frames);
+
+    let mut reader = PipedStreamReader::new(Cursor::new(stream)).unwrap();
+    let header = reader.header().clone();
+    assert_eq!(header.version, 2);
+    assert_eq!((header.width, header.height), (WIDTH, HEIGHT));
+    assert_eq!((header.fps_num, header.fps_den), (30000, 1001));
+    assert_eq!(header.encoding, payload.encoding_name());
+    assert_eq!(header.has_alpha(), alpha);
+    assert_eq!(header.frame_count(), Some(frames.len() as u32));
+    assert_eq!(header.source_name(), Some("sample.omv"));
+
+    let decoded: Vec<_> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+    assert!(reader.is_finished());
+    assert_eq!(reader.end_frames(), Some(frames.len() as u32));
+    assert_eq!(decoded.len(), frames.len());
+
+    for (i, (frame, expected)) in decoded.iter().zip(&frames).enumerate() {
+        assert_eq!(frame.pts, i as u64);
+        assert_eq!(frame.image.dimensions(), (WIDTH, HEIGHT));
+        if payload.keeps_alpha(alpha) {
+            assert_eq!(&frame.image, expected, "payload {:?} frame {}", payload, i);
+        } else {
+            for (got, want) in frame.image.pixels().zip(expected.pixels()) {
+                assert_eq!(got.0[..3], want.0[..3]);
+                assert_eq!(got.0[3], 0xff);
+            }
+        }
+    }
+}
+
+#[test]
+fn roundtrip_raw_rgba() {
+    roundtrip(PipedPayload::RawRgba, true);
+}
+
+#[test]
+fn roundtrip_raw_rgb() {
+    roundtrip(PipedPayload::RawRgb, true);
+}
+
+#[test]
+fn roundtrip_qoi() {
+    roundtrip(PipedPayload::Qoi, true);
+    roundtrip(PipedPayload::Qoi, false);
+}
+
+#[test]
+fn roundtrip_png() {
+    roundtrip(PipedPayload::Png, true);
+    roundtrip(PipedPayload::Png, false);
+}
+
+#[test]
+fn opaque_source_is_sent_as_rgb() {
+    let stream = encode(PipedPayload::Png, false, &[test_frame(0, false)]);
+    let reader = PipedStreamReader::new(Cursor::new(stream)).unwrap();
+    assert_eq!(reader.header().channels, "rgb");
+}
+
+#[test]
+fn truncated_stream_is_an_error() {
+    let frames: Vec<_> = (0..3).map(|i| test_frame(i, true)).collect();
+    let stream = encode(PipedPayload::RawRgba, true, &frames);
+
+    // drop the END trailer
+    let end = stream.len() - b"END FRAMES 3\n".len();
+    let reader = PipedStreamReader::new(Cursor::new(stream[..end].to_vec())).unwrap();
+    let results: Vec<_> = reader.collect();
+    assert_eq!(results.len(), 4);
+    assert!(results[..3].iter().all(|r| r.is_ok()));
+    assert!(results[3].is_err());
+
+    // cut inside the last payload
+    let reader = PipedStreamReader::new(Cursor::new(stream[..end - 10].to_vec())).unwrap();
+    let results: Vec<_> = reader.collect();
+    assert_eq!(results.len(), 3);
+    assert!(results[2].is_err());
+}
+
+#[test]
+fn payload_size_is_checked() {
+    let read = |header: &str, bytes: u64, payload: &[u8]| {
+        let mut stream = format!("{}\nFRAME\nPTS 0\nBYTES {}\n", header, bytes).into_bytes();
+        stream.extend_from_slice(payload);
+        let mut reader = PipedStreamReader::new(Cursor::new(stream)).unwrap();
+        reader.read_frame().map(|_| ())
+    };
+    let raw = "PXY4M V2 W4 H3 F30/1 Crgba Enc:raw";
+    assert!(read(raw, 48, &[0; 48]).is_ok());
+    // a raw payload must be exactly one frame
+    let e = read(raw, 47, &[0; 47]).unwrap_err();
+    assert!(e.to_string().contains("needs 48"), "{}", e);
+
+    // an absurd BYTES field fails before anything is allocated
+    let png = "PXY4M V2 W4 H3 F30/1 Crgba Enc:png";
+    let e = read(png, 99_999_999_999_999, &[]).unwrap_err();
+    assert!(e.to_string().contains("more than"), "{}", e);
+    let e = read(png, 1000, &[0; 10]).unwrap_err();
+    assert!(e.to_string().contains("truncated"), "{}", e);
+}
+
+#[test]
+fn version_1_stream_is_accepted() {
+    let frame = test_frame(0, true);
+    let mut png = Vec::new();
+    frame
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+
+    let mut stream =
+        format!("PXY4M W{} H{} F30000/1001 Crgba Enc:png\n", WIDTH, HEIGHT).into_bytes();
+    stream.extend_from_slice(format!("FRAME\nPTS 0\nBYTES {}\n", png.len()).as_bytes());
+    stream.extend_from_slice(&png);
+
+    let mut reader = PipedStreamReader::new(Cursor::new(stream)).unwrap();
+    assert_eq!(reader.header().version, 1);
+    assert_eq!(
+        (reader.header().fps_num, reader.header().fps_den),
+        (30000, 1001)
+    );
+    let decoded = reader.read_frame().unwrap().unwrap();
+    assert_eq!(decoded.image, frame);
+    assert!(reader.read_frame().unwrap().is_none());
+    assert_eq!(reader.end_frames(), Some(1));
+}
+
+fn write_frames(mut cvt: PipedPngConverter, frames: &[RgbaImage]) {
+    cvt.prepare(WIDTH, HEIGHT, FrameRate::new(24, 1).unwrap())
+        .unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        cvt.convert_frame(frame.clone(), i as u32).unwrap();
+    }
+    cvt.finish().unwrap();
+}
+
+fn read_frames(reader: impl std::io::Read) -> Vec<RgbaImage> {
+    let reader = PipedStreamReader::new(BufReader::new(reader)).unwrap();
+    reader.map(|frame| frame.unwrap().image).collect()
+}
+
+#[test]
+fn stream_to_file() {
+    let path = std::env::temp_dir().join(format!("omvdecoder-piped-{}.pxy4m", std::process::id()));
+    assert_eq!(
+        PipedOutput::from_path(&path),
+        PipedOutput::File(path.clone())
+    );
+
+    let frames: Vec<_> = (0..3).map(|i| test_frame(i, true)).collect();
+    write_frames(PipedPngConverter::new(&path), &frames);
+
+    let decoded = read_frames(std::fs::File::open(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(decoded, frames);
+}
+
+#[cfg(unix)]
+#[test]
+fn stream_to_unix_socket() {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("omvdecoder-piped-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let target = format!("unix:{}", path.display());
+    assert_eq!(
+        PipedOutput::from_path(&target),
+        PipedOutput::UnixSocket(path.clone())
+    );
+
+    let frames: Vec<_> = (0..3).map(|i| test_frame(i, true)).collect();
+    let consumer = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        read_frames(stream)
+    });
+    write_frames(PipedPngConverter::new(&target), &frames);
+
+    let decoded = consumer.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(decoded, frames);
+}
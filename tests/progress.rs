@@ -0,0 +1,97 @@
+use omvdecoder::progress::{Progress, ProgressEvent};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// A writer the test can read back after handing it to `Progress`.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn events(&self) -> Vec<ProgressEvent> {
+        let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+#[test]
+fn json_events_per_interval_and_file() {
+    let out = Shared::default();
+    let mut progress = Progress::json(out.clone(), 2);
+    progress.begin("op.omv", 5);
+    for _ in 0..5 {
+        progress.advance();
+    }
+    progress.finish();
+    // no frame count in the metadata
+    progress.begin("ed.omv", 0);
+    progress.advance();
+    progress.advance();
+    progress.fail(&anyhow::anyhow!("corrupt Ogg payload"));
+
+    let events = out.events();
+    assert_eq!(events.len(), 7, "{:?}", events);
+    assert_eq!(
+        events[0],
+        ProgressEvent::Start {
+            file: "op.omv".into(),
+            total: Some(5)
+        }
+    );
+    let frames: Vec<_> = events[1..3]
+        .iter()
+        .map(|event| match event {
+            ProgressEvent::Frame {
+                file, frame, total, ..
+            } => (file.as_str(), *frame, *total),
+            other => panic!("{:?}", other),
+        })
+        .collect();
+    assert_eq!(frames, [("op.omv", 2, Some(5)), ("op.omv", 4, Some(5))]);
+    assert!(matches!(events[3], ProgressEvent::Finish { frames: 5, .. }));
+    assert_eq!(
+        events[4],
+        ProgressEvent::Start {
+            file: "ed.omv".into(),
+            total: None
+        }
+    );
+    assert!(matches!(
+        events[5],
+        ProgressEvent::Frame {
+            total: None,
+            eta: None,
+            ..
+        }
+    ));
+    assert!(
+        matches!(events[6], ProgressEvent::Error { ref message, .. } if message == "corrupt Ogg payload")
+    );
+    // nothing is reported outside a file
+    progress.advance();
+    progress.finish();
+    assert_eq!(out.events().len(), 7);
+}
+
+#[test]
+fn event_lines_are_tagged() {
+    let out = Shared::default();
+    let mut progress = Progress::json(out.clone(), 1);
+    progress.begin("op.omv", 1);
+    let line = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(
+        line,
+        "{\"event\":\"start\",\"file\":\"op.omv\",\"total\":1}\n"
+    );
+}
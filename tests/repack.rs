@@ -0,0 +1,67 @@
+mod common;
+
+use common::{omv_header, theora_stream};
+use omvdecoder::decoder;
+use omvdecoder::repack::{self, RepackOptions};
+
+/// A 640x480 shader video (alpha bands below a 360 row frame) at 30 fps.
+fn original(padding2: [u8; 0x24]) -> Vec<u8> {
+    let stream = theora_stream(1, (640, 480), (30, 1), 10);
+    // data_pack_count equals the page count: 2 header pages + 10 frames
+    let mut omv = omv_header(padding2, [640, 360, 33333, 7, 8, 9, 12, 10]);
+    omv.extend_from_slice(&stream);
+    omv
+}
+
+#[test]
+fn repack_updates_metadata_and_keeps_header_bytes() {
+    let mut padding2 = [0u8; 0x24];
+    for (i, byte) in padding2.iter_mut().enumerate() {
+        *byte = i as u8 + 0x40;
+    }
+    let original = original(padding2);
+    let ogv = theora_stream(2, (640, 480), (60, 1), 25);
+
+    let repacked = repack::repack(&original, &ogv, &RepackOptions::default()).unwrap();
+    assert_eq!(&repacked[..8], &original[..8]);
+    assert_eq!(&repacked[8..0x2c], &padding2[..]);
+    assert_eq!(&repacked[0x4c..], &ogv[..]);
+
+    let header = decoder::read_omv_header(&repacked).unwrap();
+    let metadata = header.metadata();
+    assert_eq!((metadata.width, metadata.height), (640, 360));
+    assert_eq!(metadata.frame_time, 16666);
+    assert_eq!(metadata.frame_count, 25);
+    assert_eq!(metadata.data_pack_count, 27);
+    // fields the stream does not describe are kept
+    assert_eq!(
+        (metadata.stream_id, metadata.stream_id2, metadata.unknown),
+        (7, 8, 9)
+    );
+
+    // frame_time is whole microseconds, rounded down
+    let ogv = theora_stream(2, (640, 480), (24000, 1001), 5);
+    let repacked = repack::repack(&original, &ogv, &RepackOptions::default()).unwrap();
+    let header = decoder::read_omv_header(&repacked).unwrap();
+    assert_eq!(header.metadata().frame_time, 41708);
+}
+
+#[test]
+fn repack_checks_the_new_stream() {
+    let original = original([0; 0x24]);
+
+    // alpha bands of a 360 row frame do not fit 400 rows
+    let ogv = theora_stream(2, (640, 400), (30, 1), 5);
+    assert!(repack::repack(&original, &ogv, &RepackOptions::default()).is_err());
+    // unless the new frame height is given
+    let options = RepackOptions { height: Some(300) };
+    let repacked = repack::repack(&original, &ogv, &options).unwrap();
+    let header = decoder::read_omv_header(&repacked).unwrap();
+    assert_eq!(header.metadata().height, 300);
+
+    let mut damaged = theora_stream(2, (640, 480), (30, 1), 5);
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    assert!(repack::repack(&original, &damaged, &RepackOptions::default()).is_err());
+    assert!(repack::repack(&original, b"not ogg", &RepackOptions::default()).is_err());
+}
@@ -0,0 +1,301 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{decodable_stream, make_page, omv_file, theora_ident};
+use image::RgbaImage;
+use omvdecoder::decoder::{DecodeOptions, OmvReader};
+use omvdecoder::frame_rate::FrameRate;
+use omvdecoder::ogg::{self, TheoraStream};
+use omvdecoder::seek::{Keyframe, KeyframeIndex};
+
+/// A Theora stream with one packet per page, keyframes where `keys` is set.
+fn stream(serial: u32, keys: &[bool]) -> Vec<u8> {
+    let ident = theora_ident((3, 2, 1), 6, (64, 48), (30, 1));
+    let mut data = make_page(serial, 0, 0x02, 0, &[&ident], false);
+    data.extend(make_page(
+        serial,
+        1,
+        0,
+        0,
+        &[b"\x81comment", b"\x82setup"],
+        false,
+    ));
+    let mut keyframe = 0;
+    for (frame, &key) in keys.iter().enumerate() {
+        if key {
+            keyframe = frame;
+        }
+        // intra frames have the frame type bit clear
+        let packet: &[u8] = if key { b"\x00key" } else { b"\x40inter" };
+        let granule = (((keyframe + 1) << 6) + frame - keyframe) as i64;
+        let eos = if frame + 1 == keys.len() { 0x04 } else { 0 };
+        data.extend(make_page(
+            serial,
+            frame as u32 + 2,
+            eos,
+            granule,
+            &[packet],
+            false,
+        ));
+    }
+    data
+}
+
+const SIZE: (u32, u32) = (64, 48);
+
+/// Two chained streams, with keyframes at frames 0, 4, 8 and 11.
+fn decodable() -> Vec<u8> {
+    decodable_from(-3)
+}
+
+/// [`decodable`] with the first frame at `dc`, within ±3 to ±6 so the file keeps
+/// its length.
+fn decodable_from(dc: i32) -> Vec<u8> {
+    let first = [
+        (true, [dc, 1, 0]),
+        (false, [1, 0, 0]),
+        (false, [1, -1, 0]),
+        (false, [0, 0, 2]),
+        (true, [2, 0, 0]),
+        (false, [-1, 0, 0]),
+        (false, [-2, 3, 0]),
+        (false, [3, 0, 0]),
+        (true, [0, 0, -2]),
+        (false, [1, 0, 0]),
+        (false, [1, 1, 1]),
+    ];
+    let second = [(true, [4, 0, 0]), (false, [-1, 0, 0]), (false, [0, 2, 0])];
+    let mut payload = decodable_stream(1, SIZE, (30, 1), &first);
+    payload.extend(decodable_stream(2, SIZE, (30, 1), &second));
+    omv_file(SIZE, 14, &payload)
+}
+
+fn sequential(data: &[u8]) -> Vec<RgbaImage> {
+    OmvReader::open(data, DecodeOptions::default())
+        .unwrap()
+        .map(|frame| frame.unwrap().image)
+        .collect()
+}
+
+#[test]
+fn packets_know_their_frame_type() {
+    let data = stream(1, &[true, false, false, true, false]);
+    let stream = TheoraStream::collect(ogg::valid_pages(&data)).unwrap();
+    let keys: Vec<(u64, bool)> = stream
+        .packets
+        .iter()
+        .map(|p| (p.frame, p.keyframe))
+        .collect();
+    assert_eq!(
+        keys,
+        [(0, true), (1, false), (2, false), (3, true), (4, false)]
+    );
+}
+
+#[test]
+fn index_spans_chained_streams() {
+    let mut data = stream(1, &[true, false, false, true, false]);
+    data.extend(stream(2, &[true, false, true]));
+    let streams = ogg::logical_streams(ogg::valid_pages(&data));
+    let index = KeyframeIndex::build(&data, &streams);
+
+    assert_eq!(index.frames, 8);
+    assert_eq!(index.streams[1].first_frame, 5);
+    let key = |frame, stream, packet| Keyframe {
+        frame,
+        stream,
+        packet,
+    };
+    assert_eq!(
+        index.keyframes,
+        [key(0, 0, 0), key(3, 0, 3), key(5, 1, 0), key(7, 1, 2)]
+    );
+    assert_eq!(index.keyframe_before(2), Some(&key(0, 0, 0)));
+    assert_eq!(index.keyframe_before(4), Some(&key(3, 0, 3)));
+    assert_eq!(index.keyframe_before(6), Some(&key(5, 1, 0)));
+    assert_eq!(index.keyframe_before(100), Some(&key(7, 1, 2)));
+
+    assert!(index.matches(&data, &streams));
+    assert!(!index.matches(&data[..data.len() - 1], &streams));
+    assert!(!index.matches(&data, &streams[..1]));
+
+    // the same length and serials, other frame types
+    let mut other = stream(1, &[true, true, false, false, false]);
+    other.extend(stream(2, &[true, false, true]));
+    assert_eq!(other.len(), data.len());
+    assert_eq!(ogg::logical_streams(ogg::valid_pages(&other)), streams);
+    assert!(!index.matches(&other, &streams));
+}
+
+#[test]
+fn resumes_after_the_headers() {
+    let data = stream(1, &[true, false, false, true, false]);
+    let repaired = ogg::repair(&data);
+    let full = TheoraStream::collect(ogg::valid_pages(&repaired.data)).unwrap();
+    let resume = &full.packets[3];
+    let spliced = repaired.without(full.packets[0].page_offset..resume.page_offset);
+
+    let stream = TheoraStream::collect(ogg::valid_pages(&spliced.data)).unwrap();
+    let frames: Vec<u64> = stream.packets.iter().map(|p| p.frame).collect();
+    assert_eq!(frames, [3, 4]);
+    assert!(stream.packets[0].keyframe);
+    assert_eq!(
+        spliced.source_offset(stream.packets[0].page_offset),
+        resume.page_offset
+    );
+}
+
+#[test]
+fn sidecar_round_trip() {
+    let data = stream(1, &[true, false, true]);
+    let streams = ogg::logical_streams(ogg::valid_pages(&data));
+    let index = KeyframeIndex::build(&data, &streams);
+
+    let dir = std::env::temp_dir().join(format!("omvdecoder-seek-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = KeyframeIndex::sidecar_path(dir.join("op.omv"));
+    assert_eq!(path, dir.join("op.omv.keyframes.json"));
+    index.save(&path).unwrap();
+    assert_eq!(KeyframeIndex::load(&path).unwrap(), index);
+    std::fs::write(&path, "{}").unwrap();
+    assert!(KeyframeIndex::load(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn time_maps_to_frames() {
+    let rate = FrameRate::new(30000, 1001).unwrap();
+    for frame in [0, 1, 29, 30, 1798, 107892] {
+        assert_eq!(rate.index_at(rate.timestamp(frame)), frame);
+        // anywhere within the frame's display time
+        let late = rate.timestamp(frame) + rate.frame_duration() - Duration::from_nanos(1);
+        assert_eq!(rate.index_at(late), frame);
+    }
+    assert_eq!(
+        FrameRate::new(30, 1)
+            .unwrap()
+            .index_at(Duration::from_secs(2)),
+        60
+    );
+}
+
+#[test]
+fn seeking_matches_sequential_decoding() {
+    let data = decodable();
+    let frames = sequential(&data);
+    assert_eq!(frames.len(), 14);
+    let blue: Vec<u8> = frames.iter().map(|f| f.get_pixel(0, 0).0[2]).collect();
+    assert_eq!(
+        blue,
+        [122, 124, 126, 126, 132, 130, 126, 132, 128, 130, 132, 136, 134, 134]
+    );
+
+    let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+    // keyframes, frames between them and the first frame of the second link
+    for target in [4, 0, 6, 8, 3, 13, 11, 10, 1] {
+        reader.seek_to_frame(target).unwrap();
+        let rest: Vec<(u32, RgbaImage)> = reader
+            .by_ref()
+            .map(|frame| frame.unwrap())
+            .map(|frame| (frame.index, frame.image))
+            .collect();
+        let expected: Vec<(u32, RgbaImage)> = (target as u32..)
+            .zip(frames[target as usize..].iter().cloned())
+            .collect();
+        assert!(rest == expected, "seeking to frame {}", target);
+    }
+}
+
+#[test]
+fn seeking_past_the_end_keeps_the_position() {
+    let data = decodable();
+    let frames = sequential(&data);
+    let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+    reader.seek_to_frame(5).unwrap();
+    reader.read_frame().unwrap();
+
+    assert!(reader.seek_to_frame(14).is_err());
+    let frame = reader.read_frame().unwrap().unwrap();
+    assert_eq!(frame.index, 6);
+    assert!(frame.image == frames[6]);
+}
+
+#[test]
+fn frame_at_decodes_the_frame_on_screen() {
+    let data = decodable();
+    let frames = sequential(&data);
+    let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+    let rate = reader.info().frame_rate;
+
+    for (time, index) in [
+        (Duration::ZERO, 0),
+        (Duration::from_millis(250), 7),
+        (rate.timestamp(9), 9),
+        (rate.timestamp(12) - Duration::from_nanos(1), 11),
+    ] {
+        let frame = reader.frame_at(time).unwrap().unwrap();
+        assert_eq!(frame.index, index, "at {:?}", time);
+        assert!(frame.image == frames[index as usize], "at {:?}", time);
+    }
+    assert!(reader.frame_at(rate.timestamp(14)).unwrap().is_none());
+    assert!(reader.frame_at(Duration::from_secs(60)).unwrap().is_none());
+}
+
+#[test]
+fn index_sidecar_is_written_and_rebuilt() {
+    let data = decodable();
+    let frames = sequential(&data);
+    let dir = std::env::temp_dir().join(format!("omvdecoder-sidecar-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = KeyframeIndex::sidecar_path(dir.join("op.omv"));
+
+    let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+    reader.use_index_sidecar(&path).unwrap();
+    let index = reader.keyframe_index().clone();
+    assert_eq!(index.frames, 14);
+    assert_eq!(KeyframeIndex::load(&path).unwrap(), index);
+
+    // an up to date sidecar is used as it is
+    let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+    reader.use_index_sidecar(&path).unwrap();
+    reader.seek_to_frame(10).unwrap();
+    assert!(reader.read_frame().unwrap().unwrap().image == frames[10]);
+
+    // one for another file, or an unreadable one, is replaced
+    let mut stale = index.clone();
+    stale.payload_len += 1;
+    stale.save(&path).unwrap();
+    for _ in 0..2 {
+        let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+        reader.use_index_sidecar(&path).unwrap();
+        assert_eq!(KeyframeIndex::load(&path).unwrap(), index);
+        std::fs::write(&path, "{}").unwrap();
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sidecar_of_a_same_length_payload_is_rebuilt() {
+    let data = decodable();
+    let other = decodable_from(3);
+    assert_eq!(other.len(), data.len());
+    let frames = sequential(&other);
+    let dir = std::env::temp_dir().join(format!("omvdecoder-same-len-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = KeyframeIndex::sidecar_path(dir.join("op.omv"));
+
+    let mut reader = OmvReader::open(&data, DecodeOptions::default()).unwrap();
+    reader.use_index_sidecar(&path).unwrap();
+    let stale = KeyframeIndex::load(&path).unwrap();
+
+    let mut reader = OmvReader::open(&other, DecodeOptions::default()).unwrap();
+    assert!(reader.set_keyframe_index(stale.clone()).is_err());
+    reader.use_index_sidecar(&path).unwrap();
+    let rebuilt = KeyframeIndex::load(&path).unwrap();
+    assert_eq!(rebuilt.payload_len, stale.payload_len);
+    assert_ne!(rebuilt.fingerprint, stale.fingerprint);
+    reader.seek_to_frame(2).unwrap();
+    assert!(reader.read_frame().unwrap().unwrap().image == frames[2]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
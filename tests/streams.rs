@@ -0,0 +1,92 @@
+mod common;
+
+use common::{make_page, omv_header, theora_stream};
+use omvdecoder::decoder::{self, DecodeOptions, OmvReader};
+use omvdecoder::ogg::{self, Codec};
+
+/// A Vorbis identification header: 2 channels at 44100 Hz.
+fn vorbis_ident() -> Vec<u8> {
+    let mut p = b"\x01vorbis".to_vec();
+    p.extend_from_slice(&0u32.to_le_bytes());
+    p.push(2);
+    p.extend_from_slice(&44100u32.to_le_bytes());
+    p.extend_from_slice(&[0; 13]);
+    p
+}
+
+/// Theora stream 1 multiplexed with Vorbis stream 2, chained to Theora stream 3.
+fn chained() -> Vec<u8> {
+    let video = theora_stream(1, (640, 480), (30, 1), 4);
+    let pages: Vec<&[u8]> = ogg::valid_pages(&video).map(|page| page.raw).collect();
+    let mut data = pages[0].to_vec();
+    data.extend(make_page(2, 0, 0x02, 0, &[&vorbis_ident()], false));
+    for page in &pages[1..] {
+        data.extend_from_slice(page);
+    }
+    data.extend(make_page(2, 1, 0x04, 44100, &[&[0; 100]], false));
+    data.extend(theora_stream(3, (640, 480), (30, 1), 2));
+    data
+}
+
+#[test]
+fn lists_chained_streams() {
+    let data = chained();
+    let streams = ogg::logical_streams(ogg::valid_pages(&data));
+    let summary: Vec<(u32, &str, usize, usize, bool)> = streams
+        .iter()
+        .map(|s| (s.serial, s.codec.name(), s.link, s.pages, s.has_eos))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (1, "theora", 0, 6, true),
+            (2, "vorbis", 0, 2, true),
+            (3, "theora", 1, 4, true),
+        ]
+    );
+    assert!(matches!(streams[0].codec, Codec::Theora(ref info) if info.pic_width == 640));
+}
+
+#[test]
+fn extracts_one_stream() {
+    let data = chained();
+    let audio = ogg::extract_stream(&data, 2, 0);
+    let pages: Vec<ogg::Page> = ogg::valid_pages(&audio.data).collect();
+    assert_eq!(pages.len(), 2);
+    assert!(pages.iter().all(|page| page.serial == 2));
+    // the second page sits after five video pages
+    let second = pages[1].offset;
+    assert_eq!(&data[audio.source_offset(second)..][..4], b"OggS");
+    assert_eq!(
+        ogg::parse_page(&data, audio.source_offset(second))
+            .unwrap()
+            .granule_position,
+        44100
+    );
+
+    let video = ogg::extract_stream(&data, 3, 1);
+    let stream = ogg::TheoraStream::collect(ogg::valid_pages(&video.data)).unwrap();
+    assert_eq!(stream.info.serial, 3);
+    assert_eq!(stream.packets.len(), 2);
+    assert!(ogg::extract_stream(&data, 3, 0).data.is_empty());
+}
+
+#[test]
+fn serial_selection() {
+    let mut omv = omv_header([0; 0x24], [640, 480, 33333, 0, 0, 0, 6, 4]);
+    omv.extend(chained());
+    let open = |serial| {
+        let options = DecodeOptions {
+            serial: Some(serial),
+            ..Default::default()
+        };
+        OmvReader::open(&omv, options).err().unwrap().to_string()
+    };
+    assert_eq!(open(2), "stream 00000002 is vorbis, not Theora");
+    assert_eq!(open(9), "no logical stream with serial 00000009");
+
+    assert_eq!(decoder::parse_serial("0x1F").unwrap(), 31);
+    assert_eq!(decoder::parse_serial("31").unwrap(), 31);
+    assert!(decoder::parse_serial("0xg").is_err());
+    assert!(decoder::parse_serial("-1").is_err());
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use std::path::Path;
+
+use common::{decodable_stream, omv_file, omv_header, theora_stream};
+use omvdecoder::decoder::{DecodeOptions, OmvReader};
+use omvdecoder::inspect;
+use omvdecoder::progress::Progress;
+use omvdecoder::verify::{self, Report, Status, VerifyOptions};
+
+/// A 640x480 shader video (alpha bands below a 360 row frame) at 30 fps.
+fn omv(metadata: [u32; 8], frames: u32) -> Vec<u8> {
+    let mut omv = omv_header([0; 0x24], metadata);
+    omv.extend_from_slice(&theora_stream(1, (640, 480), (30, 1), frames));
+    omv
+}
+
+const METADATA: [u32; 8] = [640, 360, 33333, 0, 0, 0, 12, 10];
+
+fn check(data: &[u8]) -> Report {
+    let options = VerifyOptions {
+        decode: false,
+        ..Default::default()
+    };
+    verify::verify(data, &options, &mut Progress::hidden())
+}
+
+fn status(report: &Report, name: &str) -> Status {
+    report
+        .check(name)
+        .unwrap_or_else(|| panic!("no {} check in {:?}", name, report))
+        .status
+}
+
+#[test]
+fn consistent_file_passes() {
+    let report = check(&omv(METADATA, 10));
+    assert!(report.passed(), "{:?}", report);
+    let names: Vec<_> = report.checks.iter().map(|c| c.name).collect();
+    assert_eq!(
+        names,
+        [
+            "header",
+            "pages",
+            "stream",
+            "geometry",
+            "frame count",
+            "frame time"
+        ]
+    );
+    assert!(report.checks.iter().all(|c| c.status == Status::Pass));
+}
+
+#[test]
+fn header_must_match_the_stream() {
+    let mut metadata = METADATA;
+    metadata[7] = 12;
+    let report = check(&omv(metadata, 10));
+    assert!(!report.passed());
+    assert_eq!(status(&report, "frame count"), Status::Fail);
+
+    // a frame time rounded up instead of down still passes
+    let mut metadata = METADATA;
+    metadata[2] = 33334;
+    assert_eq!(
+        status(&check(&omv(metadata, 10)), "frame time"),
+        Status::Pass
+    );
+    let mut metadata = METADATA;
+    metadata[2] = 41666;
+    assert_eq!(
+        status(&check(&omv(metadata, 10)), "frame time"),
+        Status::Fail
+    );
+
+    let mut metadata = METADATA;
+    metadata[0] = 320;
+    assert_eq!(status(&check(&omv(metadata, 10)), "geometry"), Status::Fail);
+    // alpha bands of 400 rows do not fit 480
+    let mut metadata = METADATA;
+    metadata[1] = 400;
+    assert_eq!(status(&check(&omv(metadata, 10)), "geometry"), Status::Fail);
+
+    // an unexpected data_pack_count only warns
+    let mut metadata = METADATA;
+    metadata[6] = 99;
+    let report = check(&omv(metadata, 10));
+    assert!(report.passed());
+    assert_eq!(status(&report, "data packs"), Status::Warn);
+}
+
+#[test]
+fn damage_and_truncation_fail() {
+    let mut damaged = omv(METADATA, 10);
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    let report = check(&damaged);
+    assert_eq!(status(&report, "pages"), Status::Fail);
+    assert!(!report.passed());
+
+    // cut inside the last page: no end-of-stream page, a frame short
+    let full = omv(METADATA, 10);
+    let report = check(&full[..full.len() - 1]);
+    assert_eq!(status(&report, "stream"), Status::Fail);
+    assert_eq!(status(&report, "frame count"), Status::Fail);
+
+    let mut trailing = full.clone();
+    trailing.extend_from_slice(&[0; 16]);
+    let report = check(&trailing);
+    assert!(report.passed());
+    assert_eq!(status(&report, "pages"), Status::Warn);
+
+    let report = check(&full[..20]);
+    assert_eq!(report.checks.len(), 1);
+    assert_eq!(status(&report, "header"), Status::Fail);
+}
+
+#[test]
+fn malformed_corpus_fails() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/malformed");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "omv") {
+            continue;
+        }
+        let data = std::fs::read(&path).unwrap();
+        let report = verify::verify(&data, &VerifyOptions::default(), &mut Progress::hidden());
+        assert!(!report.passed(), "{} passed: {:?}", path.display(), report);
+    }
+}
+
+/// Two links of stream 1, 5 keyframes then a keyframe and 2 inter frames, whose
+/// blue channels count up from 124 in steps of 2.
+fn chained(frame_count: u32) -> Vec<u8> {
+    const SIZE: (u32, u32) = (64, 48);
+    let first: Vec<(bool, [i32; 3])> = (0..5).map(|i| (true, [i - 2, 0, 0])).collect();
+    let second = [(true, [3, 0, 0]), (false, [1, 0, 0]), (false, [1, 0, 0])];
+    let mut payload = decodable_stream(1, SIZE, (30, 1), &first);
+    payload.extend(decodable_stream(1, SIZE, (30, 1), &second));
+    omv_file(SIZE, frame_count, &payload)
+}
+
+#[test]
+fn chained_payload_is_checked_across_links() {
+    let data = chained(8);
+    let report = verify::verify(&data, &VerifyOptions::default(), &mut Progress::hidden());
+    assert!(report.passed(), "{:?}", report);
+    let stream = report.check("stream").unwrap();
+    assert!(
+        stream.detail.ends_with("in 2 chained links"),
+        "{:?}",
+        stream
+    );
+    assert_eq!(
+        report.check("frame count").unwrap().detail,
+        "header 8, stream 8"
+    );
+    assert_eq!(report.check("decode").unwrap().detail, "8 frame(s)");
+
+    let blue: Vec<u8> = OmvReader::open(&data, DecodeOptions::default())
+        .unwrap()
+        .map(|frame| frame.unwrap().image.get_pixel(0, 0).0[2])
+        .collect();
+    assert_eq!(blue, [124, 126, 128, 130, 132, 134, 136, 138]);
+
+    // the first link alone is not the whole video
+    let report = check(&chained(5));
+    assert_eq!(status(&report, "frame count"), Status::Fail);
+
+    // the serial comes back in the second link, a stream of its own
+    let inspection = inspect::inspect(&data).unwrap();
+    let streams: Vec<_> = inspection
+        .streams
+        .iter()
+        .map(|s| (s.link, s.serial, s.pages))
+        .collect();
+    assert_eq!(streams, [(0, 1, 7), (1, 1, 5)]);
+}